@@ -1,9 +1,7 @@
 use clap::{Parser, Subcommand};
-use crate::core::database::CommitStorage;
-use crate::core::branch::BranchManager;
+use crate::core::database::{parse_hash, CommitStorage, SCHEMA_MARKER_ID};
 use crate::core::query::QueryProcessor;
 use crate::error::{GitDBError, Result};
-use rocksdb::DB;
 use hex;
 use csv;
 use crate::core::models::Change;
@@ -59,15 +57,15 @@ pub enum Commands {
         commit_hash: Option<String>,
     },
     Revert {
-        #[arg(help = "Commit hash to revert to")]
+        #[arg(help = "Commit hash, branch, or tag to revert to")]
         commit_hash: String,
     },
     
     Diff {
-        #[arg(help = "First commit hash")]
+        #[arg(help = "First commit hash, branch, or tag")]
         from: String,
         
-        #[arg(help = "Second commit hash")]
+        #[arg(help = "Second commit hash, branch, or tag")]
         to: String,
     },
     
@@ -78,7 +76,7 @@ pub enum Commands {
     },
 
     Checkout {
-        #[arg(help = "Commit hash or branch name")]
+        #[arg(help = "Branch name, tag, or commit hash")]
         target: String,
     },
     
@@ -94,24 +92,30 @@ pub fn handle_commit(storage: &CommitStorage, message: &str) -> Result<()> {
     }
 
     let changes = Vec::new();
-    let hash = storage.create_commit(message, changes)?;
+    let hash = storage.create_commit_allow_empty(message, changes)?;
     println!("Created commit with hash: {}", hex::encode(hash));
     Ok(())
 }
 
-pub fn handle_branch(branch_mgr: &BranchManager, name: &str, delete: bool) -> Result<()> {
+pub fn handle_branch(storage: &CommitStorage, name: &str, delete: bool) -> Result<()> {
     if delete {
-        branch_mgr.delete_branch(name)?;
+        let orphaned = storage.delete_branch(name)?;
         println!("Deleted branch '{}'.", name);
+        if let Some(tip) = orphaned {
+            println!("Commit {} is no longer reachable and will be removed by gc.", hex::encode(tip));
+        }
     } else {
-        branch_mgr.create_branch(name)?;
-        println!("Created branch '{}'.", name);
+        let head = storage.get_head()?.ok_or_else(|| {
+            GitDBError::InvalidInput(format!("Cannot create branch '{}' before the first commit", name))
+        })?;
+        storage.create_branch(name.trim(), head)?;
+        println!("Created branch '{}'.", name.trim());
     }
     Ok(())
 }
 
-pub fn handle_query(sql: &str, db: &DB) -> Result<()> {
-    let processor = QueryProcessor::new(db);
+pub fn handle_query(sql: &str, storage: &CommitStorage) -> Result<()> {
+    let processor = QueryProcessor::new(storage);
     processor.execute(sql)
 }
 
@@ -123,11 +127,7 @@ pub fn handle_sql(storage: &CommitStorage, command: &str) -> Result<()> {
             .nth(2)
             .ok_or_else(|| GitDBError::InvalidInput("Missing table name".into()))?;
         
-        let changes = vec![Change::Insert {
-            table: table_name.to_string(),
-            id: "!schema".to_string(),
-            value: bincode::serialize(&CrdtValue::Register(b"{}".to_vec()))?,
-        }];
+        let changes = vec![Change::schema_add(table_name)];
         
         storage.create_commit(&format!("SQL: {}", command), changes)?;
         Ok(())
@@ -148,11 +148,7 @@ pub fn handle_sql(storage: &CommitStorage, command: &str) -> Result<()> {
         
         let json_value = serde_json::to_string(&values)?;  
         
-        let changes = vec![Change::Insert {
-            table: table.to_string(),
-            id: values[0].to_string(),
-            value: bincode::serialize(&CrdtValue::Register(json_value.as_bytes().to_vec()))?,
-        }];
+        let changes = vec![Change::insert_value(table, values[0].as_str(), &CrdtValue::Register(json_value.into_bytes()))?];
         
         storage.create_commit(&format!("SQL: {}", command), changes)?;
         Ok(())
@@ -188,11 +184,7 @@ pub fn handle_sql(storage: &CommitStorage, command: &str) -> Result<()> {
 
         let json_value = serde_json::to_string(&updates)?;
         
-        let changes = vec![Change::Update {
-            table: table.to_string(),
-            id: id.to_string(),
-            value: bincode::serialize(&CrdtValue::Register(json_value.as_bytes().to_vec()))?,
-        }];
+        let changes = vec![Change::update_value(table, id, &CrdtValue::Register(json_value.into_bytes()))?];
         
         storage.create_commit(&format!("SQL: {}", command), changes)?;
         Ok(())
@@ -254,23 +246,21 @@ pub fn handle_import_csv(storage: &CommitStorage, file: &str, table: &str) -> Re
             row.push(format!("\"{}\":\"{}\"", headers.get(i).unwrap_or(&i.to_string()), field));
         }
         
-        changes.push(Change::Insert {
-            table: table.to_string(),
-            id: id.to_string(),
-            value: bincode::serialize(&CrdtValue::Register(
-                format!("{{{}}}", row.join(",")).as_bytes().to_vec()
-            ))?,
-        });
+        changes.push(Change::insert_value(
+            table,
+            id,
+            &CrdtValue::Register(format!("{{{}}}", row.join(",")).into_bytes()),
+        )?);
     }
 
     storage.create_commit(&format!("Import {} into {}", file, table), changes)?;
     Ok(())
 }
 
-pub fn handle_show_table(db: &DB, table_name: &str, commit_hash: Option<&str>) -> Result<()> {
-    let processor = QueryProcessor::new(db);
+pub fn handle_show_table(storage: &CommitStorage, table_name: &str, commit_hash: Option<&str>) -> Result<()> {
+    let processor = QueryProcessor::new(storage);
     let hash = match commit_hash {
-        Some(h) => hex::decode(h)?,
+        Some(h) => parse_hash(h)?.to_vec(),
         None => processor.get_head_hash()?,
     };
 
@@ -279,7 +269,7 @@ pub fn handle_show_table(db: &DB, table_name: &str, commit_hash: Option<&str>) -
     match processor.get_table_at_commit(table_name, &hash) {
         Ok(rows) => {
             for (id, value) in rows {
-                if id == "!schema" {
+                if id == SCHEMA_MARKER_ID {
                     continue;
                 }
                 match value {
@@ -289,6 +279,9 @@ pub fn handle_show_table(db: &DB, table_name: &str, commit_hash: Option<&str>) -
                     CrdtValue::Counter(count) => {
                         println!("{}: {}", id, count);
                     }
+                    fields @ CrdtValue::Fields(_) => {
+                        println!("{}: {:?}", id, fields);
+                    }
                 }
             }
             Ok(())
@@ -298,9 +291,13 @@ pub fn handle_show_table(db: &DB, table_name: &str, commit_hash: Option<&str>) -
             eprintln!("Falling back to direct table scan...");
             
             // Direct table scan fallback
-            let iter = db.prefix_iterator(table_name.as_bytes());
+            let prefix = format!("{}:", table_name);
+            let iter = storage.db.prefix_iterator(prefix.as_bytes());
             for item in iter {
                 let (key, value) = item?;
+                if !key.starts_with(prefix.as_bytes()) {
+                    break;
+                }
                 println!("{}: {}", 
                     String::from_utf8_lossy(&key),
                     String::from_utf8_lossy(&value));
@@ -311,23 +308,14 @@ pub fn handle_show_table(db: &DB, table_name: &str, commit_hash: Option<&str>) -
 }
 
 pub fn handle_revert(storage: &CommitStorage, commit_hash: &str) -> Result<()> {
-    // Validate commit hash format
-    if commit_hash.len() != 64 {
-        return Err(GitDBError::InvalidInput(
-            "Commit hash must be 64 characters long".into()
-        ));
-    }
-    
-    let hash_bytes = hex::decode(commit_hash)?;
-    let hash_array: [u8; 32] = hash_bytes.try_into()
-        .map_err(|_| GitDBError::InvalidInput("Invalid commit hash".into()))?;
+    let hash_array = storage.resolve_ref(commit_hash)?;
     
     // Verify the commit exists
     storage.get_commit_by_hash(&hash_array)?;
     
     // Get and print current state before revert
     println!("\nState before revert:");
-    let before_state: Vec<_> = storage.db.iterator(rocksdb::IteratorMode::Start)
+    let before_state: Vec<_> = storage.db.iterator()
         .filter_map(|item| item.ok())
         .collect();
     
@@ -348,7 +336,7 @@ pub fn handle_revert(storage: &CommitStorage, commit_hash: &str) -> Result<()> {
     
     // Print state after revert
     println!("\nState after revert:");
-    let after_state: Vec<_> = storage.db.iterator(rocksdb::IteratorMode::Start)
+    let after_state: Vec<_> = storage.db.iterator()
         .filter_map(|item| item.ok())
         .collect();
     
@@ -384,13 +372,8 @@ pub fn handle_revert(storage: &CommitStorage, commit_hash: &str) -> Result<()> {
 }
 
 pub fn handle_diff(storage: &CommitStorage, from: &str, to: &str) -> Result<()> {
-    let from_bytes = hex::decode(from)?;
-    let from_array: [u8; 32] = from_bytes.try_into()
-        .map_err(|_| GitDBError::InvalidInput("Invalid commit hash length".into()))?;
-    
-    let to_bytes = hex::decode(to)?;
-    let to_array: [u8; 32] = to_bytes.try_into()
-        .map_err(|_| GitDBError::InvalidInput("Invalid commit hash length".into()))?;
+    let from_array = storage.resolve_ref(from)?;
+    let to_array = storage.resolve_ref(to)?;
     
     let diffs = storage.get_commit_diffs(&from_array, &to_array)?;
     
@@ -403,12 +386,12 @@ pub fn handle_diff(storage: &CommitStorage, from: &str, to: &str) -> Result<()>
 }
 
 pub fn handle_history(storage: &CommitStorage, limit: Option<usize>) -> Result<()> {
-    let history = storage.get_commit_history()?;
+    let history = storage.get_commit_history_with_hashes()?;
     
     let display_count = limit.unwrap_or(history.len());
-    for commit in history.iter().take(display_count) {
-        let hash = blake3::hash(&bincode::serialize(commit)?);
-        println!("{}: {}", hex::encode(&hash.as_bytes()[..8]), commit.message);
+    for record in history.iter().take(display_count) {
+        let commit = &record.commit;
+        println!("{}: {}", hex::encode(&record.hash[..8]), commit.message);
         println!("  Date: {}", commit.timestamp);
         println!("  Changes: {}", commit.changes.len());
         println!();
@@ -430,41 +413,38 @@ pub fn handle_init(path: &str) -> Result<()> {
 
 pub fn handle_checkout(storage: &CommitStorage, target: &str) -> Result<()> {
     // Try as branch first
-    if let Ok(Some(branch_data)) = storage.db.get(target.as_bytes()) {
-        storage.db.put(b"HEAD", branch_data)?;
+    if storage.get_branch(target)?.is_some() {
+        storage.set_head_to_branch(target)?;
         println!("Switched to branch '{}'", target);
         return Ok(());
     }
 
-    // Try as commit hash
-    let hash_bytes = hex::decode(target)
-        .map_err(|_| GitDBError::InvalidInput("Invalid commit hash or branch name".into()))?;
-    
-    if storage.db.get(&hash_bytes)?.is_none() {
-        return Err(GitDBError::InvalidInput("Commit not found".into()));
-    }
-
-    storage.db.put(b"HEAD", &hash_bytes)?;
+    // Otherwise detach at whatever the reference names
+    let hash_array = storage.resolve_ref(target)?;
+    storage.detach_head(&hash_array)?;
     println!("Switched to commit {}", target);
     Ok(())
 }
 
 pub fn handle_log(storage: &CommitStorage, verbose: bool) -> Result<()> {
-    let mut current_hash = storage.get_head()?;
-    
-    while let Some(hash) = current_hash {
-        let commit = storage.get_commit_by_hash(&hash)?;
+    if verbose {
+        match storage.current_branch()? {
+            Some(branch) => println!("On branch {}\n", branch),
+            None => println!("HEAD detached\n"),
+        }
+    }
+
+    for record in storage.get_commit_history_with_hashes()? {
+        let commit = &record.commit;
         
         if verbose {
-            println!("commit {}", hex::encode(&hash)); // Show full hash
-            println!("Author: <user>");
+            println!("commit {}", hex::encode(record.hash)); // Show full hash
+            println!("Author: {} <{}>", commit.author, commit.email);
             println!("Date:   {}", commit.timestamp);
             println!("\n    {}\n", commit.message);
         } else {
-            println!("{} {}", hex::encode(&hash), commit.message); // Show full hash instead of short_hash
+            println!("{} {}", hex::encode(record.hash), commit.message); // Show full hash instead of short_hash
         }
-        
-        current_hash = commit.parents.get(0).cloned();
     }
     
     Ok(())
@@ -0,0 +1,162 @@
+use crate::core::database::{CommitStorage, CHECKSUM_ALGORITHM_KEY, CODEC_KEY, INTEGRITY_TRAILER_KEY};
+use crate::core::hashing::ChecksumAlgorithm;
+use crate::error::{GitDBError, Result};
+use crate::core::backend::WriteBatch;
+use std::io::{Read, Write};
+
+// Layout: magic, version byte, then (key_len u32, key, value_len u32, value)
+// records, an END_MARKER key length, and a blake3 checksum of everything before.
+const MAGIC: &[u8; 8] = b"GITDBARC";
+const ARCHIVE_VERSION: u8 = 1;
+const END_MARKER: u32 = u32::MAX;
+
+/// Keys that hold repository settings rather than data. A repository holding
+/// only these still counts as empty for `import_archive`.
+const SETTINGS_KEYS: [&[u8]; 3] = [CHECKSUM_ALGORITHM_KEY, INTEGRITY_TRAILER_KEY, CODEC_KEY];
+
+/// Writer that hashes everything passing through it.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.hasher.update(bytes);
+        self.inner.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn put_len(&mut self, len: usize) -> Result<()> {
+        let len = u32::try_from(len)
+            .ok()
+            .filter(|l| *l != END_MARKER)
+            .ok_or_else(|| GitDBError::InvalidInput("Record too large to archive".into()))?;
+        self.put(&len.to_le_bytes())
+    }
+}
+
+/// Reader that hashes everything read through it.
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    /// Reads exactly `len` bytes. Lengths come from the archive itself, so the
+    /// buffer only grows as bytes actually arrive instead of being allocated
+    /// up front from a possibly corrupt length field.
+    fn take(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.inner).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(GitDBError::CorruptData("Archive ends in the middle of a record".into()));
+        }
+        self.hasher.update(&buf);
+        Ok(buf)
+    }
+
+    fn take_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl CommitStorage {
+    /// Streams every key in the database (commits, refs, HEAD and table rows)
+    /// into a versioned archive ending in a blake3 checksum of the stream.
+    pub fn export_archive(&self, writer: impl Write) -> Result<()> {
+        let mut out = HashingWriter { inner: writer, hasher: blake3::Hasher::new() };
+        out.put(MAGIC)?;
+        out.put(&[ARCHIVE_VERSION])?;
+
+        for item in self.db.iterator() {
+            let (key, value) = item?;
+            out.put_len(key.len())?;
+            out.put(&key)?;
+            out.put_len(value.len())?;
+            out.put(&value)?;
+        }
+        out.put(&END_MARKER.to_le_bytes())?;
+
+        let checksum = out.hasher.finalize();
+        out.inner.write_all(checksum.as_bytes())?;
+        out.inner.flush()?;
+        Ok(())
+    }
+
+    /// Loads an archive written by `export_archive` into this repository,
+    /// which must not hold any commits, refs or rows yet. Nothing is written
+    /// unless the whole stream parses, its checksum matches and it was made
+    /// with the same checksum algorithm, codec and trailer setting.
+    pub fn import_archive(&self, reader: impl Read) -> Result<()> {
+        for item in self.db.iterator() {
+            let (key, _) = item?;
+            if !SETTINGS_KEYS.contains(&key.as_ref()) {
+                return Err(GitDBError::InvalidInput("Cannot import an archive into a non-empty repository".into()));
+            }
+        }
+
+        let mut input = HashingReader { inner: reader, hasher: blake3::Hasher::new() };
+        if input.take(MAGIC.len())? != MAGIC {
+            return Err(GitDBError::InvalidInput("Not a GitDB archive".into()));
+        }
+        let version = input.take(1)?[0];
+        if version != ARCHIVE_VERSION {
+            return Err(GitDBError::InvalidInput(format!("Unsupported archive version {}", version)));
+        }
+
+        // Archives without a recorded setting predate it and use the default.
+        let mut algorithm = ChecksumAlgorithm::Blake3;
+        let mut codec = "bincode".to_string();
+        let mut integrity_trailer = true;
+        let mut batch = WriteBatch::default();
+        loop {
+            let key_len = input.take_u32()?;
+            if key_len == END_MARKER {
+                break;
+            }
+            let key = input.take(key_len as usize)?;
+            let value_len = input.take_u32()?;
+            let value = input.take(value_len as usize)?;
+            if key == CHECKSUM_ALGORITHM_KEY {
+                algorithm = ChecksumAlgorithm::from_name(&String::from_utf8_lossy(&value))?;
+            } else if key == CODEC_KEY {
+                codec = String::from_utf8_lossy(&value).into_owned();
+            } else if key == INTEGRITY_TRAILER_KEY {
+                integrity_trailer = value != [0];
+            }
+            batch.put(key, value);
+        }
+
+        let expected = input.hasher.finalize();
+        let mut stored = [0u8; 32];
+        input.inner.read_exact(&mut stored)?;
+        if expected.as_bytes() != &stored {
+            return Err(GitDBError::CorruptData("Archive checksum mismatch".into()));
+        }
+
+        let ours = self.checksum_algorithm();
+        if algorithm != ours {
+            return Err(GitDBError::InvalidInput(format!(
+                "Archive uses {} checksums but this repository uses {}", algorithm.name(), ours.name()
+            )));
+        }
+        if codec != self.codec_name() {
+            return Err(GitDBError::InvalidInput(format!(
+                "Archive uses the {} codec but this repository uses {}", codec, self.codec_name()
+            )));
+        }
+        if integrity_trailer != self.integrity_trailer() {
+            let state = |enabled: bool| if enabled { "on" } else { "off" };
+            return Err(GitDBError::InvalidInput(format!(
+                "Archive has integrity trailers {} but this repository has them {}",
+                state(integrity_trailer), state(self.integrity_trailer())
+            )));
+        }
+
+        self.db.write(batch)?;
+        self.clear_commit_cache();
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use crate::error::Result;
+use rocksdb::{IteratorMode, DB};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+pub type KvPair = (Box<[u8]>, Box<[u8]>);
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<KvPair>> + 'a>;
+
+#[derive(Serialize, Deserialize)]
+enum BatchOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Writes collected up front and applied atomically by `Backend::write`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) {
+        self.ops.push(BatchOp::Put(key.as_ref().to_vec(), value.as_ref().to_vec()));
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.ops.push(BatchOp::Delete(key.as_ref().to_vec()));
+    }
+}
+
+/// The key-value operations `CommitStorage` needs from its store.
+pub trait Backend: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Looks up many keys at once; results line up with `keys`.
+    fn multi_get(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>>;
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
+    fn delete(&self, key: &[u8]) -> Result<()>;
+    /// Applies every operation in `batch`, or none of them.
+    fn write(&self, batch: WriteBatch) -> Result<()>;
+    /// Entries whose keys start with `prefix`, in key order.
+    fn prefix_iterator<'a>(&'a self, prefix: &[u8]) -> KvIter<'a>;
+    /// Every entry, in key order.
+    fn iterator(&self) -> KvIter<'_>;
+    /// Pushes buffered writes to durable storage.
+    fn flush(&self) -> Result<()>;
+    /// Compacts the whole key range.
+    fn compact(&self) -> Result<()>;
+}
+
+impl Backend for DB {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(DB::get(self, key)?)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>> {
+        DB::multi_get(self, keys).into_iter()
+            .map(|item| item.map_err(Into::into))
+            .collect()
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        Ok(DB::put(self, key, value)?)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        Ok(DB::delete(self, key)?)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => rocks_batch.put(key, value),
+                BatchOp::Delete(key) => rocks_batch.delete(key),
+            }
+        }
+        Ok(DB::write(self, rocks_batch)?)
+    }
+
+    fn prefix_iterator<'a>(&'a self, prefix: &[u8]) -> KvIter<'a> {
+        // Without a prefix extractor RocksDB keeps going past the prefix.
+        let prefix = prefix.to_vec();
+        Box::new(DB::prefix_iterator(self, &prefix)
+            .map(|item| item.map_err(Into::into))
+            .take_while(move |item| match item {
+                Ok((key, _)) => key.starts_with(&prefix),
+                Err(_) => true,
+            }))
+    }
+
+    fn iterator(&self) -> KvIter<'_> {
+        Box::new(DB::iterator(self, IteratorMode::Start).map(|item| item.map_err(Into::into)))
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(DB::flush(self)?)
+    }
+
+    fn compact(&self) -> Result<()> {
+        DB::compact_range::<&[u8], &[u8]>(self, None, None);
+        Ok(())
+    }
+}
+
+/// A `BTreeMap`-backed store that lives only as long as the process, for
+/// tests and throwaway repositories.
+#[derive(Default)]
+pub struct InMemoryBackend {
+    entries: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<Vec<u8>, Vec<u8>>> {
+        self.entries.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn modify(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<Vec<u8>, Vec<u8>>> {
+        self.entries.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Copies the matching entries out so iteration does not hold the lock.
+    fn collect(&self, prefix: &[u8]) -> KvIter<'_> {
+        let entries: Vec<Result<KvPair>> = self.read()
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| Ok((key.clone().into_boxed_slice(), value.clone().into_boxed_slice())))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+}
+
+impl Backend for InMemoryBackend {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.read().get(key).cloned())
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>> {
+        let entries = self.read();
+        Ok(keys.iter().map(|key| entries.get(key).cloned()).collect())
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.modify().insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.modify().remove(key);
+        Ok(())
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut entries = self.modify();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => {
+                    entries.insert(key, value);
+                }
+                BatchOp::Delete(key) => {
+                    entries.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn prefix_iterator<'a>(&'a self, prefix: &[u8]) -> KvIter<'a> {
+        self.collect(prefix)
+    }
+
+    fn iterator(&self) -> KvIter<'_> {
+        self.collect(&[])
+    }
+
+    /// Nothing is buffered, so there is nothing to flush.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(iter: KvIter<'_>) -> Vec<Vec<u8>> {
+        iter.map(|entry| entry.unwrap().0.into_vec()).collect()
+    }
+
+    #[test]
+    fn in_memory_prefix_iteration_is_ordered_and_bounded() {
+        let backend = InMemoryBackend::new();
+        for key in ["users:2", "users:1", "user:1", "usersx", "teams:1"] {
+            backend.put(key.as_bytes(), b"v").unwrap();
+        }
+
+        assert_eq!(keys(backend.prefix_iterator(b"users:")), vec![b"users:1".to_vec(), b"users:2".to_vec()]);
+        assert_eq!(keys(backend.iterator()).len(), 5);
+    }
+
+    #[test]
+    fn in_memory_batches_apply_in_order() {
+        let backend = InMemoryBackend::new();
+        backend.put(b"a", b"1").unwrap();
+
+        let mut batch = WriteBatch::default();
+        batch.delete(b"a");
+        batch.put(b"b", b"2");
+        batch.put(b"a", b"3");
+        backend.write(batch).unwrap();
+
+        assert_eq!(backend.get(b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(backend.multi_get(&[b"b".to_vec(), b"c".to_vec()]).unwrap(), vec![Some(b"2".to_vec()), None]);
+    }
+}
@@ -1,13 +1,14 @@
+use crate::core::database::branch_key;
 use crate::error::{GitDBError, Result};
-use rocksdb::DB;
+use crate::core::backend::Backend;
 use std::sync::Arc;
 
 pub struct BranchManager {
-    pub db: Arc<DB>,
+    pub db: Arc<dyn Backend>,
 }
 
 impl BranchManager {
-    pub fn new(db: Arc<DB>) -> Self {
+    pub fn new(db: Arc<dyn Backend>) -> Self {
         Self { db }
     }
 
@@ -17,7 +18,7 @@ impl BranchManager {
             return Err(GitDBError::InvalidInput("Branch name cannot be empty".into()));
         }
 
-        let branch_key = format!("branch:{}", trimmed);
+        let branch_key = branch_key(trimmed);
         if self.db.get(branch_key.as_bytes())?.is_some() {
             return Err(GitDBError::InvalidInput(format!("Branch '{}' already exists", trimmed)));
         }
@@ -26,19 +27,8 @@ impl BranchManager {
             GitDBError::InvalidInput(format!("Cannot create branch '{}'", trimmed))
         })?;
 
-        self.db.put(branch_key.as_bytes(), head)?;
+        self.db.put(branch_key.as_bytes(), &head)?;
         println!("Created new branch '{}" , trimmed);
         Ok(())
     }
-
-    pub fn delete_branch(&self, name: &str) -> Result<()> {
-        let branch_key = format!("branch:{}", name);
-        if self.db.get(branch_key.as_bytes())?.is_none() {
-            return Err(GitDBError::InvalidInput(format!("Branch '{}' does not exist", name)));
-        }
-
-        self.db.delete(branch_key.as_bytes())?;
-        println!("Deleted branch '{}" , name);
-        Ok(())
-    }
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use crate::core::database::{validate_changes, CommitStorage};
+use crate::core::models::Change;
+use crate::error::Result;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Changes held in memory before a `CommitBuilder` writes them out.
+const BUILDER_CHUNK: usize = 10_000;
+
+/// Gives every builder its own keys, whichever repository it writes to.
+static NEXT_BUILDER: AtomicU64 = AtomicU64::new(0);
+
+/// Builds one commit from a stream of changes too large to collect up front.
+///
+/// Pushed changes are written out in chunks under keys of the builder's own,
+/// apart from the staging area, so memory use while pushing stays bounded and
+/// nothing staged elsewhere ends up in the commit. The tables each chunk
+/// touches are hashed as it is written. `finish` then commits the changes on
+/// HEAD, holding only their net effect; a builder dropped without `finish`
+/// deletes what it wrote.
+pub struct CommitBuilder<'a> {
+    storage: &'a CommitStorage,
+    id: u64,
+    pending: Vec<Change>,
+    written: u64,
+    tree: BTreeMap<String, [u8; 32]>,
+    tree_head: Option<[u8; 32]>,
+}
+
+impl<'a> CommitBuilder<'a> {
+    /// Validates `change` and queues it, writing the queue out once it fills.
+    pub fn push(&mut self, change: Change) -> Result<()> {
+        validate_changes(std::slice::from_ref(&change))?;
+        self.pending.push(change);
+        if self.pending.len() >= BUILDER_CHUNK {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining changes and commits everything pushed on HEAD.
+    pub fn finish(mut self, message: &str) -> Result<[u8; 32]> {
+        self.write_pending()?;
+        let tree = std::mem::take(&mut self.tree);
+        let hash = self.storage.commit_build(self.id, message, tree, self.tree_head)?;
+        // The commit deleted what was written out.
+        self.written = 0;
+        Ok(hash)
+    }
+
+    fn write_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.update_tree()?;
+        self.storage.stage_build_chunk(self.id, self.written, &self.pending)?;
+        self.written += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Hashes the tables the pending changes touch for the first time, and
+    /// every table again if HEAD has moved since they were hashed.
+    fn update_tree(&mut self) -> Result<()> {
+        let head = self.storage.get_head()?;
+        if head != self.tree_head {
+            for (table, hash) in &mut self.tree {
+                *hash = self.storage.calculate_table_hash(table)?;
+            }
+            self.tree_head = head;
+        }
+        for change in &self.pending {
+            let table = change.table();
+            if !self.tree.contains_key(table) {
+                self.tree.insert(table.to_string(), self.storage.calculate_table_hash(table)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CommitBuilder<'_> {
+    fn drop(&mut self) {
+        if self.written > 0 {
+            let _ = self.storage.discard_build(self.id);
+        }
+    }
+}
+
+impl CommitStorage {
+    /// Starts a `CommitBuilder` for a commit on HEAD.
+    pub fn commit_builder(&self) -> CommitBuilder<'_> {
+        CommitBuilder {
+            storage: self,
+            id: NEXT_BUILDER.fetch_add(1, Ordering::Relaxed),
+            pending: Vec::with_capacity(BUILDER_CHUNK),
+            written: 0,
+            tree: BTreeMap::new(),
+            tree_head: None,
+        }
+    }
+}
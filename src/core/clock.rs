@@ -0,0 +1,16 @@
+use crate::error::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the wall-clock time stamped on commits and reflog entries.
+pub trait Clock: Send + Sync {
+    /// Seconds since the UNIX epoch.
+    fn now(&self) -> Result<u64>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Result<u64> {
+        Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::core::crdt::{CrdtEngine, CrdtValue};
+use crate::core::database::WalEntry;
+use crate::core::models::{BranchlessReflogEntry, Change, Commit, LegacyCommit, PreMetadataCommit, ReflogEntry, UnsignedCommit};
+use crate::error::{GitDBError, Result};
+use bincode::Options;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// How everything a repository stores is turned into bytes: commits (and the
+/// bytes a commit's id is hashed from), materialized rows, staged changes,
+/// reflog entries, snapshots and write-ahead log entries.
+///
+/// Values inside a `Change` are not re-encoded: they are always a bincode
+/// `CrdtValue`, the encoding the `Change` constructors give them. Every API
+/// handing out row values (`get_row`, `checkout_state`, diffs) uses that same
+/// encoding whatever the codec, so only the stored bytes depend on it.
+pub trait Codec: Send + Sync {
+    /// Recorded in the repository so it is always read back the same way.
+    fn name(&self) -> &'static str;
+    fn encode(&self, commit: &Commit) -> Result<Vec<u8>>;
+    fn decode(&self, bytes: &[u8]) -> Result<Commit>;
+
+    /// The bytes a commit's id is hashed from: its encoding without the
+    /// signature, so signing a commit does not change the hash it signs.
+    fn identity(&self, commit: &Commit) -> Result<Vec<u8>> {
+        self.encode(&Commit { signature: None, ..commit.clone() })
+    }
+
+    /// A row value as stored under `table:id`. Table hashes are taken over
+    /// these bytes.
+    fn encode_value(&self, value: &CrdtValue) -> Result<Vec<u8>>;
+    fn decode_value(&self, bytes: &[u8]) -> Result<CrdtValue>;
+
+    /// A stored row value in the encoding of `Change` values.
+    fn to_change_value(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&self.decode_value(bytes)?)?)
+    }
+
+    fn encode_change(&self, change: &Change) -> Result<Vec<u8>>;
+    fn decode_change(&self, bytes: &[u8]) -> Result<Change>;
+
+    fn encode_reflog(&self, entry: &ReflogEntry) -> Result<Vec<u8>>;
+    fn decode_reflog(&self, bytes: &[u8]) -> Result<ReflogEntry>;
+
+    fn encode_snapshot(&self, engine: &CrdtEngine) -> Result<Vec<u8>>;
+    fn decode_snapshot(&self, bytes: &[u8]) -> Result<CrdtEngine>;
+
+    fn encode_wal(&self, entry: &WalEntry) -> Result<Vec<u8>>;
+    fn decode_wal(&self, bytes: &[u8]) -> Result<WalEntry>;
+}
+
+/// The fields of a commit that existed before metadata, laid out as they
+/// were serialized then.
+#[derive(Serialize)]
+struct PreMetadataIdentity<'a> {
+    parents: &'a Vec<[u8; 32]>,
+    message: &'a String,
+    timestamp: u64,
+    changes: &'a Vec<Change>,
+    tree: &'a BTreeMap<String, [u8; 32]>,
+    author: &'a String,
+    email: &'a String,
+    signature: Option<Vec<u8>>,
+}
+
+/// The original commit encoding, and the default.
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn encode(&self, commit: &Commit) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(commit)?)
+    }
+
+    /// Also reads every earlier commit layout.
+    fn decode(&self, bytes: &[u8]) -> Result<Commit> {
+        // Reject trailing bytes so a legacy payload never half-parses as a current one.
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes();
+        match options.deserialize::<Commit>(bytes) {
+            Ok(commit) => Ok(commit),
+            Err(e) => options.deserialize::<PreMetadataCommit>(bytes)
+                .map(Commit::from)
+                .or_else(|_| options.deserialize::<UnsignedCommit>(bytes).map(Commit::from))
+                .or_else(|_| options.deserialize::<LegacyCommit>(bytes).map(Commit::from))
+                .map_err(|_| e.into()),
+        }
+    }
+
+    /// Commits without metadata hash exactly as they did before the field
+    /// existed, so older hashes and signatures stay valid.
+    fn identity(&self, commit: &Commit) -> Result<Vec<u8>> {
+        if commit.metadata.is_empty() {
+            Ok(bincode::serialize(&PreMetadataIdentity {
+                parents: &commit.parents,
+                message: &commit.message,
+                timestamp: commit.timestamp,
+                changes: &commit.changes,
+                tree: &commit.tree,
+                author: &commit.author,
+                email: &commit.email,
+                signature: None,
+            })?)
+        } else {
+            self.encode(&Commit { signature: None, ..commit.clone() })
+        }
+    }
+
+    fn encode_value(&self, value: &CrdtValue) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode_value(&self, bytes: &[u8]) -> Result<CrdtValue> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Rows are already stored in the `Change` encoding.
+    fn to_change_value(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+
+    fn encode_change(&self, change: &Change) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(change)?)
+    }
+
+    fn decode_change(&self, bytes: &[u8]) -> Result<Change> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    fn encode_reflog(&self, entry: &ReflogEntry) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(entry)?)
+    }
+
+    /// Also reads entries written before they recorded branches.
+    fn decode_reflog(&self, bytes: &[u8]) -> Result<ReflogEntry> {
+        match bincode::deserialize::<ReflogEntry>(bytes) {
+            Ok(entry) => Ok(entry),
+            Err(e) => bincode::deserialize::<BranchlessReflogEntry>(bytes)
+                .map(ReflogEntry::from)
+                .map_err(|_| e.into()),
+        }
+    }
+
+    fn encode_snapshot(&self, engine: &CrdtEngine) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(engine)?)
+    }
+
+    /// Also reads snapshots taken before tombstones existed, which hold only
+    /// state and clocks.
+    fn decode_snapshot(&self, bytes: &[u8]) -> Result<CrdtEngine> {
+        match bincode::deserialize::<CrdtEngine>(bytes) {
+            Ok(engine) => Ok(engine),
+            Err(e) => bincode::deserialize(bytes)
+                .map(|(state, clocks)| CrdtEngine { state, clocks, tombstones: HashMap::new() })
+                .map_err(|_| e.into()),
+        }
+    }
+
+    fn encode_wal(&self, entry: &WalEntry) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(entry)?)
+    }
+
+    fn decode_wal(&self, bytes: &[u8]) -> Result<WalEntry> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// The built-in codec called `name`. Repositories written with a custom codec
+/// have to be opened with it set in `StorageConfig::codec`.
+pub fn codec_by_name(name: &str) -> Result<Arc<dyn Codec>> {
+    match name {
+        "bincode" => Ok(Arc::new(BincodeCodec)),
+        other => Err(GitDBError::InvalidInput(format!(
+            "Repository uses the '{}' codec, which must be supplied in StorageConfig::codec", other
+        ))),
+    }
+}
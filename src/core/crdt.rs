@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 use crate::error::{GitDBError, Result};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use crate::core::models::Change;
 
 pub type TableState = HashMap<String, CrdtValue>;
@@ -9,68 +9,331 @@ pub type TableState = HashMap<String, CrdtValue>;
 pub enum CrdtValue {
     Counter(u64),
     Register(Vec<u8>),
+    /// A row made of independently updatable fields.
+    Fields(BTreeMap<String, CrdtValue>),
 }
 
-#[derive(Debug, Clone)]
+impl CrdtValue {
+    /// Joins `other` into `self`: counters keep the larger count, registers
+    /// keep the greater byte string and field rows join field by field.
+    pub fn join(&mut self, other: &CrdtValue) -> Result<()> {
+        match (self, other) {
+            (CrdtValue::Counter(local), CrdtValue::Counter(remote)) => {
+                *local = (*local).max(*remote);
+            }
+            (CrdtValue::Register(local), CrdtValue::Register(remote)) => {
+                if *remote > *local {
+                    *local = remote.clone();
+                }
+            }
+            (CrdtValue::Fields(local), CrdtValue::Fields(remote)) => {
+                for (field, value) in remote {
+                    match local.get_mut(field) {
+                        Some(existing) => existing.join(value)?,
+                        None => {
+                            local.insert(field.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(GitDBError::TypeMismatch("Cannot join values of different kinds".into()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CrdtEngine {
     pub state: HashMap<String, TableState>,
+    /// Highest logical clock applied to each row, keyed by table then id.
+    pub clocks: HashMap<String, HashMap<String, u64>>,
+    /// Deleted rows, keyed by table then id, holding the row's clock at the
+    /// time of the delete (0 if it had none). A tombstone lets a delete win a
+    /// merge against a side that simply never saw it.
+    pub tombstones: HashMap<String, HashMap<String, u64>>,
+}
+
+impl Default for CrdtEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CrdtEngine {
     pub fn new() -> Self {
         Self {
             state: HashMap::new(),
+            clocks: HashMap::new(),
+            tombstones: HashMap::new(),
+        }
+    }
+
+    pub fn clock(&self, table: &str, id: &str) -> Option<u64> {
+        self.clocks.get(table).and_then(|rows| rows.get(id)).copied()
+    }
+
+    /// The clock recorded when `id` was deleted, if it is currently deleted.
+    pub fn tombstone(&self, table: &str, id: &str) -> Option<u64> {
+        self.tombstones.get(table).and_then(|rows| rows.get(id)).copied()
+    }
+
+    fn clear_tombstone(&mut self, table: &str, id: &str) {
+        if let Some(rows) = self.tombstones.get_mut(table) {
+            rows.remove(id);
         }
     }
 
     pub fn apply_change(&mut self, change: &Change) -> Result<()> {
         match change {
             Change::Insert { table, id, value } => {
+                self.clear_tombstone(table, id);
                 let row = self.state.entry(table.clone()).or_default();
                 let decoded: CrdtValue = bincode::deserialize(value)?;
                 row.insert(id.clone(), decoded);
             }
-            Change::Update { table, id, value } => {
+            Change::Update { table, id, value, clock } => {
+                self.clear_tombstone(table, id);
                 let row = self.state.entry(table.clone()).or_default();
+                let clocks = self.clocks.entry(table.clone()).or_default();
                 let decoded: CrdtValue = bincode::deserialize(value)?;
-                row.insert(id.clone(), decoded);
+                let current = clocks.get(id).copied();
+
+                match (*clock, current, row.get_mut(id)) {
+                    // Older writes lose to whatever is already there.
+                    (Some(incoming), Some(current), Some(_)) if incoming < current => {}
+                    // Equal clocks fall back to the deterministic CRDT join.
+                    (Some(incoming), Some(current), Some(existing)) if incoming == current => {
+                        existing.join(&decoded).map_err(|_| {
+                            GitDBError::TypeMismatch(format!("Type mismatch on update for ID: {}", id))
+                        })?;
+                    }
+                    _ => {
+                        row.insert(id.clone(), decoded);
+                    }
+                }
+
+                if let Some(incoming) = *clock {
+                    clocks.insert(id.clone(), current.map_or(incoming, |c| c.max(incoming)));
+                }
             }
             Change::Delete { table, id } => {
                 if let Some(row_map) = self.state.get_mut(table) {
                     row_map.remove(id);
                 }
+                let clock = self.clocks.get_mut(table).and_then(|clocks| clocks.remove(id));
+                self.tombstones.entry(table.clone()).or_default().insert(id.clone(), clock.unwrap_or(0));
+            }
+            Change::FieldUpdate { table, id, field, value } => {
+                self.clear_tombstone(table, id);
+                let decoded: CrdtValue = bincode::deserialize(value)?;
+                let row = self.state.entry(table.clone()).or_default();
+                match row.entry(id.clone()).or_insert_with(|| CrdtValue::Fields(BTreeMap::new())) {
+                    CrdtValue::Fields(fields) => {
+                        fields.insert(field.clone(), decoded);
+                    }
+                    _ => {
+                        return Err(GitDBError::TypeMismatch(format!(
+                            "Field update of {} on ID {} whose value has no fields", field, id
+                        )));
+                    }
+                }
+            }
+            Change::SchemaAdd { table } => {
+                self.state.entry(table.clone()).or_default();
+            }
+            Change::SchemaDrop { table } => {
+                self.state.remove(table);
+                self.clocks.remove(table);
+                self.tombstones.remove(table);
             }
         }
         Ok(())
     }
 
+    /// Applies `changes` in order, stopping at the first one that fails. The
+    /// error names the index of the failing change; earlier changes stay applied.
+    pub fn apply_changes(&mut self, changes: &[Change]) -> Result<()> {
+        for (index, change) in changes.iter().enumerate() {
+            self.apply_change(change).map_err(|e| match e {
+                GitDBError::TypeMismatch(msg) => {
+                    GitDBError::TypeMismatch(format!("change {} ({}:{}): {}", index, change.table(), change.id(), msg))
+                }
+                other => GitDBError::CorruptData(format!(
+                    "change {} ({}:{}) could not be applied: {}", index, change.table(), change.id(), other
+                )),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Joins `other` into this engine. A tombstone on either side removes the
+    /// row unless the other side updated it with a higher clock since.
     pub fn merge(&mut self, other: &Self) -> Result<()> {
+        for (table, deleted) in &other.tombstones {
+            for (id, &deleted_at) in deleted {
+                if self.clock(table, id).is_some_and(|local| local > deleted_at) {
+                    continue;
+                }
+                if let Some(rows) = self.state.get_mut(table) {
+                    rows.remove(id);
+                }
+                if let Some(clocks) = self.clocks.get_mut(table) {
+                    clocks.remove(id);
+                }
+                let tombstone = self.tombstones.entry(table.clone()).or_default().entry(id.clone()).or_insert(0);
+                *tombstone = (*tombstone).max(deleted_at);
+            }
+        }
+
         for (table, rows) in &other.state {
             let my_rows = self.state.entry(table.clone()).or_default();
+            let my_clocks = self.clocks.entry(table.clone()).or_default();
+            let my_tombstones = self.tombstones.entry(table.clone()).or_default();
             for (id, val) in rows {
-                match (my_rows.get_mut(id), val) {
-                    (Some(CrdtValue::Counter(local)), CrdtValue::Counter(remote)) => {
-                        *local = (*local).max(*remote);
+                let local_clock = my_clocks.get(id).copied();
+                let remote_clock = other.clock(table, id);
+                if let Some(&deleted_at) = my_tombstones.get(id) {
+                    if remote_clock.is_none_or(|r| r <= deleted_at) {
+                        continue;
                     }
-                    (Some(CrdtValue::Register(local)), CrdtValue::Register(remote)) => {
-                        if *remote > *local {
-                            *local = remote.clone();
+                    my_tombstones.remove(id);
+                }
+                match my_rows.get_mut(id) {
+                    Some(local) => match (local_clock, remote_clock) {
+                        (Some(l), Some(r)) if r > l => *local = val.clone(),
+                        (Some(l), Some(r)) if l > r => {}
+                        _ => {
+                            local.join(val).map_err(|_| {
+                                GitDBError::TypeMismatch(format!("Type mismatch on merge for ID: {}", id))
+                            })?;
                         }
-                    }
-                    (None, val) => {
+                    },
+                    None => {
                         my_rows.insert(id.clone(), val.clone());
                     }
-                    // Type mismatch
-                    _ => {
-                        return Err(GitDBError::TypeMismatch(format!("Type mismatch on merge for ID: {}", id)));
-                    }
+                }
+                if let Some(r) = remote_clock {
+                    my_clocks.insert(id.clone(), local_clock.map_or(r, |l| l.max(r)));
                 }
             }
         }
         Ok(())
     }
 
+    /// The live rows, with tombstoned ones left out.
     pub fn into_data(self) -> HashMap<String, TableState> {
-        self.state
+        let tombstones = self.tombstones;
+        self.state.into_iter()
+            .map(|(table, mut rows)| {
+                if let Some(deleted) = tombstones.get(&table) {
+                    rows.retain(|id, _| !deleted.contains_key(id));
+                }
+                (table, rows)
+            })
+            .collect()
+    }
+
+    /// Borrows every table without giving up the engine.
+    pub fn data(&self) -> &HashMap<String, TableState> {
+        &self.state
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableState> {
+        self.state.get(name)
+    }
+
+    /// A copy of the rows, clocks and tombstones of just `tables`.
+    pub fn subset<'a>(&self, tables: impl IntoIterator<Item = &'a str>) -> CrdtEngine {
+        let mut subset = CrdtEngine::new();
+        for table in tables {
+            if let Some(rows) = self.state.get(table) {
+                subset.state.insert(table.to_string(), rows.clone());
+            }
+            if let Some(clocks) = self.clocks.get(table) {
+                subset.clocks.insert(table.to_string(), clocks.clone());
+            }
+            if let Some(deleted) = self.tombstones.get(table) {
+                subset.tombstones.insert(table.to_string(), deleted.clone());
+            }
+        }
+        subset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(bytes: &[u8]) -> CrdtValue {
+        CrdtValue::Register(bytes.to_vec())
+    }
+
+    #[test]
+    fn apply_changes_applies_a_mixed_batch() {
+        let mut engine = CrdtEngine::new();
+        engine.apply_changes(&[
+            Change::insert_value("users", "1", &register(b"alice")).unwrap(),
+            Change::insert_value("users", "2", &register(b"bob")).unwrap(),
+            Change::update_value("users", "1", &register(b"alicia")).unwrap(),
+            Change::delete("users", "2"),
+            Change::field_update("teams", "1", "name", &register(b"core")).unwrap(),
+        ]).unwrap();
+
+        let users = engine.table("users").unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users["1"], register(b"alicia"));
+        assert_eq!(
+            engine.table("teams").unwrap()["1"],
+            CrdtValue::Fields(BTreeMap::from([("name".to_string(), register(b"core"))]))
+        );
+    }
+
+    #[test]
+    fn apply_changes_reports_the_failing_index() {
+        let mut engine = CrdtEngine::new();
+        let result = engine.apply_changes(&[
+            Change::insert_value("users", "1", &register(b"alice")).unwrap(),
+            Change::field_update("users", "1", "name", &register(b"alicia")).unwrap(),
+            Change::insert_value("users", "2", &register(b"bob")).unwrap(),
+        ]);
+
+        match result {
+            Err(GitDBError::TypeMismatch(message)) => assert!(message.starts_with("change 1 (users:1)"), "{}", message),
+            other => panic!("expected a type mismatch, got {:?}", other),
+        }
+        assert_eq!(engine.table("users").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn borrowed_state_matches_what_was_applied() {
+        let mut engine = CrdtEngine::new();
+        engine.apply_changes(&[
+            Change::insert_value("users", "1", &register(b"alice")).unwrap(),
+            Change::schema_add("teams"),
+        ]).unwrap();
+
+        assert_eq!(engine.data().len(), 2);
+        assert_eq!(engine.table("users").unwrap()["1"], register(b"alice"));
+        assert!(engine.table("teams").unwrap().is_empty());
+        assert!(engine.table("audit").is_none());
+        // Borrowing leaves the engine usable.
+        engine.apply_change(&Change::delete("users", "1")).unwrap();
+        assert!(engine.table("users").unwrap().is_empty());
+    }
+
+    #[test]
+    fn merged_tombstone_removes_a_row_the_other_side_still_has() {
+        let insert = Change::insert_value("users", "1", &register(b"alice")).unwrap();
+        let mut deleted = CrdtEngine::new();
+        deleted.apply_changes(&[insert.clone(), Change::delete("users", "1")]).unwrap();
+        let mut untouched = CrdtEngine::new();
+        untouched.apply_change(&insert).unwrap();
+
+        untouched.merge(&deleted).unwrap();
+
+        assert_eq!(untouched.tombstone("users", "1"), Some(0));
+        assert!(untouched.into_data()["users"].is_empty());
     }
 }
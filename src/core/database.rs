@@ -1,249 +1,3163 @@
-use rocksdb::{DB, Options};
-use blake3;
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::core::models::{Commit, Change};
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, DB, Options};
+use crate::core::hashing::{ChecksumAlgorithm, Hasher};
+use crate::core::clock::{Clock, SystemClock};
+use crate::core::codec::{codec_by_name, BincodeCodec, Codec};
+use std::thread;
+use std::time::Duration;
+use crate::core::models::{coalesce_changes, ChangelogGroup, Commit, CommitRecord, Change, Conflict, DiffStats, FieldChange, IntegrityIssue, ReflogEntry, UNKNOWN_AUTHOR};
 use crate::error::{GitDBError, Result};
-use std::sync::Arc;
-use std::collections::HashMap;
-use crate::core::crdt::CrdtEngine;
-use rocksdb::WriteBatch;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::num::NonZeroUsize;
+use lru::LruCache;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use crate::core::crdt::{CrdtEngine, CrdtValue, TableState};
+use crate::core::merge::{find_conflicts, three_way_merge};
+use crate::core::backend::{Backend, InMemoryBackend, WriteBatch};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+const CHECKSUM_LEN: usize = 32;
+const DEFAULT_COMMIT_CACHE_CAPACITY: usize = 256;
+/// Insert and update values larger than this are kept out of line by default.
+pub const DEFAULT_BLOB_THRESHOLD: usize = 1 << 20;
+/// Longest commit message, in bytes, accepted unless configured otherwise.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+// One-byte tags in front of new commit payloads. Commits written before the
+// tag existed start with the parents length, which never reaches these values.
+const FORMAT_RAW: u8 = 0xF0;
+const FORMAT_ZSTD: u8 = 0xF1;
+/// Set on the format tag of commits stored without a checksum trailer.
+const FORMAT_UNCHECKED: u8 = 0x02;
+
+pub const BRANCH_PREFIX: &str = "refs/heads/";
+pub const TAG_PREFIX: &str = "refs/tags/";
+pub const WORKSPACE_PREFIX: &str = "refs/workspaces/";
+/// Rows of a workspace live under `ws:<name>:<table>:<id>`.
+const WORKSPACE_ROW_PREFIX: &str = "ws:";
+pub(crate) const HEAD_BRANCH_KEY: &[u8] = b"HEAD_BRANCH";
+const REFLOG_PREFIX: &str = "reflog:";
+const REFLOG_SEQ_KEY: &[u8] = b"REFLOG_SEQ";
+const SNAPSHOT_PREFIX: &str = "snapshot:";
+const STAGING_PREFIX: &str = "staging:";
+const STAGING_SEQ_KEY: &[u8] = b"STAGING_SEQ";
+const BLOB_PREFIX: &str = "blob:";
+/// Changes a `CommitBuilder` has written out live under `build:<builder>:<seq>`.
+const BUILD_PREFIX: &str = "build:";
+pub(crate) const CHECKSUM_ALGORITHM_KEY: &[u8] = b"CHECKSUM_ALGORITHM";
+pub(crate) const INTEGRITY_TRAILER_KEY: &[u8] = b"INTEGRITY_TRAILER";
+pub(crate) const CODEC_KEY: &[u8] = b"CODEC";
+const WAL_PREFIX: &str = "wal:";
+const WAL_KEY: &[u8] = b"wal:pending";
+/// Bookkeeping keyspaces that share the `name:` shape of row keys, so no
+/// table may be named after one of them.
+const INTERNAL_PREFIXES: [&str; 7] = [REFLOG_PREFIX, SNAPSHOT_PREFIX, STAGING_PREFIX, BUILD_PREFIX, BLOB_PREFIX, WORKSPACE_ROW_PREFIX, WAL_PREFIX];
+/// Refs live under `refs/`, which a table's rows must not land in either.
+const REFS_PREFIX: &str = "refs/";
+
+/// Row id older databases used to mark that a table exists, before
+/// `Change::SchemaAdd` was introduced.
+pub const SCHEMA_MARKER_ID: &str = "!schema";
+/// Separates the table from the id in row keys.
+pub const KEY_DELIMITER: char = ':';
+
+/// Identity hash of a commit: the hash of `Codec::identity`.
+pub fn commit_id(hasher: &dyn Hasher, codec: &dyn Codec, commit: &Commit) -> Result<[u8; 32]> {
+    Ok(hasher.hash(&codec.identity(commit)?))
+}
+
+/// Whether a stored commit was written without a checksum trailer.
+fn is_unchecked(raw: &[u8]) -> bool {
+    matches!(raw.first(), Some(&tag) if tag == FORMAT_RAW | FORMAT_UNCHECKED || tag == FORMAT_ZSTD | FORMAT_UNCHECKED)
+}
+
+/// Splits a stored commit into payload and trailing checksum, verifies the
+/// checksum and deserializes the payload. Commits written without a trailer
+/// are deserialized as they are.
+pub(crate) fn decode_commit(hasher: &dyn Hasher, codec: &dyn Codec, hash: &[u8], raw: &[u8]) -> Result<Commit> {
+    if is_unchecked(raw) {
+        return decode_payload(codec, hash, raw);
+    }
+    if raw.len() < CHECKSUM_LEN {
+        return Err(GitDBError::CorruptData(format!(
+            "Commit {} is too short to contain a checksum", hex::encode(hash)
+        )));
+    }
+
+    let (payload, stored_checksum) = raw.split_at(raw.len() - CHECKSUM_LEN);
+    if hasher.hash(payload) != stored_checksum {
+        return Err(GitDBError::CorruptData(format!(
+            "Checksum mismatch for commit {}", hex::encode(hash)
+        )));
+    }
+    decode_payload(codec, hash, payload)
+}
+
+/// Decodes a commit payload with its checksum already removed.
+fn decode_payload(codec: &dyn Codec, hash: &[u8], payload: &[u8]) -> Result<Commit> {
+    let payload: Cow<[u8]> = match payload.split_first() {
+        Some((&tag, body)) if tag & !FORMAT_UNCHECKED == FORMAT_RAW => Cow::Borrowed(body),
+        Some((&tag, body)) if tag & !FORMAT_UNCHECKED == FORMAT_ZSTD => Cow::Owned(zstd::decode_all(body).map_err(|e| {
+            GitDBError::CorruptData(format!("Cannot decompress commit {}: {}", hex::encode(hash), e))
+        })?),
+        _ => Cow::Borrowed(payload),
+    };
+
+    codec.decode(&payload)
+}
+
+/// Tags a serialized commit with its storage format, compressing it with zstd
+/// when a level is given and that actually saves space.
+fn encode_payload(serialized: &[u8], compression_level: Option<i32>) -> Result<Vec<u8>> {
+    if let Some(level) = compression_level {
+        let compressed = zstd::encode_all(serialized, level)?;
+        if compressed.len() < serialized.len() {
+            let mut tagged = Vec::with_capacity(compressed.len() + 1);
+            tagged.push(FORMAT_ZSTD);
+            tagged.extend_from_slice(&compressed);
+            return Ok(tagged);
+        }
+    }
+
+    let mut tagged = Vec::with_capacity(serialized.len() + 1);
+    tagged.push(FORMAT_RAW);
+    tagged.extend_from_slice(serialized);
+    Ok(tagged)
+}
+
+/// Fails with a conflict if `changes` update or delete rows missing from `state`.
+fn ensure_applicable(state: &CrdtEngine, changes: &[Change], action: &str, source: &[u8; 32]) -> Result<()> {
+    for change in changes {
+        let exists = state.state.get(change.table())
+            .is_some_and(|rows| rows.contains_key(change.id()));
+        let problem = match change {
+            Change::Update { .. } if !exists => Some("update of a missing row"),
+            Change::Delete { .. } if !exists => Some("delete of a missing row"),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            return Err(GitDBError::Conflict(format!(
+                "Cannot {} {}: {} {}:{}",
+                action, hex::encode(source), problem, change.table(), change.id()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects changes whose values do not decode as a `CrdtValue`, which would
+/// otherwise break every later replay of the commit, and changes whose rows
+/// would land in another table's or an internal keyspace.
+pub(crate) fn validate_changes(changes: &[Change]) -> Result<()> {
+    for change in changes {
+        // Row keys are `table:id`, so a delimiter in either part could land
+        // the row under another table's prefix.
+        if change.table().contains(KEY_DELIMITER) || change.id().contains(KEY_DELIMITER) {
+            return Err(GitDBError::InvalidInput(format!(
+                "Table and id must not contain '{}': {}:{}", KEY_DELIMITER, change.table(), change.id()
+            )));
+        }
+        let prefix = row_key(change.table(), "");
+        if INTERNAL_PREFIXES.contains(&prefix.as_str()) || prefix.starts_with(REFS_PREFIX) {
+            return Err(GitDBError::InvalidInput(format!(
+                "Table name '{}' is reserved for internal use", change.table()
+            )));
+        }
+        if let Change::Insert { value, .. } | Change::Update { value, .. } | Change::FieldUpdate { value, .. } = change {
+            bincode::deserialize::<CrdtValue>(value).map_err(|_| {
+                GitDBError::InvalidInput(format!(
+                    "Change value for {}:{} is not a valid CRDT value", change.table(), change.id()
+                ))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Rejects messages longer than `max_len` bytes or containing control
+/// characters other than newline.
+fn validate_message(message: &str, max_len: usize) -> Result<()> {
+    if message.len() > max_len {
+        return Err(GitDBError::InvalidInput(format!(
+            "Commit message is {} bytes; the limit is {}", message.len(), max_len
+        )));
+    }
+    if let Some(c) = message.chars().find(|c| c.is_control() && *c != '\n') {
+        return Err(GitDBError::InvalidInput(format!(
+            "Commit message contains control character {:?}", c
+        )));
+    }
+    Ok(())
+}
+
+/// Returns `raw` without its trailing checksum when one is present. Rows were
+/// only ever checksummed with blake3, before the algorithm was configurable.
+fn strip_checksum(raw: &[u8]) -> &[u8] {
+    if raw.len() >= CHECKSUM_LEN {
+        let (payload, trailer) = raw.split_at(raw.len() - CHECKSUM_LEN);
+        if blake3::hash(payload).as_bytes() == trailer {
+            return payload;
+        }
+    }
+    raw
+}
+
+/// Decodes a hex commit hash, requiring exactly 32 bytes.
+pub fn parse_hash(s: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(s)
+        .map_err(|_| GitDBError::InvalidInput(format!("'{}' is not a valid hex hash", s)))?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        GitDBError::InvalidInput(format!("Commit hash must be 32 bytes, got {}", bytes.len()))
+    })
+}
+
+/// Picks the final value of a conflicting row for `merge_commits_with`.
+pub type ConflictResolver = dyn Fn(&Conflict) -> Vec<u8>;
+
+pub fn row_key(table: &str, id: &str) -> String {
+    format!("{}{}{}", table, KEY_DELIMITER, id)
+}
+
+/// Splits a stored key into `(table, id)` if it is a materialized row rather
+/// than a commit, ref or bookkeeping key.
+fn split_row_key(key: &[u8]) -> Option<(&str, &str)> {
+    // Commit keys are raw hashes and essentially never valid UTF-8.
+    let key = std::str::from_utf8(key).ok()?;
+    if INTERNAL_PREFIXES.iter().any(|prefix| key.starts_with(prefix)) || key.starts_with(REFS_PREFIX) {
+        return None;
+    }
+    key.split_once(':')
+}
+
+pub fn branch_key(name: &str) -> String {
+    format!("{}{}", BRANCH_PREFIX, name)
+}
+
+/// Zero-padded so reflog keys sort in the order entries were written.
+fn reflog_key(seq: u64) -> String {
+    format!("{}{:020}", REFLOG_PREFIX, seq)
+}
+
+fn blob_key(hash: &[u8; 32]) -> String {
+    format!("{}{}", BLOB_PREFIX, hex::encode(hash))
+}
+
+/// The blob hash if `value` is a reference to an out-of-line value. References
+/// are `blob:` followed by the raw hash; a real value is a bincode `CrdtValue`
+/// whose leading variant tag can never spell `blob`.
+fn blob_reference(value: &[u8]) -> Option<[u8; 32]> {
+    value.strip_prefix(BLOB_PREFIX.as_bytes())?.try_into().ok()
+}
+
+/// Swaps blob references in a decoded commit back for the values they name.
+pub(crate) fn resolve_blobs(db: &dyn Backend, commit: &mut Commit) -> Result<()> {
+    for change in &mut commit.changes {
+        if let Change::Insert { value, .. } | Change::Update { value, .. } = change {
+            if let Some(hash) = blob_reference(value) {
+                *value = db.get(blob_key(&hash).as_bytes())?.ok_or_else(|| GitDBError::CorruptData(format!(
+                    "Blob {} referenced by a commit is missing", hex::encode(hash)
+                )))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Zero-padded so staged changes come back in the order they were staged.
+fn staging_key(seq: u64) -> String {
+    format!("{}{:020}", STAGING_PREFIX, seq)
+}
+
+fn build_prefix(builder: u64) -> String {
+    format!("{}{:020}:", BUILD_PREFIX, builder)
+}
+
+/// Zero-padded like `staging_key`, so a builder's changes keep their order.
+fn build_key(builder: u64, seq: u64) -> String {
+    format!("{}{:020}", build_prefix(builder), seq)
+}
+
+fn snapshot_key(hash: &[u8; 32]) -> String {
+    format!("{}{}", SNAPSHOT_PREFIX, hex::encode(hash))
+}
+
+pub fn tag_key(name: &str) -> String {
+    format!("{}{}", TAG_PREFIX, name)
+}
+
+pub fn workspace_key(name: &str) -> String {
+    format!("{}{}", WORKSPACE_PREFIX, name)
+}
+
+/// The table name under which `table`'s rows are stored for workspace `name`,
+/// so `row_key` and `calculate_table_hash` work on workspace rows unchanged.
+fn workspace_table(name: &str, table: &str) -> String {
+    format!("{}{}{}{}", WORKSPACE_ROW_PREFIX, name, KEY_DELIMITER, table)
+}
+
+fn validate_ref_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(GitDBError::InvalidInput(format!("{} name cannot be empty", kind)));
+    }
+    // Ref keys must never look like `table:id` row keys.
+    if name.chars().any(|c| c.is_whitespace() || c.is_control() || c == KEY_DELIMITER) {
+        return Err(GitDBError::InvalidInput(format!("Invalid {} name '{}'", kind.to_lowercase(), name)));
+    }
+    Ok(())
+}
+
+/// JSON shape of a single change as emitted by `diff_to_json`.
+#[derive(Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum JsonChange<'a> {
+    Insert { table: &'a str, id: &'a str, value_base64: String },
+    Update {
+        table: &'a str,
+        id: &'a str,
+        value_base64: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        clock: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fields: Option<Vec<JsonFieldChange>>,
+    },
+    Delete { table: &'a str, id: &'a str },
+    #[serde(rename = "field_update")]
+    FieldUpdate { table: &'a str, id: &'a str, field: &'a str, value_base64: String },
+    #[serde(rename = "schema_add")]
+    SchemaAdd { table: &'a str },
+    #[serde(rename = "schema_drop")]
+    SchemaDrop { table: &'a str },
+}
+
+impl<'a> From<&'a Change> for JsonChange<'a> {
+    fn from(change: &'a Change) -> Self {
+        match change {
+            Change::Insert { table, id, value } => JsonChange::Insert {
+                table,
+                id,
+                value_base64: BASE64.encode(value),
+            },
+            Change::Update { table, id, value, clock } => JsonChange::Update {
+                table,
+                id,
+                value_base64: BASE64.encode(value),
+                clock: *clock,
+                fields: None,
+            },
+            Change::Delete { table, id } => JsonChange::Delete { table, id },
+            Change::FieldUpdate { table, id, field, value } => JsonChange::FieldUpdate {
+                table,
+                id,
+                field,
+                value_base64: BASE64.encode(value),
+            },
+            Change::SchemaAdd { table } => JsonChange::SchemaAdd { table },
+            Change::SchemaDrop { table } => JsonChange::SchemaDrop { table },
+        }
+    }
+}
+
+/// JSON shape of a `FieldChange`, attached to updates by `diff_to_json_with_fields`.
+#[derive(Serialize)]
+struct JsonFieldChange {
+    field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from_base64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_base64: Option<String>,
+}
+
+impl From<FieldChange> for JsonFieldChange {
+    fn from(change: FieldChange) -> Self {
+        JsonFieldChange {
+            field: change.field,
+            from_base64: change.from.map(|v| BASE64.encode(v)),
+            to_base64: change.to.map(|v| BASE64.encode(v)),
+        }
+    }
+}
+
+/// Lists the fields that differ between two encoded row values, sorted by
+/// field name. Values without fields are compared whole and, if different,
+/// reported as a single change to the field `""`.
+pub fn field_diff(from_value: &[u8], to_value: &[u8]) -> Result<Vec<FieldChange>> {
+    let from: CrdtValue = bincode::deserialize(from_value)?;
+    let to: CrdtValue = bincode::deserialize(to_value)?;
+
+    let (CrdtValue::Fields(from_fields), CrdtValue::Fields(to_fields)) = (&from, &to) else {
+        if from == to {
+            return Ok(Vec::new());
+        }
+        return Ok(vec![FieldChange {
+            field: String::new(),
+            from: Some(from_value.to_vec()),
+            to: Some(to_value.to_vec()),
+        }]);
+    };
+
+    let fields: BTreeSet<&String> = from_fields.keys().chain(to_fields.keys()).collect();
+    let mut changes = Vec::new();
+    for field in fields {
+        let before = from_fields.get(field);
+        let after = to_fields.get(field);
+        if before != after {
+            changes.push(FieldChange {
+                field: field.clone(),
+                from: before.map(bincode::serialize).transpose()?,
+                to: after.map(bincode::serialize).transpose()?,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Changes that turn the rows of `table` in `from` into those in `to`.
+fn diff_table(table: &str, from: &CrdtEngine, to: &CrdtEngine) -> Result<Vec<Change>> {
+    let encode = |engine: &CrdtEngine| -> Result<HashMap<String, Vec<u8>>> {
+        let mut rows = HashMap::new();
+        for (id, value) in engine.state.get(table).into_iter().flatten() {
+            rows.insert(id.clone(), bincode::serialize(value)?);
+        }
+        Ok(rows)
+    };
+
+    Ok(diff_states(&encode(from)?, &encode(to)?, table)
+        .into_iter()
+        .map(|change| {
+            let clock = to.clock(table, change.id());
+            change.with_clock(clock)
+        })
+        .collect())
+}
+
+/// Changes that turn the rows `from` into the rows `to`, both keyed by id
+/// with values in the same encoding as `Change` values. Sorted by id.
+pub fn diff_states(from: &HashMap<String, Vec<u8>>, to: &HashMap<String, Vec<u8>>, table: &str) -> Vec<Change> {
+    let ids: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    ids.into_iter()
+        .filter_map(|id| match (from.get(id), to.get(id)) {
+            (Some(from_val), Some(to_val)) if from_val != to_val => Some(Change::Update {
+                table: table.to_string(),
+                id: id.clone(),
+                value: to_val.clone(),
+                clock: None,
+            }),
+            (None, Some(to_val)) => Some(Change::Insert {
+                table: table.to_string(),
+                id: id.clone(),
+                value: to_val.clone(),
+            }),
+            (Some(_), None) => Some(Change::delete(table, id)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Like `diff_table`, but a table appearing or disappearing is reported as
+/// `SchemaAdd`/`SchemaDrop` around its row changes. Changes to the legacy
+/// `!schema` marker row are left out since the schema change covers them.
+fn diff_table_with_schema(table: &str, from: &CrdtEngine, to: &CrdtEngine) -> Result<Vec<Change>> {
+    let existed = from.state.contains_key(table);
+    let exists = to.state.contains_key(table);
+
+    let mut diffs = Vec::new();
+    if exists && !existed {
+        diffs.push(Change::schema_add(table));
+    }
+    diffs.extend(diff_table(table, from, to)?.into_iter().filter(|c| c.id() != SCHEMA_MARKER_ID));
+    if existed && !exists {
+        diffs.push(Change::schema_drop(table));
+    }
+    Ok(diffs)
+}
+
+/// Tables whose rows may differ between two commits with these trees: those
+/// `to` records differently from `from`, then those only `from` records,
+/// which may have been dropped on the way to `to`.
+fn tables_to_diff<'a>(from: &'a BTreeMap<String, [u8; 32]>, to: &'a BTreeMap<String, [u8; 32]>) -> Vec<&'a String> {
+    let changed = to.iter().filter(|(table, hash)| from.get(*table) != Some(*hash)).map(|(table, _)| table);
+    let dropped = from.keys().filter(|table| !to.contains_key(*table));
+    changed.chain(dropped).collect()
+}
+
+/// Changes that turn every table in `from` into its state in `to`.
+fn diff_engines(from: &CrdtEngine, to: &CrdtEngine) -> Result<Vec<Change>> {
+    let tables: BTreeSet<&String> = from.state.keys().chain(to.state.keys()).collect();
+    let mut diffs = Vec::new();
+    for table in tables {
+        diffs.extend(diff_table_with_schema(table, from, to)?);
+    }
+    Ok(diffs)
+}
+
+/// Counts inserts, updates and deletes per table, ignoring schema changes.
+fn tally_changes(changes: &[Change]) -> HashMap<String, DiffStats> {
+    let mut summary: HashMap<String, DiffStats> = HashMap::new();
+    for change in changes {
+        if change.is_schema() {
+            continue;
+        }
+        let stats = summary.entry(change.table().to_string()).or_default();
+        match change {
+            Change::Insert { .. } => stats.inserts += 1,
+            Change::Update { .. } | Change::FieldUpdate { .. } => stats.updates += 1,
+            Change::Delete { .. } => stats.deletes += 1,
+            Change::SchemaAdd { .. } | Change::SchemaDrop { .. } => {}
+        }
+    }
+    summary
+}
+
+const PREVIEW_BYTES: usize = 8;
+
+/// Hex of the first few bytes of `value`, with `...` if it was cut short.
+fn value_preview(value: &[u8]) -> String {
+    if value.len() > PREVIEW_BYTES {
+        format!("{}...", hex::encode(&value[..PREVIEW_BYTES]))
+    } else {
+        hex::encode(value)
+    }
+}
+
+/// RocksDB reports a held `LOCK` file as a plain IO error, so the message is
+/// the only way to tell it apart from other open failures. A lock held by
+/// another process mentions the lock file; one held by this process says so.
+fn is_lock_error(err: &rocksdb::Error) -> bool {
+    let message = err.to_string();
+    message.contains("lock file") || message.contains("lock hold by current process")
+}
+
+/// Escapes `text` for use inside a double-quoted DOT string.
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn cycle_detected(hash: &[u8; 32]) -> GitDBError {
+    GitDBError::CorruptData(format!("cycle detected at commit {}", hex::encode(hash)))
+}
+
+/// Orders `commits`, all reachable from `start`, so each one comes after all
+/// of its descendants; ties are broken by timestamp and then hash.
+fn newest_first(start: [u8; 32], mut commits: HashMap<[u8; 32], Commit>) -> Result<Vec<CommitRecord>> {
+    let Some(start_commit) = commits.get(&start) else {
+        return Ok(Vec::new());
+    };
+
+    let mut pending_children: HashMap<[u8; 32], usize> = HashMap::new();
+    for commit in commits.values() {
+        for parent in &commit.parents {
+            *pending_children.entry(*parent).or_default() += 1;
+        }
+    }
+
+    let mut ready = BinaryHeap::from([(start_commit.timestamp, start)]);
+    let mut history = Vec::with_capacity(commits.len());
+    while let Some((_, hash)) = ready.pop() {
+        let Some(commit) = commits.remove(&hash) else {
+            continue;
+        };
+        // Only the start can be emitted with children still pending, and
+        // only if one of its own ancestors claims it as a parent.
+        if pending_children.get(&hash).is_some_and(|n| *n > 0) {
+            return Err(cycle_detected(&hash));
+        }
+        for parent in &commit.parents {
+            let remaining = pending_children.entry(*parent).or_default();
+            *remaining -= 1;
+            if *remaining == 0 {
+                if let Some(parent_commit) = commits.get(parent) {
+                    ready.push((parent_commit.timestamp, *parent));
+                }
+            }
+        }
+        history.push(CommitRecord { hash, commit });
+    }
+
+    // Commits on a cycle never run out of pending children.
+    if let Some(stuck) = commits.keys().next() {
+        return Err(cycle_detected(stuck));
+    }
+    Ok(history)
+}
+
+/// Keeps a commit built on a parent from the same, not yet written, batch
+/// strictly newer than that parent.
+fn follow_staged_parent(commit: &mut Commit, parent_timestamp: Option<u64>) {
+    if let Some(parent_timestamp) = parent_timestamp {
+        commit.timestamp = commit.timestamp.max(parent_timestamp + 1);
+    }
+}
+
+/// RocksDB tuning knobs for `CommitStorage::open_with_options`. Fields left
+/// as `None` keep RocksDB's defaults.
+#[derive(Clone, Default)]
+pub struct StorageConfig {
+    pub write_buffer_size: Option<usize>,
+    pub max_open_files: Option<i32>,
+    pub compression: Option<DBCompressionType>,
+    pub block_cache_bytes: Option<usize>,
+    /// Hash algorithm for a new repository. Opening an existing repository
+    /// with a different algorithm than it was created with fails.
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Whether new commits get a checksum trailer. Recorded in the database;
+    /// `None` keeps the recorded choice, which defaults to on. Commits already
+    /// stored read correctly either way.
+    pub integrity_trailer: Option<bool>,
+    /// Longest commit message in bytes; `None` uses `DEFAULT_MAX_MESSAGE_LEN`.
+    pub max_message_len: Option<usize>,
+    /// Codec for a new repository, and the one a repository written with a
+    /// custom codec has to be reopened with. `None` uses the recorded
+    /// built-in codec, bincode if none was recorded.
+    pub codec: Option<Arc<dyn Codec>>,
+}
+
+impl std::fmt::Debug for StorageConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StorageConfig")
+            .field("write_buffer_size", &self.write_buffer_size)
+            .field("max_open_files", &self.max_open_files)
+            .field("compression", &self.compression)
+            .field("block_cache_bytes", &self.block_cache_bytes)
+            .field("checksum", &self.checksum)
+            .field("integrity_trailer", &self.integrity_trailer)
+            .field("max_message_len", &self.max_message_len)
+            .field("codec", &self.codec.as_ref().map(|c| c.name()))
+            .finish()
+    }
+}
+
+/// A commit write logged before it is applied. `head` is HEAD as it was when
+/// the write was prepared, so recovery can tell whether it still applies.
+#[derive(Serialize, Deserialize)]
+pub struct WalEntry {
+    pub head: Option<[u8; 32]>,
+    pub batch: WriteBatch,
+}
+
+/// Settles a write left in the log by a crash: replays it if HEAD has not moved
+/// since it was logged, and otherwise (or if the entry is unreadable) drops it.
+/// Returns whether a write was replayed.
+fn recover_wal(db: &dyn Backend, codec: &dyn Codec) -> Result<bool> {
+    let Some(raw) = db.get(WAL_KEY)? else {
+        return Ok(false);
+    };
+    let head = db.get(b"HEAD")?;
+    match codec.decode_wal(&raw) {
+        Ok(WalEntry { head: logged, mut batch }) if logged.map(|h| h.to_vec()) == head => {
+            batch.delete(WAL_KEY);
+            db.write(batch)?;
+            Ok(true)
+        }
+        _ => {
+            db.delete(WAL_KEY)?;
+            Ok(false)
+        }
+    }
+}
+
+/// Deletes what builders that never finished wrote before the process stopped.
+/// No builder can be running while a repository opens.
+fn clear_abandoned_builds(db: &dyn Backend) -> Result<()> {
+    let mut batch = WriteBatch::default();
+    for item in db.prefix_iterator(BUILD_PREFIX.as_bytes()) {
+        let (key, _) = item?;
+        batch.delete(key);
+    }
+    db.write(batch)
+}
+
+/// Picks the codec for `db`: `requested` if given, which must match the
+/// recorded one, otherwise the recorded built-in codec. A codec is only
+/// recorded once one is requested, and only before anything was committed.
+fn settle_codec(db: &dyn Backend, requested: Option<Arc<dyn Codec>>) -> Result<Arc<dyn Codec>> {
+    let recorded = db.get(CODEC_KEY)?.map(|name| String::from_utf8_lossy(&name).into_owned());
+    let Some(codec) = requested else {
+        return match recorded {
+            Some(name) => codec_by_name(&name),
+            None => Ok(Arc::new(BincodeCodec)),
+        };
+    };
+    match recorded {
+        Some(name) if name == codec.name() => {}
+        Some(name) => {
+            return Err(GitDBError::InvalidInput(format!(
+                "Repository uses the '{}' codec and cannot be opened with '{}'", name, codec.name()
+            )));
+        }
+        None if codec.name() == BincodeCodec.name() => {}
+        None => {
+            if db.get(b"HEAD")?.is_some() || db.prefix_iterator(BRANCH_PREFIX.as_bytes()).next().is_some() {
+                return Err(GitDBError::InvalidInput(format!(
+                    "Repository already has commits and cannot switch to the '{}' codec", codec.name()
+                )));
+            }
+            db.put(CODEC_KEY, codec.name().as_bytes())?;
+        }
+    }
+    Ok(codec)
+}
+
+/// The checksum algorithm recorded in `db`, blake3 if none was recorded.
+pub fn repo_hasher(db: &dyn Backend) -> Result<Arc<dyn Hasher>> {
+    let algorithm = match db.get(CHECKSUM_ALGORITHM_KEY)? {
+        Some(name) => ChecksumAlgorithm::from_name(&String::from_utf8_lossy(&name))?,
+        None => ChecksumAlgorithm::default(),
+    };
+    Ok(algorithm.hasher())
+}
+
+/// Picks the algorithm for `db` and records it if nothing was recorded yet.
+fn settle_checksum_algorithm(db: &dyn Backend, requested: Option<ChecksumAlgorithm>) -> Result<ChecksumAlgorithm> {
+    if let Some(name) = db.get(CHECKSUM_ALGORITHM_KEY)? {
+        let stored = ChecksumAlgorithm::from_name(&String::from_utf8_lossy(&name))?;
+        if requested.is_some_and(|r| r != stored) {
+            return Err(GitDBError::InvalidInput(format!(
+                "Repository uses {} checksums and cannot be opened with another algorithm", stored.name()
+            )));
+        }
+        return Ok(stored);
+    }
+
+    // Repositories from before the key existed are blake3 throughout. Other
+    // settings may already be recorded in a repository that holds no data.
+    let algorithm = requested.unwrap_or_default();
+    let holds_data = db.iterator().any(|item| {
+        item.map_or(true, |(key, _)| ![CODEC_KEY, INTEGRITY_TRAILER_KEY].contains(&&*key))
+    });
+    if algorithm != ChecksumAlgorithm::Blake3 && holds_data {
+        return Err(GitDBError::InvalidInput(format!(
+            "Cannot switch an existing blake3 repository to {} checksums", algorithm.name()
+        )));
+    }
+    db.put(CHECKSUM_ALGORITHM_KEY, algorithm.name().as_bytes())?;
+    Ok(algorithm)
+}
+
+/// Records `requested` if given, otherwise returns the recorded setting.
+fn settle_integrity_trailer(db: &dyn Backend, requested: Option<bool>) -> Result<bool> {
+    if let Some(enabled) = requested {
+        db.put(INTEGRITY_TRAILER_KEY, &[enabled as u8])?;
+        return Ok(enabled);
+    }
+    Ok(db.get(INTEGRITY_TRAILER_KEY)?.is_none_or(|raw| raw != [0]))
+}
+
+impl StorageConfig {
+    fn to_options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        if let Some(size) = self.write_buffer_size {
+            opts.set_write_buffer_size(size);
+        }
+        if let Some(files) = self.max_open_files {
+            opts.set_max_open_files(files);
+        }
+        if let Some(compression) = self.compression {
+            opts.set_compression_type(compression);
+        }
+        if let Some(bytes) = self.block_cache_bytes {
+            let mut table_opts = BlockBasedOptions::default();
+            table_opts.set_block_cache(&Cache::new_lru_cache(bytes));
+            opts.set_block_based_table_factory(&table_opts);
+        }
+        opts
+    }
+}
 
 pub struct CommitStorage {
-    pub db: Arc<DB>,
+    pub db: Arc<dyn Backend>,
+    /// zstd level used for new commit payloads; `None` stores them uncompressed.
+    pub compression_level: Option<i32>,
+    /// Recently decoded commits; `None` when caching is disabled.
+    commit_cache: Option<Mutex<LruCache<[u8; 32], Arc<Commit>>>>,
+    /// Held across every read-HEAD / write / move-HEAD sequence so concurrent
+    /// writers line up into a single chain.
+    head_lock: Mutex<()>,
+    /// Held while numbering or consuming staged changes so no two get the
+    /// same sequence number and none is cleared without being committed.
+    /// Taken after `head_lock` when both are needed.
+    staging_lock: Mutex<()>,
+    hasher: Arc<dyn Hasher>,
+    /// Upper bound on the commits one history traversal may load.
+    max_history_depth: Option<usize>,
+    /// Whether commit writes are logged under `wal:` before being applied.
+    write_ahead_log: bool,
+    max_message_len: usize,
+    /// Values above this many bytes are stored once under `blob:<hash>` and
+    /// referenced from the commit; `None` keeps every value inline.
+    blob_threshold: Option<usize>,
+    clock: Arc<dyn Clock>,
+    /// Whether new commits end in a checksum of their payload.
+    integrity_trailer: bool,
+    codec: Arc<dyn Codec>,
 }
 
-impl CommitStorage {
-    pub fn open(path: &str) -> Result<Self> {
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        let db = DB::open(&opts, path)?;
-        Ok(Self {
-            db: Arc::new(db)
-        })
+impl CommitStorage {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::open_with_options(path, StorageConfig::default())
+    }
+
+    /// Opens the database with room for `capacity` decoded commits in memory.
+    /// A capacity of zero disables the cache.
+    pub fn open_with_cache(path: &str, capacity: usize) -> Result<Self> {
+        Self::open_configured(path, &StorageConfig::default(), capacity)
+    }
+
+    /// Opens the database with RocksDB tuned according to `cfg`.
+    pub fn open_with_options(path: &str, cfg: StorageConfig) -> Result<Self> {
+        Self::open_configured(path, &cfg, DEFAULT_COMMIT_CACHE_CAPACITY)
+    }
+
+    fn open_configured(path: &str, cfg: &StorageConfig, capacity: usize) -> Result<Self> {
+        Self::from_db(DB::open(&cfg.to_options(), path)?, cfg, capacity)
+    }
+
+    /// Like `open`, but if another process holds the database lock, tries
+    /// again up to `attempts` times in total, doubling the wait from `backoff`
+    /// each time. Other open errors are returned immediately.
+    pub fn open_with_retry(path: &str, attempts: u32, backoff: Duration) -> Result<Self> {
+        Self::open_with_options_and_retry(path, StorageConfig::default(), attempts, backoff)
+    }
+
+    /// `open_with_retry` with the settings of `cfg`, as `open_with_options` applies them.
+    pub fn open_with_options_and_retry(path: &str, cfg: StorageConfig, attempts: u32, backoff: Duration) -> Result<Self> {
+        let mut delay = backoff;
+        let mut attempt = 1;
+        loop {
+            match DB::open(&cfg.to_options(), path) {
+                Ok(db) => return Self::from_db(db, &cfg, DEFAULT_COMMIT_CACHE_CAPACITY),
+                Err(e) if !is_lock_error(&e) => return Err(e.into()),
+                Err(e) if attempt >= attempts => {
+                    return Err(GitDBError::IoError(format!(
+                        "Database at {} is still locked by another process after {} attempts: {}", path, attempt, e
+                    )));
+                }
+                Err(_) => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn from_db(db: DB, cfg: &StorageConfig, capacity: usize) -> Result<Self> {
+        Self::with_backend_and_options(Arc::new(db), cfg, capacity)
+    }
+
+    /// A repository held entirely in memory; nothing touches disk.
+    pub fn open_in_memory() -> Self {
+        Self::assemble(Arc::new(InMemoryBackend::new()), DEFAULT_COMMIT_CACHE_CAPACITY, ChecksumAlgorithm::default())
+    }
+
+    pub fn with_backend(db: Arc<dyn Backend>, capacity: usize) -> Result<Self> {
+        Self::with_backend_and_options(db, &StorageConfig::default(), capacity)
+    }
+
+    /// Like `with_backend`, applying the settings of `cfg` that are not
+    /// RocksDB tuning.
+    pub fn with_backend_and_options(db: Arc<dyn Backend>, cfg: &StorageConfig, capacity: usize) -> Result<Self> {
+        let algorithm = settle_checksum_algorithm(&*db, cfg.checksum)?;
+        let codec = settle_codec(&*db, cfg.codec.clone())?;
+        recover_wal(&*db, &*codec)?;
+        clear_abandoned_builds(&*db)?;
+        let integrity_trailer = settle_integrity_trailer(&*db, cfg.integrity_trailer)?;
+        let max_message_len = cfg.max_message_len.unwrap_or(DEFAULT_MAX_MESSAGE_LEN);
+        Ok(Self { integrity_trailer, codec, max_message_len, ..Self::assemble(db, capacity, algorithm) })
+    }
+
+    fn assemble(db: Arc<dyn Backend>, capacity: usize, algorithm: ChecksumAlgorithm) -> Self {
+        Self {
+            db,
+            compression_level: None,
+            commit_cache: NonZeroUsize::new(capacity).map(|c| Mutex::new(LruCache::new(c))),
+            head_lock: Mutex::new(()),
+            staging_lock: Mutex::new(()),
+            hasher: algorithm.hasher(),
+            max_history_depth: None,
+            write_ahead_log: false,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            blob_threshold: Some(DEFAULT_BLOB_THRESHOLD),
+            clock: Arc::new(SystemClock),
+            integrity_trailer: true,
+            codec: Arc::new(BincodeCodec),
+        }
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()
+    }
+
+    /// Compacts the whole database, e.g. after `gc` or `prune_before` freed space.
+    pub fn compact(&self) -> Result<()> {
+        self.db.compact()
+    }
+
+    pub fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.hasher.algorithm()
+    }
+
+    /// The lock guards no data, so a panic while holding it leaves nothing to repair.
+    fn lock_head(&self) -> MutexGuard<'_, ()> {
+        self.head_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_staging(&self) -> MutexGuard<'_, ()> {
+        self.staging_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Makes history traversals fail once they have loaded more than `depth`
+    /// commits instead of walking arbitrarily far.
+    pub fn with_max_history_depth(mut self, depth: usize) -> Self {
+        self.max_history_depth = Some(depth);
+        self
+    }
+
+    /// Logs each commit write under `wal:` before applying it, so a write cut
+    /// short is replayed the next time the repository is opened.
+    pub fn with_write_ahead_log(mut self, enabled: bool) -> Self {
+        self.write_ahead_log = enabled;
+        self
+    }
+
+    /// Applies a commit write, going through the log if it is enabled. The
+    /// log entry is cleared by the same atomic write it describes.
+    fn write_logged(&self, mut batch: WriteBatch) -> Result<()> {
+        if !self.write_ahead_log {
+            return self.db.write(batch);
+        }
+        let entry = WalEntry { head: self.get_head()?, batch };
+        self.db.put(WAL_KEY, &self.codec.encode_wal(&entry)?)?;
+        batch = entry.batch;
+        batch.delete(WAL_KEY);
+        self.db.write(batch).inspect_err(|_| {
+            // Best effort: a stale entry would otherwise replay on the next open.
+            let _ = self.db.delete(WAL_KEY);
+        })
+    }
+
+    fn check_history_depth(&self, loaded: usize) -> Result<()> {
+        match self.max_history_depth {
+            Some(max) if loaded > max => Err(GitDBError::CorruptData(format!(
+                "History exceeds the maximum depth of {} commits", max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Encodes everything with `codec` and records it in the repository. A
+    /// repository can only switch codecs while it has no commits. To reopen
+    /// one written with a custom codec, set `StorageConfig::codec`.
+    pub fn with_codec(mut self, codec: Arc<dyn Codec>) -> Result<Self> {
+        self.codec = settle_codec(&*self.db, Some(codec))?;
+        self.clear_commit_cache();
+        Ok(self)
+    }
+
+    /// Takes commit and reflog timestamps from `clock` instead of the system time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the size above which new values are stored out of line, or keeps
+    /// them all inline with `None`.
+    pub fn with_blob_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.blob_threshold = threshold;
+        self
+    }
+
+    /// Compresses commits written from now on with zstd at `level`.
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+    
+    pub fn get_commit_by_hash(&self, hash: &[u8; 32]) -> Result<Commit> {
+        if let Some(cache) = &self.commit_cache {
+            if let Some(commit) = cache.lock().ok().and_then(|mut c| c.get(hash).cloned()) {
+                return Ok((*commit).clone());
+            }
+        }
+
+        let raw = self.db.get(hash)?
+            .ok_or_else(|| GitDBError::InvalidInput("Commit not found".into()))?;
+        let mut commit = decode_commit(&*self.hasher, &*self.codec, hash, &raw)?;
+        resolve_blobs(&*self.db, &mut commit)?;
+
+        if let Some(cache) = &self.commit_cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.put(*hash, Arc::new(commit.clone()));
+            }
+        }
+        Ok(commit)
+    }
+
+    /// Name of the codec new commits are encoded with.
+    pub(crate) fn codec_name(&self) -> &'static str {
+        self.codec.name()
+    }
+
+    /// Whether new commits end in a checksum of their payload.
+    pub(crate) fn integrity_trailer(&self) -> bool {
+        self.integrity_trailer
+    }
+
+    pub(crate) fn clear_commit_cache(&self) {
+        if let Some(cache) = &self.commit_cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.clear();
+            }
+        }
+    }
+
+    fn evict_cached_commit(&self, hash: &[u8; 32]) {
+        if let Some(cache) = &self.commit_cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.pop(hash);
+            }
+        }
+    }
+
+    pub fn get_head(&self) -> Result<Option<[u8; 32]>> {
+        match self.db.get(b"HEAD")? {
+            Some(raw) if raw.len() == 32 => {
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(&raw);
+                Ok(Some(bytes))
+            }
+            Some(_) => Err(GitDBError::InvalidInput("HEAD contains invalid data".into())),
+            None => Ok(None),
+        }
+    }
+
+    /// Fails if HEAD points at a commit that is not stored.
+    pub fn validate_head(&self) -> Result<()> {
+        if let Some(head) = self.get_head()? {
+            if !self.commit_exists(head)? {
+                return Err(GitDBError::CorruptData(format!(
+                    "HEAD points to missing commit {}", hex::encode(head)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Repoints a dangling HEAD, and the branch it is on, at `fallback`. With
+    /// no fallback HEAD is cleared and its branch left unborn, so the next
+    /// commit starts a new root. Returns `false` if HEAD was not dangling.
+    pub fn repair_head(&self, fallback: Option<[u8; 32]>) -> Result<bool> {
+        let _guard = self.lock_head();
+        let Some(head) = self.get_head()? else {
+            return Ok(false);
+        };
+        if self.commit_exists(head)? {
+            return Ok(false);
+        }
+
+        let mut batch = WriteBatch::default();
+        match fallback {
+            Some(fallback) => {
+                self.ensure_commit_exists(&fallback, "fallback")?;
+                self.stage_head(&mut batch, &fallback, "repair: HEAD was dangling")?;
+            }
+            None => {
+                batch.delete(b"HEAD");
+                if let Some(branch) = self.current_branch()? {
+                    if self.get_branch(&branch)? == Some(head) {
+                        batch.delete(branch_key(&branch).as_bytes());
+                    }
+                }
+            }
+        }
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    /// Reads the stored value of a single row, in the same encoding as `Change` values.
+    pub fn get_row(&self, table: &str, id: &str) -> Result<Option<Vec<u8>>> {
+        self.db.get(row_key(table, id).as_bytes())?
+            .map(|raw| self.row_value(&raw))
+            .transpose()
+    }
+
+    /// A stored row in the encoding of `Change` values, whatever the codec.
+    fn row_value(&self, raw: &[u8]) -> Result<Vec<u8>> {
+        self.codec.to_change_value(strip_checksum(raw))
+    }
+
+    /// Reads many rows of `table` in one lookup. Every requested id is in the
+    /// result, mapped to `None` if the row does not exist.
+    pub fn get_rows(&self, table: &str, ids: &[&str]) -> Result<HashMap<String, Option<Vec<u8>>>> {
+        let keys: Vec<Vec<u8>> = ids.iter().map(|id| row_key(table, id).into_bytes()).collect();
+        let values = self.db.multi_get(&keys)?;
+        ids.iter()
+            .zip(values)
+            .map(|(id, value)| Ok((id.to_string(), value.map(|raw| self.row_value(&raw)).transpose()?)))
+            .collect()
+    }
+
+    /// Reads every stored row of `table`, keyed by row id.
+    pub fn get_table(&self, table: &str) -> Result<HashMap<String, Vec<u8>>> {
+        self.iter_table(table).collect()
+    }
+
+    /// Lazily yields `(id, value)` for each stored row of `table` in key order.
+    pub fn iter_table(&self, table: &str) -> impl Iterator<Item = Result<(String, Vec<u8>)>> + '_ {
+        let prefix = format!("{}:", table);
+        self.db.prefix_iterator(prefix.as_bytes())
+            .map_while(move |item| match item {
+                Ok((key, value)) => {
+                    let id = key.strip_prefix(prefix.as_bytes())?;
+                    Some(self.row_value(&value).map(|value| (String::from_utf8_lossy(id).into_owned(), value)))
+                }
+                Err(e) => Some(Err(e)),
+            })
+    }
+
+    /// Name of the branch HEAD is attached to, or `None` when HEAD is detached.
+    pub fn current_branch(&self) -> Result<Option<String>> {
+        match self.db.get(HEAD_BRANCH_KEY)? {
+            Some(raw) => String::from_utf8(raw)
+                .map(Some)
+                .map_err(|_| GitDBError::CorruptData("HEAD branch name is not valid UTF-8".into())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_branch(&self, name: &str) -> Result<Option<[u8; 32]>> {
+        match self.db.get(branch_key(name).as_bytes())? {
+            Some(raw) => {
+                let tip: [u8; 32] = raw.try_into().map_err(|_| {
+                    GitDBError::CorruptData(format!("Branch '{}' contains invalid data", name))
+                })?;
+                Ok(Some(tip))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn create_branch(&self, name: &str, at: [u8; 32]) -> Result<()> {
+        validate_ref_name("Branch", name)?;
+        if self.get_branch(name)?.is_some() {
+            return Err(GitDBError::InvalidInput(format!("Branch '{}' already exists", name)));
+        }
+        self.get_commit_by_hash(&at)?;
+
+        self.db.put(branch_key(name).as_bytes(), &at)?;
+        Ok(())
+    }
+
+    pub fn list_branches(&self) -> Result<Vec<(String, [u8; 32])>> {
+        let mut branches = Vec::new();
+        for item in self.db.prefix_iterator(BRANCH_PREFIX.as_bytes()) {
+            let (key, value) = item?;
+            let Some(name) = key.strip_prefix(BRANCH_PREFIX.as_bytes()) else {
+                break;
+            };
+            let name = String::from_utf8_lossy(name).into_owned();
+            let tip: [u8; 32] = value.as_ref().try_into().map_err(|_| {
+                GitDBError::CorruptData(format!("Branch '{}' contains invalid data", name))
+            })?;
+            branches.push((name, tip));
+        }
+        branches.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(branches)
+    }
+
+    /// Removes a branch ref. The branch HEAD is attached to cannot be deleted.
+    ///
+    /// Returns the branch's old tip if no other ref reaches it any more, so
+    /// the caller can warn that `gc` would remove it.
+    pub fn delete_branch(&self, name: &str) -> Result<Option<[u8; 32]>> {
+        let tip = self.get_branch(name)?
+            .ok_or_else(|| GitDBError::InvalidInput(format!("Branch '{}' does not exist", name)))?;
+        if self.current_branch()?.as_deref() == Some(name) {
+            return Err(GitDBError::InvalidInput("cannot delete current branch".into()));
+        }
+
+        let still_reachable = self.reached_from(self.ref_tips(Some(name))?, tip)?;
+        self.db.delete(branch_key(name).as_bytes())?;
+        Ok((!still_reachable).then_some(tip))
+    }
+
+    /// Whether `target` is one of `tips` or an ancestor of any of them. The
+    /// walk is shared between tips and never descends below `target`'s
+    /// timestamp, since parents are always older than their children.
+    fn reached_from(&self, tips: Vec<[u8; 32]>, target: [u8; 32]) -> Result<bool> {
+        let floor = self.get_commit_by_hash(&target)?.timestamp;
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from(tips);
+        while let Some(hash) = queue.pop_front() {
+            if hash == target {
+                return Ok(true);
+            }
+            if !seen.insert(hash) {
+                continue;
+            }
+            let commit = self.get_commit_by_hash(&hash)?;
+            if commit.timestamp > floor {
+                queue.extend(commit.parents);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Creates an immutable tag pointing at `commit`.
+    pub fn create_tag(&self, name: &str, commit: [u8; 32]) -> Result<()> {
+        validate_ref_name("Tag", name)?;
+        if self.db.get(tag_key(name).as_bytes())?.is_some() {
+            return Err(GitDBError::InvalidInput(format!("Tag '{}' already exists", name)));
+        }
+        self.get_commit_by_hash(&commit)?;
+
+        self.db.put(tag_key(name).as_bytes(), &commit)?;
+        Ok(())
+    }
+
+    pub fn resolve_tag(&self, name: &str) -> Result<[u8; 32]> {
+        let raw = self.db.get(tag_key(name).as_bytes())?
+            .ok_or_else(|| GitDBError::InvalidInput(format!("Tag '{}' does not exist", name)))?;
+        raw.try_into().map_err(|_| {
+            GitDBError::CorruptData(format!("Tag '{}' contains invalid data", name))
+        })
+    }
+
+    /// Creates an independent line of history named `name`, starting at `at`
+    /// or empty. Workspaces share commit storage with the main repository but
+    /// have their own head and their own materialized rows.
+    pub fn create_workspace(&self, name: &str, at: Option<[u8; 32]>) -> Result<()> {
+        validate_ref_name("Workspace", name)?;
+        if self.db.get(workspace_key(name).as_bytes())?.is_some() {
+            return Err(GitDBError::InvalidInput(format!("Workspace '{}' already exists", name)));
+        }
+        if let Some(at) = at {
+            self.ensure_commit_exists(&at, "workspace start")?;
+        }
+
+        // An empty value marks a workspace that has no commits yet.
+        let head: &[u8] = at.as_ref().map_or(&[], |hash| hash.as_slice());
+        self.db.put(workspace_key(name).as_bytes(), head)?;
+        Ok(())
+    }
+
+    /// Head of workspace `name`, or `None` if it has no commits yet.
+    pub fn workspace_head(&self, name: &str) -> Result<Option<[u8; 32]>> {
+        let raw = self.db.get(workspace_key(name).as_bytes())?
+            .ok_or_else(|| GitDBError::InvalidInput(format!("Workspace '{}' does not exist", name)))?;
+        if raw.is_empty() {
+            return Ok(None);
+        }
+        raw.try_into().map(Some).map_err(|_| {
+            GitDBError::CorruptData(format!("Workspace '{}' contains invalid data", name))
+        })
+    }
+
+    /// Workspaces that have at least one commit, with their heads.
+    pub fn list_workspaces(&self) -> Result<Vec<(String, [u8; 32])>> {
+        let mut workspaces = Vec::new();
+        for item in self.db.prefix_iterator(WORKSPACE_PREFIX.as_bytes()) {
+            let (key, _) = item?;
+            let name = String::from_utf8_lossy(&key[WORKSPACE_PREFIX.len()..]).into_owned();
+            if let Some(head) = self.workspace_head(&name)? {
+                workspaces.push((name, head));
+            }
+        }
+        Ok(workspaces)
+    }
+
+    /// Commits `changes` on top of workspace `name`'s head and advances it.
+    /// HEAD and the main rows are left alone.
+    pub fn commit_in_workspace(&self, name: &str, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
+        if changes.is_empty() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+
+        let _guard = self.lock_head();
+        let parent = self.workspace_head(name)?;
+        let changes = self.effective_changes(parent, changes)?;
+
+        let mut commit = self.build_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes)?;
+        for (table, table_hash) in commit.tree.iter_mut() {
+            *table_hash = self.calculate_table_hash(&workspace_table(name, table))?;
+        }
+
+        let mut batch = WriteBatch::default();
+        let hash = self.stage_built_commit(&mut batch, &commit)?;
+        batch.put(workspace_key(name).as_bytes(), hash);
+        self.db.write(batch)?;
+        Ok(hash)
+    }
+
+    /// Rewrites workspace `name`'s rows to match its head.
+    pub fn checkout_workspace(&self, name: &str) -> Result<()> {
+        let engine = self.replay_state(self.workspace_head(name)?)?;
+
+        let mut batch = WriteBatch::default();
+        let prefix = format!("{}{}{}", WORKSPACE_ROW_PREFIX, name, KEY_DELIMITER);
+        for item in self.db.prefix_iterator(prefix.as_bytes()) {
+            let (key, _) = item?;
+            batch.delete(key);
+        }
+        for (table, rows) in &engine.state {
+            let table = workspace_table(name, table);
+            for (id, value) in rows {
+                batch.put(row_key(&table, id).as_bytes(), self.codec.encode_value(value)?);
+            }
+        }
+        self.db.write(batch)
+    }
+
+    pub fn list_tags(&self) -> Result<Vec<String>> {
+        let mut tags = Vec::new();
+        for item in self.db.prefix_iterator(TAG_PREFIX.as_bytes()) {
+            let (key, _) = item?;
+            let Some(name) = key.strip_prefix(TAG_PREFIX.as_bytes()) else {
+                break;
+            };
+            tags.push(String::from_utf8_lossy(name).into_owned());
+        }
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// Resolves a user-facing reference to a commit hash. Tries, in order, a
+    /// full hex hash of an existing commit, a branch, a tag and `HEAD`.
+    pub fn resolve_ref(&self, reference: &str) -> Result<[u8; 32]> {
+        if let Ok(hash) = parse_hash(reference) {
+            if self.db.get(&hash)?.is_some() {
+                return Ok(hash);
+            }
+        }
+        if let Some(tip) = self.get_branch(reference)? {
+            return Ok(tip);
+        }
+        if self.db.get(tag_key(reference).as_bytes())?.is_some() {
+            return self.resolve_tag(reference);
+        }
+        if reference == "HEAD" {
+            if let Some(head) = self.get_head()? {
+                return Ok(head);
+            }
+        }
+        Err(GitDBError::InvalidInput(format!("Unknown reference '{}'", reference)))
+    }
+
+    /// Attaches HEAD to `name` so later commits advance that branch.
+    pub fn set_head_to_branch(&self, name: &str) -> Result<()> {
+        let tip = self.get_branch(name)?
+            .ok_or_else(|| GitDBError::InvalidInput(format!("Branch '{}' does not exist", name)))?;
+
+        let _guard = self.lock_head();
+        let mut batch = WriteBatch::default();
+        self.stage_reflog(&mut batch, &tip, &format!("checkout: {}", name), None)?;
+        batch.put(b"HEAD", tip);
+        batch.put(HEAD_BRANCH_KEY, name.as_bytes());
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Points HEAD directly at a commit, leaving no current branch.
+    pub fn detach_head(&self, hash: &[u8; 32]) -> Result<()> {
+        self.get_commit_by_hash(hash)?;
+
+        let _guard = self.lock_head();
+        let mut batch = WriteBatch::default();
+        self.stage_reflog(&mut batch, hash, &format!("checkout: {}", hex::encode(hash)), None)?;
+        batch.put(b"HEAD", hash);
+        batch.delete(HEAD_BRANCH_KEY);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    pub fn create_commit(&self, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
+        self.create_commit_with_author(UNKNOWN_AUTHOR, "", message, changes)
+    }
+
+    /// Rejects commits with no changes, or whose changes leave the data at
+    /// HEAD exactly as it was.
+    pub fn create_commit_with_author(&self, author: &str, email: &str, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
+        if changes.is_empty() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+
+        let _guard = self.lock_head();
+        let parent = self.get_head()?;
+        let changes = self.effective_changes(parent, changes)?;
+        self.write_commit(parent.into_iter().collect(), author, email, message, changes)
+    }
+
+    /// Creates a commit on HEAD whose hash is signed with `signing_key`.
+    pub fn create_signed_commit(&self, signing_key: &SigningKey, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
+        if changes.is_empty() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+
+        let _guard = self.lock_head();
+        let parent = self.get_head()?;
+        let changes = self.effective_changes(parent, changes)?;
+
+        let mut commit = self.build_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes)?;
+        let hash = commit_id(&*self.hasher, &*self.codec, &commit)?;
+        commit.signature = Some(signing_key.sign(&hash).to_bytes().to_vec());
+
+        self.write_built_commit(WriteBatch::default(), &commit, &format!("commit (signed): {}", message))
+    }
+
+    /// Creates a commit on HEAD carrying `metadata` alongside its message.
+    pub fn create_commit_with_metadata(&self, message: &str, changes: Vec<Change>, metadata: BTreeMap<String, String>) -> Result<[u8; 32]> {
+        if changes.is_empty() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+
+        let _guard = self.lock_head();
+        let parent = self.get_head()?;
+        let changes = self.effective_changes(parent, changes)?;
+
+        let mut commit = self.build_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes)?;
+        commit.metadata = metadata;
+        self.write_built_commit(WriteBatch::default(), &commit, &format!("commit: {}", message))
+    }
+
+    pub fn get_commit_metadata(&self, hash: [u8; 32]) -> Result<BTreeMap<String, String>> {
+        Ok(self.get_commit_by_hash(&hash)?.metadata)
+    }
+
+    /// Adds `change` to the staging area for a later `commit_staged`.
+    pub fn stage_change(&self, change: Change) -> Result<()> {
+        validate_changes(std::slice::from_ref(&change))?;
+        self.stage_validated(&[change])
+    }
+
+    /// Appends already validated `changes` to the staging area in one write.
+    fn stage_validated(&self, changes: &[Change]) -> Result<()> {
+        let _guard = self.lock_staging();
+        let mut seq = self.next_staging_seq()?;
+
+        let mut batch = WriteBatch::default();
+        for change in changes {
+            batch.put(staging_key(seq).as_bytes(), self.codec.encode_change(change)?);
+            seq += 1;
+        }
+        if let Some(last) = seq.checked_sub(1) {
+            batch.put(STAGING_SEQ_KEY, last.to_le_bytes());
+        }
+        self.db.write(batch)
+    }
+
+    /// The sequence number the next staged change will get.
+    fn next_staging_seq(&self) -> Result<u64> {
+        match self.db.get(STAGING_SEQ_KEY)? {
+            Some(raw) => Ok(u64::from_le_bytes(raw.as_slice().try_into().map_err(|_| {
+                GitDBError::CorruptData("Staging sequence contains invalid data".into())
+            })?) + 1),
+            None => Ok(0),
+        }
+    }
+
+    /// Staged changes, in the order they were staged.
+    pub fn get_staged(&self) -> Result<Vec<Change>> {
+        self.iter_staged().collect()
+    }
+
+    fn iter_staged(&self) -> impl Iterator<Item = Result<Change>> + '_ {
+        self.db.prefix_iterator(STAGING_PREFIX.as_bytes())
+            .map(|item| item.and_then(|(_, value)| self.codec.decode_change(&value)))
+    }
+
+    pub fn unstage_all(&self) -> Result<()> {
+        let _guard = self.lock_staging();
+        let mut batch = WriteBatch::default();
+        self.stage_clear_staging(&mut batch)?;
+        self.db.write(batch)
+    }
+
+    fn stage_clear_staging(&self, batch: &mut WriteBatch) -> Result<()> {
+        for item in self.db.prefix_iterator(STAGING_PREFIX.as_bytes()) {
+            let (key, _) = item?;
+            batch.delete(key);
+        }
+        batch.delete(STAGING_SEQ_KEY);
+        Ok(())
+    }
+
+    /// Commits everything staged on HEAD and empties the staging area in the
+    /// same write. Fails like `create_commit` when nothing is staged.
+    ///
+    /// Staged changes are coalesced as they are read, so only their net
+    /// effect is ever held in memory.
+    pub fn commit_staged(&self, message: &str) -> Result<[u8; 32]> {
+        let _guard = self.lock_head();
+        let _staging = self.lock_staging();
+        if self.iter_staged().next().is_none() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+
+        let parent = self.get_head()?;
+        // Staged changes were validated when they were staged.
+        let mut failure = None;
+        let staged = self.iter_staged().map_while(|item| item.map_err(|e| failure = Some(e)).ok());
+        let changes = self.coalesce_effective(parent, staged);
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        let changes = changes?;
+
+        let commit = self.build_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes)?;
+        let mut batch = WriteBatch::default();
+        self.stage_clear_staging(&mut batch)?;
+        self.write_built_commit(batch, &commit, &format!("commit: {}", message))
+    }
+
+    /// Writes validated changes of `builder` under its own keys, numbered from `seq`.
+    pub(crate) fn stage_build_chunk(&self, builder: u64, seq: u64, changes: &[Change]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (seq, change) in (seq..).zip(changes) {
+            batch.put(build_key(builder, seq).as_bytes(), self.codec.encode_change(change)?);
+        }
+        self.db.write(batch)
+    }
+
+    /// Deletes every change `builder` has written out.
+    pub(crate) fn discard_build(&self, builder: u64) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.stage_clear_build(&mut batch, builder)?;
+        self.db.write(batch)
+    }
+
+    fn stage_clear_build(&self, batch: &mut WriteBatch, builder: u64) -> Result<()> {
+        for item in self.db.prefix_iterator(build_prefix(builder).as_bytes()) {
+            let (key, _) = item?;
+            batch.delete(key);
+        }
+        Ok(())
+    }
+
+    /// Commits on HEAD the changes `builder` wrote out, and deletes them in the
+    /// same write. `tree` holds the hashes of the tables they touch, taken while
+    /// HEAD was at `tree_head`; they are only hashed again if HEAD has moved.
+    pub(crate) fn commit_build(
+        &self,
+        builder: u64,
+        message: &str,
+        mut tree: BTreeMap<String, [u8; 32]>,
+        tree_head: Option<[u8; 32]>,
+    ) -> Result<[u8; 32]> {
+        validate_message(message, self.max_message_len)?;
+        let _guard = self.lock_head();
+        let prefix = build_prefix(builder);
+        if self.db.prefix_iterator(prefix.as_bytes()).next().is_none() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+
+        let parent = self.get_head()?;
+        // The builder validated its changes as they were pushed.
+        let mut failure = None;
+        let built = self.db.prefix_iterator(prefix.as_bytes())
+            .map(|item| item.and_then(|(_, value)| self.codec.decode_change(&value)))
+            .map_while(|item| item.map_err(|e| failure = Some(e)).ok());
+        let changes = self.coalesce_effective(parent, built);
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        let changes = changes?;
+
+        if tree_head != parent {
+            for (table, hash) in &mut tree {
+                *hash = self.calculate_table_hash(table)?;
+            }
+        }
+        // Tables whose changes cancelled out are not part of the commit.
+        let touched: BTreeSet<&str> = changes.iter().map(Change::table).collect();
+        tree.retain(|table, _| touched.contains(table.as_str()));
+
+        let commit = self.assemble_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes, tree)?;
+        let mut batch = WriteBatch::default();
+        self.stage_clear_build(&mut batch, builder)?;
+        self.write_built_commit(batch, &commit, &format!("commit: {}", message))
+    }
+
+    /// Returns `true` only if the commit is signed, its contents still hash to
+    /// `hash`, and the signature verifies under `verifying_key`.
+    pub fn verify_commit_signature(&self, hash: [u8; 32], verifying_key: &VerifyingKey) -> Result<bool> {
+        let commit = self.get_commit_by_hash(&hash)?;
+        let Some(signature) = &commit.signature else {
+            return Ok(false);
+        };
+        if commit_id(&*self.hasher, &*self.codec, &commit)? != hash {
+            return Ok(false);
+        }
+        let Ok(signature_bytes) = <[u8; 64]>::try_from(signature.as_slice()) else {
+            return Ok(false);
+        };
+        Ok(verifying_key.verify(&hash, &Signature::from_bytes(&signature_bytes)).is_ok())
+    }
+
+    /// Coalesces `changes` against the rows at `parent`, failing with
+    /// `NoOpCommit` if what is left would leave the data unchanged.
+    fn effective_changes(&self, parent: Option<[u8; 32]>, changes: Vec<Change>) -> Result<Vec<Change>> {
+        // Malformed changes are reported as such, not as having no effect.
+        validate_changes(&changes)?;
+        self.coalesce_effective(parent, changes)
+    }
+
+    /// `effective_changes` for changes already validated.
+    fn coalesce_effective(&self, parent: Option<[u8; 32]>, changes: impl IntoIterator<Item = Change>) -> Result<Vec<Change>> {
+        let mut engine = self.replay_state(parent)?;
+        let changes = coalesce_changes(changes, |table, id| {
+            engine.state.get(table).is_none_or(|rows| !rows.contains_key(id))
+        });
+        if changes.is_empty() {
+            return Err(GitDBError::NoOpCommit);
+        }
+
+        // Only the touched tables can differ, so only they are kept to compare.
+        let tables: BTreeSet<&str> = changes.iter().map(Change::table).collect();
+        let before = engine.subset(tables.iter().copied());
+        engine.apply_changes(&changes)?;
+        if tables.iter().all(|table| engine.state.get(*table) == before.state.get(*table)) {
+            return Err(GitDBError::NoOpCommit);
+        }
+        Ok(changes)
+    }
+
+    /// Like `create_commit`, but records the commit even if it changes nothing.
+    pub fn create_commit_allow_empty(&self, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
+        let _guard = self.lock_head();
+        let parent = self.get_head()?;
+        self.write_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes)
+    }
+
+    fn write_commit(&self, parents: Vec<[u8; 32]>, author: &str, email: &str, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
+        let commit = self.build_commit(parents, author, email, message, changes)?;
+        self.write_built_commit(WriteBatch::default(), &commit, &format!("commit: {}", message))
+    }
+
+    /// Appends `commit` and the HEAD move to `batch` and writes it all at once,
+    /// so whatever the caller already staged lands together with the commit.
+    fn write_built_commit(&self, mut batch: WriteBatch, commit: &Commit, reason: &str) -> Result<[u8; 32]> {
+        let hash_bytes = self.stage_built_commit(&mut batch, commit)?;
+        self.stage_head(&mut batch, &hash_bytes, reason)?;
+
+        // Commit object and HEAD move together so a crash cannot leave HEAD stale.
+        self.write_logged(batch)?;
+        Ok(hash_bytes)
+    }
+
+    /// Writes a chain of commits, each parented on the previous one and the
+    /// first on HEAD, in a single batch. Nothing is persisted if any fails.
+    pub fn create_commits_atomic(&self, commits: Vec<(String, Vec<Change>)>) -> Result<Vec<[u8; 32]>> {
+        let _guard = self.lock_head();
+        let mut batch = WriteBatch::default();
+        let mut parent = self.get_head()?;
+        let mut hashes = Vec::with_capacity(commits.len());
+        let mut previous_timestamp = None;
+
+        for (message, changes) in commits {
+            if changes.is_empty() {
+                return Err(GitDBError::InvalidInput("empty commit".into()));
+            }
+            let mut commit = self.build_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", &message, changes)?;
+            follow_staged_parent(&mut commit, previous_timestamp);
+            previous_timestamp = Some(commit.timestamp);
+
+            let hash = self.stage_built_commit(&mut batch, &commit)?;
+            hashes.push(hash);
+            parent = Some(hash);
+        }
+
+        if let Some(tip) = hashes.last() {
+            self.stage_head(&mut batch, tip, &format!("commit (atomic): {} commits", hashes.len()))?;
+            self.write_logged(batch)?;
+        }
+        Ok(hashes)
+    }
+
+    /// Returns the hash `create_commit` would assign to these changes right now,
+    /// without writing anything. Fails where `create_commit` would.
+    pub fn preview_commit_hash(&self, message: &str, changes: &[Change]) -> Result<[u8; 32]> {
+        if changes.is_empty() {
+            return Err(GitDBError::InvalidInput("empty commit".into()));
+        }
+        let parent = self.get_head()?;
+        let changes = self.effective_changes(parent, changes.to_vec())?;
+        let commit = self.build_commit(parent.into_iter().collect(), UNKNOWN_AUTHOR, "", message, changes)?;
+        commit_id(&*self.hasher, &*self.codec, &commit)
+    }
+
+    fn build_commit(&self, parents: Vec<[u8; 32]>, author: &str, email: &str, message: &str, changes: Vec<Change>) -> Result<Commit> {
+        validate_message(message, self.max_message_len)?;
+        validate_changes(&changes)?;
+        // Later changes to a row would otherwise replay over earlier ones.
+        // Without the parent's rows at hand no row is known to be absent.
+        let changes = coalesce_changes(changes, |_, _| false);
+
+        // Each touched table is hashed once, however many changes touch it.
+        let mut tree = BTreeMap::new();
+        for table in changes.iter().map(Change::table) {
+            if !tree.contains_key(table) {
+                tree.insert(table.to_string(), self.calculate_table_hash(table)?);
+            }
+        }
+        self.assemble_commit(parents, author, email, message, changes, tree)
+    }
+
+    /// A commit of coalesced `changes` with `tree` as its table hashes.
+    fn assemble_commit(
+        &self,
+        parents: Vec<[u8; 32]>,
+        author: &str,
+        email: &str,
+        message: &str,
+        changes: Vec<Change>,
+        tree: BTreeMap<String, [u8; 32]>,
+    ) -> Result<Commit> {
+        let timestamp = self.next_timestamp(&parents)?;
+        Ok(Commit {
+            parents,
+            message: message.to_string(),
+            timestamp,
+            changes,
+            tree,
+            author: author.to_string(),
+            email: email.to_string(),
+            signature: None,
+            metadata: BTreeMap::new(),
+        })
+    }
+
+    /// The current time in seconds, bumped past every stored parent's timestamp
+    /// so a clock that moved backwards cannot make a child look older than its
+    /// parent. Parents still sitting in an unwritten batch are skipped; see
+    /// `follow_staged_parent`.
+    fn next_timestamp(&self, parents: &[[u8; 32]]) -> Result<u64> {
+        let mut timestamp = self.clock.now()?;
+        for parent in parents {
+            if self.db.get(parent)?.is_none() {
+                continue;
+            }
+            let parent_timestamp = self.get_commit_by_hash(parent)?.timestamp;
+            if timestamp <= parent_timestamp {
+                timestamp = parent_timestamp + 1;
+            }
+        }
+        Ok(timestamp)
+    }
+
+    /// Checksums a commit and adds it to `batch` without touching HEAD.
+    fn stage_built_commit(&self, batch: &mut WriteBatch, commit: &Commit) -> Result<[u8; 32]> {
+        // The id covers the full values, so where they are stored never
+        // changes a commit's hash.
+        let hash_bytes = commit_id(&*self.hasher, &*self.codec, commit)?;
+        self.stage_commit_at(batch, hash_bytes, commit)?;
+        Ok(hash_bytes)
+    }
+
+    /// Encodes `commit` and adds it to `batch` under `key`, whatever its id.
+    fn stage_commit_at(&self, batch: &mut WriteBatch, key: [u8; 32], commit: &Commit) -> Result<()> {
+        let stored = self.stage_blobs(batch, commit);
+        let serialized = self.codec.encode(&stored)?;
+
+        let test_deserialize = self.codec.decode(&serialized)?;
+        if test_deserialize.message != commit.message {
+            return Err(GitDBError::CorruptData("Serialization roundtrip failed".into()));
+        }
+
+        let mut protected_value = encode_payload(&serialized, self.compression_level)?;
+        if self.integrity_trailer {
+            let checksum = self.hasher.hash(&protected_value);
+            protected_value.extend_from_slice(&checksum);
+        } else {
+            protected_value[0] |= FORMAT_UNCHECKED;
+        }
+
+        batch.put(key, &protected_value);
+        Ok(())
+    }
+
+    /// Moves values over the blob threshold into `batch` under their hash and
+    /// returns the commit as it should be stored, with references in their place.
+    fn stage_blobs<'a>(&self, batch: &mut WriteBatch, commit: &'a Commit) -> Cow<'a, Commit> {
+        let Some(threshold) = self.blob_threshold else {
+            return Cow::Borrowed(commit);
+        };
+        let is_large = |change: &Change| matches!(
+            change,
+            Change::Insert { value, .. } | Change::Update { value, .. } if value.len() > threshold
+        );
+        if !commit.changes.iter().any(is_large) {
+            return Cow::Borrowed(commit);
+        }
+
+        let mut stored = commit.clone();
+        for change in &mut stored.changes {
+            if let Change::Insert { value, .. } | Change::Update { value, .. } = change {
+                if value.len() > threshold {
+                    let hash = self.hasher.hash(value);
+                    batch.put(blob_key(&hash).as_bytes(), &*value);
+                    *value = [BLOB_PREFIX.as_bytes(), &hash].concat();
+                }
+            }
+        }
+        Cow::Owned(stored)
+    }
+
+    /// True when `base` is an ancestor of `target` (or the same commit), so
+    /// merging `target` into `base` only needs to move the ref forward.
+    pub fn can_fast_forward(&self, base: [u8; 32], target: [u8; 32]) -> Result<bool> {
+        self.ensure_commit_exists(&base, "base")?;
+        self.is_ancestor(base, target)
+    }
+
+    /// Whether `ancestor` is reachable from `descendant` through parent links.
+    /// A commit counts as its own ancestor.
+    pub fn is_ancestor(&self, ancestor: [u8; 32], descendant: [u8; 32]) -> Result<bool> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([descendant]);
+        while let Some(hash) = queue.pop_front() {
+            if hash == ancestor {
+                return Ok(true);
+            }
+            if seen.insert(hash) {
+                self.check_history_depth(seen.len())?;
+                queue.extend(self.get_commit_by_hash(&hash)?.parents);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Merges `theirs` into `ours` and records a merge commit with both as parents.
+    ///
+    /// If `ours` is behind `theirs`, HEAD is fast-forwarded to `theirs` instead
+    /// and no commit is written; if `theirs` is already part of `ours`, nothing
+    /// happens. Either way the resulting tip is returned.
+    pub fn merge_commits(&self, ours: [u8; 32], theirs: [u8; 32], message: &str) -> Result<[u8; 32]> {
+        if let Some(tip) = self.try_fast_forward(ours, theirs)? {
+            return Ok(tip);
+        }
+
+        let base = self.find_common_ancestor(ours, theirs)?;
+
+        let base_engine = self.replay_state(base)?;
+        let ours_engine = self.replay_state(Some(ours))?;
+        let theirs_engine = self.replay_state(Some(theirs))?;
+
+        let (_, changes) = three_way_merge(&base_engine, &ours_engine, &theirs_engine)?;
+        let _guard = self.lock_head();
+        self.write_commit(vec![ours, theirs], UNKNOWN_AUTHOR, "", message, changes)
+    }
+
+    /// Like `merge_commits`, but rows both sides changed differently are not
+    /// joined automatically: `resolver` picks their final value (encoded like
+    /// `Change` values). Without a resolver, any such row fails the merge with
+    /// a conflict error naming every conflicting row.
+    pub fn merge_commits_with(
+        &self,
+        ours: [u8; 32],
+        theirs: [u8; 32],
+        message: &str,
+        resolver: Option<&ConflictResolver>,
+    ) -> Result<[u8; 32]> {
+        if let Some(tip) = self.try_fast_forward(ours, theirs)? {
+            return Ok(tip);
+        }
+
+        let base = self.find_common_ancestor(ours, theirs)?;
+
+        let base_engine = self.replay_state(base)?;
+        let ours_engine = self.replay_state(Some(ours))?;
+        let theirs_engine = self.replay_state(Some(theirs))?;
+
+        let conflicts = find_conflicts(&base_engine, &ours_engine, &theirs_engine)?;
+        let (_, mut changes) = three_way_merge(&base_engine, &ours_engine, &theirs_engine)?;
+        if !conflicts.is_empty() {
+            let Some(resolver) = resolver else {
+                let rows: Vec<String> = conflicts.iter()
+                    .map(|c| format!("{}:{}", c.table, c.id))
+                    .collect();
+                return Err(GitDBError::Conflict(format!("Merge conflicts on {}", rows.join(", "))));
+            };
+
+            for conflict in &conflicts {
+                changes.retain(|c| !(c.table() == conflict.table && c.id() == conflict.id));
+                let resolved = resolver(conflict);
+                if conflict.ours.as_ref() == Some(&resolved) {
+                    continue;
+                }
+                let (table, id) = (conflict.table.clone(), conflict.id.clone());
+                changes.push(match conflict.ours {
+                    Some(_) => Change::Update { table, id, value: resolved, clock: None },
+                    None => Change::Insert { table, id, value: resolved },
+                });
+            }
+        }
+
+        let _guard = self.lock_head();
+        self.write_commit(vec![ours, theirs], UNKNOWN_AUTHOR, "", message, changes)
+    }
+
+    /// Handles the merges that need no merge commit: returns `ours` if it
+    /// already contains `theirs`, or moves HEAD to `theirs` if `ours` is behind
+    /// it. Fast-forwarding fails with `Conflict` unless HEAD is at `ours`.
+    fn try_fast_forward(&self, ours: [u8; 32], theirs: [u8; 32]) -> Result<Option<[u8; 32]>> {
+        self.ensure_commit_exists(&ours, "ours")?;
+        self.ensure_commit_exists(&theirs, "theirs")?;
+        if self.can_fast_forward(theirs, ours)? {
+            return Ok(Some(ours));
+        }
+
+        let _guard = self.lock_head();
+        if !self.can_fast_forward(ours, theirs)? {
+            return Ok(None);
+        }
+        if self.get_head()? != Some(ours) {
+            return Err(GitDBError::Conflict(format!(
+                "Cannot fast-forward: HEAD is not at {}", hex::encode(ours)
+            )));
+        }
+        let mut batch = WriteBatch::default();
+        self.stage_head(&mut batch, &theirs, &format!("merge {}: fast-forward", hex::encode(theirs)))?;
+        self.db.write(batch)?;
+        Ok(Some(theirs))
+    }
+
+    /// Moves every row of `old` to `new` in a single commit on HEAD. Fails if
+    /// `old` does not exist or `new` already holds rows.
+    pub fn rename_table(&self, old: &str, new: &str, message: &str) -> Result<[u8; 32]> {
+        let state = self.replay_state(self.get_head()?)?;
+        let Some(rows) = state.state.get(old) else {
+            return Err(GitDBError::InvalidInput(format!("Table '{}' does not exist", old)));
+        };
+        if state.state.get(new).is_some_and(|rows| !rows.is_empty()) {
+            return Err(GitDBError::Conflict(format!("Table '{}' already has rows", new)));
+        }
+
+        let ids: BTreeSet<&String> = rows.keys().collect();
+        let mut changes = vec![Change::schema_add(new)];
+        for id in &ids {
+            changes.push(Change::insert_value(new, *id, &rows[*id])?);
+        }
+        for id in &ids {
+            changes.push(Change::delete(old, *id));
+        }
+        changes.push(Change::schema_drop(old));
+
+        self.create_commit(message, changes)
+    }
+
+    /// Re-applies a single commit's changes on top of HEAD as a new commit.
+    pub fn cherry_pick(&self, commit_hash: [u8; 32]) -> Result<[u8; 32]> {
+        self.ensure_commit_exists(&commit_hash, "cherry-picked")?;
+        let picked = self.get_commit_by_hash(&commit_hash)?;
+        let head_engine = self.replay_state(self.get_head()?)?;
+        ensure_applicable(&head_engine, &picked.changes, "cherry-pick", &commit_hash)?;
+
+        let message = format!("Cherry-pick {}", hex::encode(commit_hash));
+        self.create_commit_with_author(&picked.author, &picked.email, &message, picked.changes)
+    }
+
+    /// Replays the commits unique to `branch` on top of `onto` as fresh
+    /// commits and returns the new tip. HEAD and branch refs are left alone;
+    /// nothing is written if any commit conflicts.
+    pub fn rebase(&self, branch: [u8; 32], onto: [u8; 32]) -> Result<[u8; 32]> {
+        let onto_history = self.ancestors(onto)?;
+        let to_replay: Vec<CommitRecord> = self.load_commit_records(Some(branch))?.into_iter()
+            .rev()
+            .filter(|record| !onto_history.contains(&record.hash))
+            .collect();
+
+        let mut engine = self.replay_state(Some(onto))?;
+        let mut batch = WriteBatch::default();
+        let mut tip = onto;
+        let mut previous_timestamp = None;
+        for record in to_replay {
+            let original = record.commit;
+            ensure_applicable(&engine, &original.changes, "rebase", &record.hash)?;
+            engine.apply_changes(&original.changes)?;
+
+            let mut commit = self.build_commit(vec![tip], &original.author, &original.email, &original.message, original.changes)?;
+            follow_staged_parent(&mut commit, previous_timestamp);
+            previous_timestamp = Some(commit.timestamp);
+            tip = self.stage_built_commit(&mut batch, &commit)?;
+        }
+
+        self.db.write(batch)?;
+        Ok(tip)
+    }
+
+    /// Commits reachable from `to` but not from `from`, newest first, like
+    /// `git log from..to`.
+    pub fn commits_between(&self, from: [u8; 32], to: [u8; 32]) -> Result<Vec<CommitRecord>> {
+        let excluded = self.ancestors(from)?;
+        Ok(self.load_commit_records(Some(to))?.into_iter()
+            .filter(|record| !excluded.contains(&record.hash))
+            .collect())
+    }
+
+    /// Replaces the commits after `from` up to and including `to` with a single
+    /// commit whose parent is `from` and whose changes are their net effect.
+    /// `to` must be HEAD, which then moves to the new commit. Fails with
+    /// `NoOpCommit` if the range has no net effect.
+    pub fn squash(&self, from: [u8; 32], to: [u8; 32], message: &str) -> Result<[u8; 32]> {
+        let _guard = self.lock_head();
+        if self.get_head()? != Some(to) {
+            return Err(GitDBError::InvalidInput(format!(
+                "Cannot squash up to {}: it is not HEAD", hex::encode(to)
+            )));
+        }
+        let before_range = self.ancestors(from)?;
+        if !self.ancestors(to)?.contains(&from) {
+            return Err(GitDBError::InvalidInput(format!(
+                "{} is not an ancestor of {}", hex::encode(from), hex::encode(to)
+            )));
+        }
+
+        let range = self.load_commit_records(Some(to))?.into_iter()
+            .rev()
+            .filter(|record| !before_range.contains(&record.hash));
+        let mut author = None;
+        let mut changes = Vec::new();
+        for record in range {
+            author = Some((record.commit.author, record.commit.email));
+            changes.extend(record.commit.changes);
+        }
+
+        let (author, email) = author.unwrap_or_else(|| (UNKNOWN_AUTHOR.to_string(), String::new()));
+        let changes = self.effective_changes(Some(from), changes)?;
+        self.write_commit(vec![from], &author, &email, message, changes)
+    }
+
+    /// Reports rows changed differently on both sides since their common ancestor.
+    pub fn detect_conflicts(&self, ours: [u8; 32], theirs: [u8; 32]) -> Result<Vec<Conflict>> {
+        let base = self.find_common_ancestor(ours, theirs)?;
+
+        let base_engine = self.replay_state(base)?;
+        let ours_engine = self.replay_state(Some(ours))?;
+        let theirs_engine = self.replay_state(Some(theirs))?;
+
+        find_conflicts(&base_engine, &ours_engine, &theirs_engine)
+    }
+
+    /// Finds the nearest commit shared by the histories of `a` and `b`.
+    ///
+    /// If one commit is an ancestor of the other, that commit is returned.
+    /// Returns `None` when the two histories are disjoint.
+    pub fn find_common_ancestor(&self, a: [u8; 32], b: [u8; 32]) -> Result<Option<[u8; 32]>> {
+        let ancestors_of_a = self.ancestors(a)?;
+
+        // Walk back from `b`, stopping at the first shared commit on each path.
+        let mut candidates = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([b]);
+        while let Some(hash) = queue.pop_front() {
+            if !visited.insert(hash) {
+                continue;
+            }
+            if ancestors_of_a.contains(&hash) {
+                candidates.insert(hash);
+                continue;
+            }
+            queue.extend(self.get_commit_by_hash(&hash)?.parents);
+        }
+
+        // Drop candidates that are themselves behind another candidate.
+        let mut redundant = HashSet::new();
+        for candidate in &candidates {
+            for parent in self.get_commit_by_hash(candidate)?.parents {
+                redundant.extend(self.ancestors(parent)?);
+            }
+        }
+
+        let mut nearest = None;
+        for candidate in candidates.difference(&redundant) {
+            let key = (self.get_commit_by_hash(candidate)?.timestamp, *candidate);
+            if nearest.is_none_or(|best| key > best) {
+                nearest = Some(key);
+            }
+        }
+        Ok(nearest.map(|(_, hash)| hash))
+    }
+
+    /// The direct parents of `commit`, in the order they were recorded.
+    pub fn parents_of(&self, commit: [u8; 32]) -> Result<Vec<[u8; 32]>> {
+        self.ensure_commit_exists(&commit, "requested")?;
+        Ok(self.get_commit_by_hash(&commit)?.parents)
+    }
+
+    /// Collects `start` and every commit reachable through any parent.
+    fn ancestors(&self, start: [u8; 32]) -> Result<HashSet<[u8; 32]>> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(hash) = queue.pop_front() {
+            if seen.insert(hash) {
+                self.check_history_depth(seen.len())?;
+                queue.extend(self.get_commit_by_hash(&hash)?.parents);
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Every commit a ref points at: HEAD, branches other than `skip_branch`,
+    /// workspaces and tags.
+    fn ref_tips(&self, skip_branch: Option<&str>) -> Result<Vec<[u8; 32]>> {
+        let mut tips: Vec<[u8; 32]> = self.get_head()?.into_iter().collect();
+        tips.extend(self.list_branches()?.into_iter()
+            .filter(|(branch, _)| Some(branch.as_str()) != skip_branch)
+            .map(|(_, tip)| tip));
+        tips.extend(self.list_workspaces()?.into_iter().map(|(_, tip)| tip));
+        for tag in self.list_tags()? {
+            tips.push(self.resolve_tag(&tag)?);
+        }
+        Ok(tips)
+    }
+
+    /// Queues deletes for every blob that no stored commit outside `dropped`
+    /// references. Blobs written to `batch` afterwards survive the deletes.
+    fn stage_blob_cleanup(&self, batch: &mut WriteBatch, dropped: &HashSet<[u8; 32]>) -> Result<()> {
+        let mut referenced = HashSet::new();
+        for item in self.db.iterator() {
+            let (key, value) = item?;
+            let Ok(hash) = <[u8; 32]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            if dropped.contains(&hash) {
+                continue;
+            }
+            // Decoded without resolving blobs, so references are still visible.
+            let Ok(commit) = decode_commit(&*self.hasher, &*self.codec, &hash, &value) else {
+                continue;
+            };
+            referenced.extend(commit.changes.iter().filter_map(|change| match change {
+                Change::Insert { value, .. } | Change::Update { value, .. } => blob_reference(value),
+                _ => None,
+            }));
+        }
+
+        for item in self.db.prefix_iterator(BLOB_PREFIX.as_bytes()) {
+            let (key, _) = item?;
+            let blob = hex::decode(&key[BLOB_PREFIX.len()..]).ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+            if blob.is_some_and(|blob| !referenced.contains(&blob)) {
+                batch.delete(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes every stored commit that is not reachable from HEAD, a branch,
+    /// a workspace or a tag, and the blobs only those commits referenced.
+    /// Returns the number of commits removed.
+    ///
+    /// Holds the HEAD lock throughout, so a commit written concurrently is
+    /// either reachable when the refs are read or not written yet.
+    pub fn gc(&self) -> Result<usize> {
+        let _guard = self.lock_head();
+        let mut reachable = HashSet::new();
+        for tip in self.ref_tips(None)? {
+            if !reachable.contains(&tip) {
+                reachable.extend(self.ancestors(tip)?);
+            }
+        }
+
+        let mut batch = WriteBatch::default();
+        let mut removed = HashSet::new();
+        for item in self.db.iterator() {
+            let (key, value) = item?;
+            // Only keys that hold a checksum-valid commit are candidates; refs
+            // and `table:id` rows never decode as one.
+            let Ok(hash) = <[u8; 32]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            if reachable.contains(&hash) || decode_commit(&*self.hasher, &*self.codec, &hash, &value).is_err() {
+                continue;
+            }
+            self.evict_cached_commit(&hash);
+            batch.delete(key);
+            batch.delete(snapshot_key(&hash).as_bytes());
+            removed.insert(hash);
+        }
+        self.stage_blob_cleanup(&mut batch, &removed)?;
+
+        self.db.write(batch)?;
+        Ok(removed.len())
+    }
+
+    /// Rewrites the checksum of a commit whose payload is intact but whose
+    /// trailer is stale or missing. Returns `false` if the commit was already
+    /// sound; refuses commits whose payload does not decode.
+    pub fn repair_commit(&self, hash: [u8; 32]) -> Result<bool> {
+        let raw = self.db.get(&hash)?
+            .ok_or_else(|| GitDBError::InvalidInput("Commit not found".into()))?;
+        if decode_commit(&*self.hasher, &*self.codec, &hash, &raw).is_ok() {
+            return Ok(false);
+        }
+
+        // The blob minus a stale trailer, or the blob as a whole in case the
+        // trailer was never written. Either can decode when the other is the
+        // real payload, since shorter legacy layouts and trailing bytes both
+        // parse, so prefer the one that hashes back to `hash`.
+        let stale = raw.len().checked_sub(CHECKSUM_LEN).map(|len| &raw[..len]);
+        let candidates: Vec<&[u8]> = stale.into_iter().chain(std::iter::once(raw.as_slice())).collect();
+        let hashes_back = |payload: &&[u8]| {
+            decode_payload(&*self.codec, &hash, payload)
+                .and_then(|commit| commit_id(&*self.hasher, &*self.codec, &commit))
+                .is_ok_and(|id| id == hash)
+        };
+        let payload = candidates.iter().copied()
+            .find(hashes_back)
+            .or_else(|| candidates.iter().copied().find(|payload| decode_payload(&*self.codec, &hash, payload).is_ok()))
+            .ok_or_else(|| GitDBError::CorruptData(format!(
+                "Commit {} has an undecodable payload and cannot be repaired", hex::encode(hash)
+            )))?;
+
+        let mut repaired = payload.to_vec();
+        repaired.extend_from_slice(&self.hasher.hash(payload));
+        self.db.put(&hash, &repaired)?;
+        self.evict_cached_commit(&hash);
+        Ok(true)
+    }
+
+    /// Copies `tip` and every commit reachable from it into a new database at
+    /// `dst`, along with the blobs they reference, the repository settings and
+    /// the rows of `tip`. The copy has a detached HEAD at `tip` and no other refs.
+    pub fn clone_subset(&self, dst: &str, tip: [u8; 32]) -> Result<()> {
+        self.ensure_commit_exists(&tip, "clone")?;
+        let target: Box<dyn Backend> = Box::new(DB::open(&StorageConfig::default().to_options(), dst)?);
+        if target.iterator().next().is_some() {
+            return Err(GitDBError::InvalidInput(format!("Clone destination {} is not empty", dst)));
+        }
+
+        let mut batch = WriteBatch::default();
+        for key in [CHECKSUM_ALGORITHM_KEY, INTEGRITY_TRAILER_KEY, CODEC_KEY] {
+            if let Some(value) = self.db.get(key)? {
+                batch.put(key, value);
+            }
+        }
+        for hash in self.ancestors(tip)? {
+            let raw = self.db.get(&hash)?
+                .ok_or_else(|| GitDBError::InvalidInput("Commit not found".into()))?;
+            // Decoded without resolving blobs, so references are still visible.
+            let commit = decode_commit(&*self.hasher, &*self.codec, &hash, &raw)?;
+            for change in &commit.changes {
+                let (Change::Insert { value, .. } | Change::Update { value, .. }) = change else {
+                    continue;
+                };
+                if let Some(blob) = blob_reference(value) {
+                    let key = blob_key(&blob);
+                    let bytes = self.db.get(key.as_bytes())?.ok_or_else(|| GitDBError::CorruptData(format!(
+                        "Commit {} references missing blob {}", hex::encode(hash), hex::encode(blob)
+                    )))?;
+                    batch.put(key.as_bytes(), bytes);
+                }
+            }
+            batch.put(hash, raw);
+        }
+        self.stage_materialize(&mut batch, BTreeSet::new(), &self.replay_state(Some(tip))?)?;
+        batch.put(b"HEAD", tip);
+
+        target.write(batch)?;
+        Ok(())
+    }
+
+    /// Makes `commit` a root by folding its full state into its changes, then
+    /// deletes every strictly older commit. Returns the number deleted.
+    ///
+    /// The new root stays under its old hash so descendants and refs remain
+    /// valid, though its contents no longer hash to it. Fails if HEAD, a
+    /// branch or a tag can reach an older commit without passing through
+    /// `commit`.
+    pub fn prune_before(&self, commit: [u8; 32]) -> Result<usize> {
+        let _guard = self.lock_head();
+        let mut root = self.get_commit_by_hash(&commit)?;
+        let mut older = self.ancestors(commit)?;
+        older.remove(&commit);
+
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from(self.ref_tips(None)?);
+        while let Some(hash) = queue.pop_front() {
+            if older.contains(&hash) {
+                return Err(GitDBError::InvalidInput(format!(
+                    "Commit {} is still referenced without passing through {}",
+                    hex::encode(hash), hex::encode(commit)
+                )));
+            }
+            if hash != commit && seen.insert(hash) {
+                queue.extend(self.get_commit_by_hash(&hash)?.parents);
+            }
+        }
+
+        let state: BTreeMap<String, BTreeMap<String, CrdtValue>> = self.replay_state(Some(commit))?
+            .into_data()
+            .into_iter()
+            .map(|(table, rows)| (table, rows.into_iter().collect()))
+            .collect();
+        let mut changes = Vec::new();
+        for (table, rows) in &state {
+            changes.push(Change::schema_add(table));
+            for (id, value) in rows {
+                changes.push(Change::insert_value(table, id, value)?);
+            }
+        }
+        root.parents.clear();
+        root.changes = changes;
+        root.signature = None;
+
+        // The new root re-stores any blob it still needs after the cleanup.
+        let mut batch = WriteBatch::default();
+        self.stage_blob_cleanup(&mut batch, &older.iter().copied().chain([commit]).collect())?;
+        self.stage_commit_at(&mut batch, commit, &root)?;
+        for hash in &older {
+            batch.delete(hash);
+            batch.delete(snapshot_key(hash).as_bytes());
+        }
+        self.db.write(batch)?;
+
+        self.evict_cached_commit(&commit);
+        for hash in &older {
+            self.evict_cached_commit(hash);
+        }
+        Ok(older.len())
+    }
+
+    /// Checks every stored commit and reports all problems found instead of
+    /// stopping at the first.
+    ///
+    /// Tree hashes describe the rows stored when a commit was written, so they
+    /// can only be rechecked for the commit HEAD points at.
+    pub fn fsck(&self) -> Result<Vec<IntegrityIssue>> {
+        let mut issues = Vec::new();
+        let mut commits = Vec::new();
+        for item in self.db.iterator() {
+            let (key, value) = item?;
+            let Ok(hash) = <[u8; 32]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            // Row and ref keys can happen to be 32 bytes long; they are never
+            // random bytes, so skip anything that reads as a named key.
+            if std::str::from_utf8(&hash).is_ok_and(|name| name.contains([':', '/'])) {
+                continue;
+            }
+
+            let checksum_ok = is_unchecked(&value) || value.len() >= CHECKSUM_LEN && {
+                let (payload, checksum) = value.split_at(value.len() - CHECKSUM_LEN);
+                self.hasher.hash(payload) == checksum
+            };
+            if !checksum_ok {
+                issues.push(IntegrityIssue::BadChecksum { hash });
+                continue;
+            }
+            match decode_commit(&*self.hasher, &*self.codec, &hash, &value) {
+                Ok(commit) => commits.push((hash, commit)),
+                Err(e) => issues.push(IntegrityIssue::Undecodable { hash, reason: e.to_string() }),
+            }
+        }
+
+        for (hash, commit) in &commits {
+            for parent in &commit.parents {
+                if self.db.get(parent)?.is_none() {
+                    issues.push(IntegrityIssue::MissingParent { commit: *hash, parent: *parent });
+                }
+            }
+        }
+
+        if let Some(head) = self.get_head()? {
+            if commits.iter().any(|(hash, _)| *hash == head) {
+                for table in self.verify_worktree(head)? {
+                    issues.push(IntegrityIssue::TreeMismatch { commit: head, table });
+                }
+            }
+        }
+        Ok(issues)
     }
-    
-    pub fn get_commit_by_hash(&self, hash: &[u8; 32]) -> Result<Commit> {
-        let raw = self.db.get(hash)?
-            .ok_or_else(|| GitDBError::InvalidInput("Commit not found".into()))?;
-        bincode::deserialize(&raw).map_err(Into::into)
+
+    /// Tables whose stored rows no longer hash to the entry `commit` records
+    /// for them, sorted by name. Only tables in the commit's tree are checked.
+    pub fn verify_worktree(&self, commit: [u8; 32]) -> Result<Vec<String>> {
+        let tree = self.get_commit_by_hash(&commit)?.tree;
+        let mut mismatched = Vec::new();
+        for (table, expected) in &tree {
+            let matches = match self.calculate_table_hash(table) {
+                Ok(actual) => actual == *expected,
+                Err(GitDBError::CorruptData(_)) => false,
+                Err(e) => return Err(e),
+            };
+            if !matches {
+                mismatched.push(table.clone());
+            }
+        }
+        Ok(mismatched)
     }
 
-    pub fn get_head(&self) -> Result<Option<[u8; 32]>> {
-        match self.db.get(b"HEAD")? {
-            Some(raw) if raw.len() == 32 => {
-                let mut bytes = [0u8; 32];
-                bytes.copy_from_slice(&raw);
-                Ok(Some(bytes))
+    /// Changes that would bring the materialized rows in line with `against`
+    /// (HEAD if `None`). Empty when the stored rows already match.
+    pub fn status(&self, against: Option<[u8; 32]>) -> Result<Vec<Change>> {
+        let tip = match against {
+            Some(hash) => Some(hash),
+            None => self.get_head()?,
+        };
+        let target = self.replay_state(tip)?;
+        let live = self.materialized_state()?;
+
+        let tables: BTreeSet<&String> = live.state.keys().chain(target.state.keys()).collect();
+        let mut changes = Vec::new();
+        for table in tables {
+            changes.extend(diff_table(table, &live, &target)?);
+        }
+        Ok(changes)
+    }
+
+    /// Bytes of keys plus values stored for each table's materialized rows.
+    pub fn table_sizes(&self) -> Result<HashMap<String, u64>> {
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        for item in self.db.iterator() {
+            let (key, value) = item?;
+            let Some((table, _)) = split_row_key(&key) else {
+                continue;
+            };
+            *sizes.entry(table.to_string()).or_default() += (key.len() + value.len()) as u64;
+        }
+        Ok(sizes)
+    }
+
+    /// Reads every stored `table:id` row into an engine.
+    fn materialized_state(&self) -> Result<CrdtEngine> {
+        let mut engine = CrdtEngine::new();
+        for item in self.db.iterator() {
+            let (key, value) = item?;
+            let Some((table, id)) = split_row_key(&key) else {
+                continue;
+            };
+            let decoded = self.codec.decode_value(strip_checksum(&value)).map_err(|_| {
+                GitDBError::CorruptData(format!("Row {}:{} holds an undecodable value", table, id))
+            })?;
+            engine.state.entry(table.to_string()).or_default().insert(id.to_string(), decoded);
+        }
+        Ok(engine)
+    }
+
+    /// Returns the full table -> id -> value snapshot at a commit without
+    /// writing anything. Values use the same encoding as `Change` values.
+    pub fn checkout_state(&self, commit_hash: [u8; 32]) -> Result<HashMap<String, HashMap<String, Vec<u8>>>> {
+        let engine = self.replay_state(Some(commit_hash))?;
+
+        let mut snapshot = HashMap::new();
+        for (table, rows) in engine.into_data() {
+            let mut encoded = HashMap::with_capacity(rows.len());
+            for (id, value) in rows {
+                encoded.insert(id, bincode::serialize(&value)?);
             }
-            Some(_) => Err(GitDBError::InvalidInput("HEAD contains invalid data".into())),
-            None => Ok(None),
+            snapshot.insert(table, encoded);
         }
+        Ok(snapshot)
     }
 
-    pub fn create_commit(&self, message: &str, changes: Vec<Change>) -> Result<[u8; 32]> {
-        let parent = self.get_head()?;
-        let mut tree = HashMap::new(); 
+    /// Materializes the full CRDT state at `commit` so later replays of it or
+    /// its descendants can start there instead of at the root.
+    pub fn create_snapshot(&self, commit: [u8; 32]) -> Result<()> {
+        let engine = self.replay_state(Some(commit))?;
+        self.db.put(snapshot_key(&commit).as_bytes(), &self.codec.encode_snapshot(&engine)?)?;
+        Ok(())
+    }
 
-        // Not sure if this is optimal — might refactor how we store tree structure later
-        for c in &changes {
-            let table_hash = self.calculate_table_hash(c.table())?;
-            tree.insert(c.table().to_string(), table_hash); 
+    /// Finds the snapshot closest to `from` by walking its ancestry breadth-first.
+    /// `from` itself counts if it has a snapshot.
+    pub fn nearest_snapshot(&self, from: [u8; 32]) -> Result<Option<[u8; 32]>> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(hash) = queue.pop_front() {
+            if !seen.insert(hash) {
+                continue;
+            }
+            if self.db.get(snapshot_key(&hash).as_bytes())?.is_some() {
+                return Ok(Some(hash));
+            }
+            queue.extend(self.get_commit_by_hash(&hash)?.parents);
         }
+        Ok(None)
+    }
 
-        let commit = Commit {
-            parents: parent.into_iter().collect(),
-            message: message.to_string(),
-            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
-            changes,
-            tree,
-        };
+    fn load_snapshot(&self, commit: &[u8; 32]) -> Result<CrdtEngine> {
+        let raw = self.db.get(snapshot_key(commit).as_bytes())?
+            .ok_or_else(|| GitDBError::InvalidInput(format!(
+                "No snapshot for commit {}", hex::encode(commit)
+            )))?;
+        self.codec.decode_snapshot(&raw).map_err(|e| GitDBError::CorruptData(format!(
+            "Snapshot for commit {} is unreadable: {}", hex::encode(commit), e
+        )))
+    }
 
-        let serialized = bincode::serialize(&commit)?;
-        let hash = blake3::hash(&serialized);
-        let hash_bytes: [u8; 32] = *hash.as_bytes();
+    /// Rebuilds the CRDT state at `tip` by replaying its history oldest-first,
+    /// starting from the nearest snapshot when every path to the root passes
+    /// through it.
+    fn replay_state(&self, tip: Option<[u8; 32]>) -> Result<CrdtEngine> {
+        let Some(tip) = tip else {
+            return Ok(CrdtEngine::new());
+        };
+        let (base, records) = self.load_replay_plan(tip)?;
+        let mut engine = match base {
+            Some(base) => self.load_snapshot(&base)?,
+            None => CrdtEngine::new(),
+        };
+        for record in records.into_iter().rev() {
+            engine.apply_changes(&record.commit.changes)?;
+        }
+        Ok(engine)
+    }
 
-        let test_deserialize: Commit = bincode::deserialize(&serialized)?;
-        if test_deserialize.message != commit.message {
-            return Err(GitDBError::CorruptData("Serialization roundtrip failed".into()));
+    /// Walks back from `tip` once, stopping at commits that have a snapshot.
+    /// If exactly one snapshot was hit and no path reached a root around it,
+    /// returns that snapshot and the commits above it, newest first. Otherwise
+    /// the walk carries on past the snapshots and returns the whole history.
+    fn load_replay_plan(&self, tip: [u8; 32]) -> Result<(Option<[u8; 32]>, Vec<CommitRecord>)> {
+        let mut commits: HashMap<[u8; 32], Commit> = HashMap::new();
+        let mut snapshots = HashSet::new();
+        let mut reached_root = false;
+        let mut queue = VecDeque::from([tip]);
+        while let Some(hash) = queue.pop_front() {
+            if commits.contains_key(&hash) || snapshots.contains(&hash) {
+                continue;
+            }
+            if self.db.get(snapshot_key(&hash).as_bytes())?.is_some() {
+                snapshots.insert(hash);
+                continue;
+            }
+            let commit = self.get_commit_by_hash(&hash)?;
+            reached_root |= commit.parents.is_empty();
+            queue.extend(commit.parents.iter().cloned());
+            commits.insert(hash, commit);
+            self.check_history_depth(commits.len())?;
         }
 
-        let checksum = blake3::hash(&serialized);
-        let mut protected_value = serialized.clone();
-        protected_value.extend_from_slice(checksum.as_bytes());
+        if snapshots.len() == 1 && !reached_root {
+            let base = snapshots.into_iter().next();
+            return Ok((base, newest_first(tip, commits)?));
+        }
 
-        self.db.put(&hash_bytes, &protected_value)?;
-        
-        self.update_head(&hash_bytes)?;
-        
-        Ok(hash_bytes)
+        queue.extend(snapshots);
+        while let Some(hash) = queue.pop_front() {
+            if commits.contains_key(&hash) {
+                continue;
+            }
+            let commit = self.get_commit_by_hash(&hash)?;
+            queue.extend(commit.parents.iter().cloned());
+            commits.insert(hash, commit);
+            self.check_history_depth(commits.len())?;
+        }
+        Ok((None, newest_first(tip, commits)?))
     }
 
+    /// Rewrites the stored rows to match `commit_hash` and records the inverse
+    /// changes as a single commit. Rows, commit and HEAD go out in one batch.
     pub fn revert_to_commit(&self, commit_hash: &[u8; 32]) -> Result<()> {
+        self.ensure_commit_exists(commit_hash, "revert target")?;
+        let _guard = self.lock_head();
         let target_commit = self.get_commit_by_hash(commit_hash)?;
-        let mut target_engine = CrdtEngine::new();
-        let commit_chain = self.load_commit_chain(Some(*commit_hash))?;
+        let target_engine = self.replay_state(Some(*commit_hash))?;
+        let head = self.get_head()?;
+        let current_engine = self.replay_state(head)?;
 
-        for commit in commit_chain.into_iter().rev() {
-            for change in &commit.changes {
-                target_engine.apply_change(change)?;
-            }
+        // Record the true inverse so the revert commit diffs like any other.
+        let revert_changes = diff_engines(&current_engine, &target_engine)?;
+
+        let tables: BTreeSet<&String> = target_commit.tree.keys()
+            .chain(current_engine.state.keys())
+            .collect();
+
+        let mut batch = WriteBatch::default();
+        self.stage_materialize(&mut batch, tables, &target_engine)?;
+
+        let message = format!("Revert to {}", hex::encode(commit_hash));
+        let mut commit = self.build_commit(head.into_iter().collect(), UNKNOWN_AUTHOR, "", &message, revert_changes)?;
+        // The rows are still only staged, so hash the tree from what is about to be written.
+        for (table, table_hash) in commit.tree.iter_mut() {
+            *table_hash = self.hash_rows(table, target_engine.state.get(table))?;
+        }
+
+        self.write_built_commit(batch, &commit, &format!("commit: {}", message))?;
+        Ok(())
+    }
+
+    /// Repoints `branch` at `to` without recording a commit. If HEAD is on
+    /// `branch` it moves too, the rows are rewritten to match `to`, and the
+    /// move lands in the reflog.
+    ///
+    /// Unless `hard` is set, `to` must already be in the branch's history.
+    pub fn reset_branch(&self, branch: &str, to: [u8; 32], hard: bool) -> Result<()> {
+        let _guard = self.lock_head();
+        let tip = self.get_branch(branch)?
+            .ok_or_else(|| GitDBError::InvalidInput(format!("Branch '{}' does not exist", branch)))?;
+        self.ensure_commit_exists(&to, "reset target")?;
+        if !hard && !self.is_ancestor(to, tip)? {
+            return Err(GitDBError::InvalidInput(format!(
+                "Commit {} is not in the history of branch '{}'", hex::encode(to), branch
+            )));
         }
 
         let mut batch = WriteBatch::default();
-        for table in target_commit.tree.keys() {
-            let prefix = format!("{}:", table);
-            let iter = self.db.prefix_iterator(prefix.as_bytes());
-            for item in iter {
-                let (key, _) = item?;
-                batch.delete(key);
-            }
+        if self.current_branch()?.as_deref() == Some(branch) {
+            let current_engine = self.replay_state(self.get_head()?)?;
+            let target_engine = self.replay_state(Some(to))?;
+            let tables = current_engine.state.keys().chain(target_engine.state.keys()).collect();
+            self.stage_materialize(&mut batch, tables, &target_engine)?;
+            self.stage_head(&mut batch, &to, &format!("reset: moving {} to {}", branch, hex::encode(to)))?;
+        } else {
+            batch.put(branch_key(branch).as_bytes(), to);
         }
+        self.db.write(batch)
+    }
 
-        for (table, rows) in target_engine.into_data() {
+    /// Adds to `batch` the writes that replace the stored rows of `tables`
+    /// with the rows of `target`.
+    fn stage_materialize(&self, batch: &mut WriteBatch, tables: BTreeSet<&String>, target: &CrdtEngine) -> Result<()> {
+        for table in tables {
+            self.stage_clear_table(batch, table)?;
+        }
+        for (table, rows) in &target.state {
             for (id, value) in rows {
-                let key = format!("{}:{}", table, id);
-                let serialized = bincode::serialize(&value)?;
-                batch.put(key.as_bytes(), serialized);
+                batch.put(row_key(table, id).as_bytes(), self.codec.encode_value(value)?);
             }
         }
+        Ok(())
+    }
 
-        let revert_changes = target_commit.changes.iter()
-            .map(|c| match c {
-                Change::Insert { table, id, .. } => Change::Delete {
-                    table: table.clone(),
-                    id: id.clone(),
-                },
-                _ => c.clone(),
-            })
-            .collect();
+    /// The hash `calculate_table_hash` will give `table` once `rows` are stored.
+    fn hash_rows(&self, table: &str, rows: Option<&TableState>) -> Result<[u8; 32]> {
+        let mut sorted = BTreeMap::new();
+        for (id, value) in rows.into_iter().flatten() {
+            sorted.insert(row_key(table, id), self.codec.encode_value(value)?);
+        }
+
+        let mut hasher = self.hasher.start();
+        for (key, value) in sorted {
+            hasher.update(key.as_bytes());
+            hasher.update(&value);
+        }
+        Ok(hasher.finish())
+    }
 
+    /// Rewrites the stored rows of just `tables` to match `commit`, leaving
+    /// every other table alone. No commit is recorded and HEAD does not move.
+    pub fn checkout_tables(&self, commit: [u8; 32], tables: &[&str]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for table in tables {
+            let engine = self.replay_table(table, commit)?;
+            self.stage_clear_table(&mut batch, table)?;
+            for (id, value) in engine.state.get(*table).into_iter().flatten() {
+                batch.put(row_key(table, id).as_bytes(), self.codec.encode_value(value)?);
+            }
+        }
         self.db.write(batch)?;
-        self.create_commit(&format!("Revert to {}", hex::encode(commit_hash)), revert_changes)?;
         Ok(())
     }
 
-    fn calculate_table_hash(&self, table: &str) -> Result<[u8; 32]> {
-        let mut hasher = blake3::Hasher::new();
-        let mut rows = Vec::new();
-        
-        let iter = self.db.prefix_iterator(table.as_bytes());
-        for result in iter {
-            let (key, value) = result?;
-            rows.push((key.to_vec(), value.to_vec()));
+    /// Adds deletes for every stored row of `table` to `batch`.
+    fn stage_clear_table(&self, batch: &mut WriteBatch, table: &str) -> Result<()> {
+        let prefix = format!("{}:", table);
+        for item in self.db.prefix_iterator(prefix.as_bytes()) {
+            let (key, _) = item?;
+            batch.delete(key);
         }
+        Ok(())
+    }
+
+    pub(crate) fn calculate_table_hash(&self, table: &str) -> Result<[u8; 32]> {
+        let mut hasher = self.hasher.start();
         
-        rows.sort_by(|a: &(Vec<u8>, Vec<u8>), b: &(Vec<u8>, Vec<u8>)| a.0.cmp(&b.0));
-        
-        for (key, value) in rows {
+        // Include the delimiter so `user` does not also pick up rows of `users`.
+        // RocksDB yields keys in sorted order, so rows hash deterministically.
+        let prefix = format!("{}:", table);
+        let mut last_key: Option<Vec<u8>> = None;
+        for result in self.db.prefix_iterator(prefix.as_bytes()) {
+            let (key, value) = result.map_err(|e| {
+                let location = match &last_key {
+                    Some(k) => format!("after row key {}", hex::encode(k)),
+                    None => format!("at start of table '{}'", table),
+                };
+                GitDBError::CorruptData(format!("Failed to read rows {}: {}", location, e))
+            })?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            if self.codec.decode_value(strip_checksum(&value)).is_err() {
+                return Err(GitDBError::CorruptData(format!(
+                    "Row key {} holds an undecodable value", hex::encode(&key)
+                )));
+            }
+
             hasher.update(&key);
             hasher.update(&value);
+            last_key = Some(key.to_vec());
         }
         
-        Ok(*hasher.finalize().as_bytes())
+        Ok(hasher.finish())
     }
 
+    /// Row-level changes between two commits for every table either tree records.
+    ///
+    /// A table created or dropped between the commits shows up as a
+    /// `SchemaAdd` before its row inserts or a `SchemaDrop` after its row deletes.
     pub fn get_commit_diffs(&self, from: &[u8; 32], to: &[u8; 32]) -> Result<Vec<Change>> {
         let from_commit = self.get_commit_by_hash(from)?;
         let to_commit = self.get_commit_by_hash(to)?;
         
         let mut diffs = Vec::new();
-        
-        for (table, to_hash) in &to_commit.tree {
-            if let Some(from_hash) = from_commit.tree.get(table) {
-                if from_hash != to_hash {
-                    let table_diffs = self.get_table_diffs(table, from, to)?;
-                    diffs.extend(table_diffs);
-                }
-            } else {
-                diffs.push(Change::Insert {
-                    table: table.clone(),
-                    id: "!schema".to_string(),
-                    value: vec![],
-                });
-            }
+        for table in tables_to_diff(&from_commit.tree, &to_commit.tree) {
+            diffs.extend(self.get_table_diffs(table, from, to)?);
         }
         Ok(diffs)
     }
 
-    fn update_head(&self, hash: &[u8; 32]) -> Result<()> {
-        self.db.put(b"HEAD", hash)?;
-        Ok(())
+    /// Walks history from HEAD, newest first, yielding each commit's hash with
+    /// its diff against its first parent. Root commits yield their own changes.
+    ///
+    /// Only the order is settled up front, from the commit graph. Each diff is
+    /// worked out when it is reached, from its parent's state as replayed from
+    /// the nearest snapshot, so a caller that stops early never pays for the rest.
+    pub fn history_diffs(&self) -> impl Iterator<Item = Result<([u8; 32], Vec<Change>)>> + '_ {
+        let hashes = match self.get_commit_history_with_hashes() {
+            Ok(records) => records.into_iter().map(|record| Ok(record.hash)).collect(),
+            Err(e) => vec![Err(e)],
+        };
+        hashes.into_iter().map(move |hash| {
+            let hash = hash?;
+            Ok((hash, self.first_parent_diff(hash)?))
+        })
     }
 
-    pub fn get_commit_history(&self) -> Result<Vec<Commit>> {
-        self.load_commit_chain(self.get_head()?)
+    /// The diff of `hash` against its first parent, or its own changes if it
+    /// is a root commit.
+    fn first_parent_diff(&self, hash: [u8; 32]) -> Result<Vec<Change>> {
+        let commit = self.get_commit_by_hash(&hash)?;
+        let Some(parent) = commit.parents.first() else {
+            return Ok(commit.changes);
+        };
+        let parent_tree = self.get_commit_by_hash(parent)?.tree;
+        let tables = tables_to_diff(&parent_tree, &commit.tree);
+        let parent_engine = self.replay_state(Some(*parent))?;
+
+        // A merge brings in another line's history, so its state is
+        // rebuilt; otherwise the commit's changes go on top of its parent.
+        let (from, to) = if commit.parents.len() > 1 {
+            (parent_engine, self.replay_state(Some(hash))?)
+        } else {
+            let from = parent_engine.subset(tables.iter().map(|table| table.as_str()));
+            let mut to = parent_engine;
+            to.apply_changes(&commit.changes)?;
+            (from, to)
+        };
+
+        let mut diff = Vec::new();
+        for table in &tables {
+            diff.extend(diff_table_with_schema(table, &from, &to)?);
+        }
+        Ok(diff)
     }
 
-    pub fn get_table_diffs(&self, table: &str, from: &[u8; 32], to: &[u8; 32]) -> Result<Vec<Change>> {
-        let from_commit = self.get_commit_by_hash(from)?;
-        let to_commit = self.get_commit_by_hash(to)?;
-    
-        let mut from_engine = CrdtEngine::new();
-        let mut to_engine = CrdtEngine::new();
-    
-        let mut current_hash = from_commit.parents.get(0).cloned();
-        while let Some(hash) = current_hash {
-            let commit = self.get_commit_by_hash(&hash)?;
-            for change in &commit.changes {
-                if change.table() == table {
-                    from_engine.apply_change(change)?;
-                }
+    /// Renders every commit reachable from HEAD, a branch, a tag or a
+    /// workspace as a Graphviz DOT graph, with an edge from each commit to
+    /// each of its parents. Nodes are labeled with a short hash and message.
+    pub fn to_dot(&self) -> Result<String> {
+        let mut tips: Vec<[u8; 32]> = self.get_head()?.into_iter().collect();
+        tips.extend(self.list_branches()?.into_iter().map(|(_, tip)| tip));
+        tips.extend(self.list_workspaces()?.into_iter().map(|(_, tip)| tip));
+        for tag in self.list_tags()? {
+            tips.push(self.resolve_tag(&tag)?);
+        }
+
+        let mut reachable = BTreeSet::new();
+        for tip in tips {
+            if !reachable.contains(&tip) {
+                reachable.extend(self.ancestors(tip)?);
             }
-            current_hash = commit.parents.get(0).cloned();
         }
-    
-        let mut current_hash = to_commit.parents.get(0).cloned();
-        while let Some(hash) = current_hash {
+
+        let mut dot = String::from("digraph commits {\n");
+        for hash in &reachable {
+            let commit = self.get_commit_by_hash(hash)?;
+            let label = format!("{} {}", hex::encode(&hash[..4]), commit.message);
+            dot.push_str(&format!("    \"{}\" [label=\"{}\"];\n", hex::encode(hash), escape_dot(&label)));
+            for parent in &commit.parents {
+                dot.push_str(&format!("    \"{}\" -> \"{}\";\n", hex::encode(hash), hex::encode(parent)));
+            }
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Diffs a commit against each of its parents, keyed by parent hash.
+    /// A root commit yields an empty map.
+    pub fn diff_against_parents(&self, merge: [u8; 32]) -> Result<HashMap<[u8; 32], Vec<Change>>> {
+        let commit = self.get_commit_by_hash(&merge)?;
+        let mut diffs = HashMap::new();
+        for parent in &commit.parents {
+            if !diffs.contains_key(parent) {
+                diffs.insert(*parent, self.get_commit_diffs(parent, &merge)?);
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Names of the tables that exist at `commit` (HEAD if `None`), sorted.
+    ///
+    /// A commit's tree only records the tables that commit touched, so this
+    /// replays history rather than reading the tree.
+    pub fn list_tables(&self, commit: Option<[u8; 32]>) -> Result<Vec<String>> {
+        let tip = match commit {
+            Some(hash) => Some(hash),
+            None => self.get_head()?,
+        };
+        let mut tables: Vec<String> = self.replay_state(tip)?.state.into_keys().collect();
+        tables.sort();
+        Ok(tables)
+    }
+
+    /// The stored tree hash of `table` as of `commit`, or `None` if the table
+    /// does not exist there. A commit's tree only records the tables it
+    /// touched, so this walks first parents to the latest commit that did;
+    /// nothing is rehashed.
+    pub fn table_hash_at(&self, commit: [u8; 32], table: &str) -> Result<Option<[u8; 32]>> {
+        self.ensure_commit_exists(&commit, "requested")?;
+        let mut current = Some(commit);
+        let mut walked = 0;
+        while let Some(hash) = current {
+            walked += 1;
+            self.check_history_depth(walked)?;
             let commit = self.get_commit_by_hash(&hash)?;
-            for change in &commit.changes {
-                if change.table() == table {
-                    to_engine.apply_change(change)?;
+            if let Some(tree_hash) = commit.tree.get(table) {
+                let dropped = commit.changes.iter().rev()
+                    .find(|c| c.table() == table)
+                    .is_some_and(|c| matches!(c, Change::SchemaDrop { .. }));
+                return Ok((!dropped).then_some(*tree_hash));
+            }
+            current = commit.parents.first().copied();
+        }
+        Ok(None)
+    }
+
+    /// Compares the per-table tree hashes of two commits without touching rows.
+    pub fn trees_equal(&self, a: [u8; 32], b: [u8; 32]) -> Result<bool> {
+        Ok(self.get_commit_by_hash(&a)?.tree == self.get_commit_by_hash(&b)?.tree)
+    }
+
+    /// Lists tables whose tree hash differs between two commits or that only
+    /// one of them records, sorted by name.
+    pub fn tables_changed(&self, a: [u8; 32], b: [u8; 32]) -> Result<Vec<String>> {
+        let tree_a = self.get_commit_by_hash(&a)?.tree;
+        let tree_b = self.get_commit_by_hash(&b)?.tree;
+
+        let tables: BTreeSet<&String> = tree_a.keys().chain(tree_b.keys()).collect();
+        Ok(tables.into_iter()
+            .filter(|table| tree_a.get(*table) != tree_b.get(*table))
+            .cloned()
+            .collect())
+    }
+
+    /// Counts inserts, updates and deletes per table between two commits.
+    /// Tables with no changes are omitted.
+    pub fn summarize_diff(&self, from: [u8; 32], to: [u8; 32]) -> Result<HashMap<String, DiffStats>> {
+        let from_engine = self.replay_state(Some(from))?;
+        let to_engine = self.replay_state(Some(to))?;
+
+        Ok(tally_changes(&diff_engines(&from_engine, &to_engine)?))
+    }
+
+    /// The commits in `from..to`, oldest first, with consecutive commits by
+    /// the same author folded into one group. Each group's stats add up the
+    /// `summarize_diff` of every commit against its first parent.
+    pub fn changelog(&self, from: [u8; 32], to: [u8; 32]) -> Result<Vec<ChangelogGroup>> {
+        let mut groups: Vec<ChangelogGroup> = Vec::new();
+        for record in self.commits_between(from, to)?.into_iter().rev() {
+            let commit = record.commit;
+            let stats = match commit.parents.first() {
+                Some(parent) => self.summarize_diff(*parent, record.hash)?,
+                None => tally_changes(&commit.changes),
+            };
+
+            match groups.last_mut() {
+                Some(group) if group.author == commit.author && group.email == commit.email => {
+                    group.messages.push(commit.message);
+                    for (table, table_stats) in stats {
+                        *group.stats.entry(table).or_default() += table_stats;
+                    }
                 }
+                _ => groups.push(ChangelogGroup {
+                    author: commit.author,
+                    email: commit.email,
+                    messages: vec![commit.message],
+                    stats,
+                }),
             }
-            current_hash = commit.parents.get(0).cloned();
         }
-        let mut diffs = Vec::new();
-        let from_rows = from_engine.state.get(table).cloned().unwrap_or_default();
-        let to_rows = to_engine.state.get(table).cloned().unwrap_or_default();
-    
-        for (id, to_val) in &to_rows {
-            match from_rows.get(id) {
-                Some(from_val) if from_val != to_val => {
-                    diffs.push(Change::Update {
-                        table: table.to_string(),
-                        id: id.clone(),
-                        value: bincode::serialize(to_val)?,
-                    });
+        Ok(groups)
+    }
+
+    /// Serializes the diff between two commits as a JSON array sorted by (table, id).
+    pub fn diff_to_json(&self, from: [u8; 32], to: [u8; 32]) -> Result<String> {
+        let mut diffs = self.get_commit_diffs(&from, &to)?;
+        diffs.sort_by(|a, b| (a.table(), a.id()).cmp(&(b.table(), b.id())));
+
+        let entries: Vec<JsonChange> = diffs.iter().map(JsonChange::from).collect();
+        serde_json::to_string(&entries).map_err(Into::into)
+    }
+
+    /// Like `diff_to_json`, but each update also lists the fields that
+    /// changed, as computed by `field_diff` against the row in `from`.
+    pub fn diff_to_json_with_fields(&self, from: [u8; 32], to: [u8; 32]) -> Result<String> {
+        let mut diffs = self.get_commit_diffs(&from, &to)?;
+        diffs.sort_by(|a, b| (a.table(), a.id()).cmp(&(b.table(), b.id())));
+        let from_state = self.replay_state(Some(from))?;
+
+        let mut entries = Vec::with_capacity(diffs.len());
+        for change in &diffs {
+            let mut entry = JsonChange::from(change);
+            if let (JsonChange::Update { fields, .. }, Change::Update { table, id, value, .. }) = (&mut entry, change) {
+                if let Some(old) = from_state.table(table).and_then(|rows| rows.get(id)) {
+                    let changed = field_diff(&bincode::serialize(old)?, value)?;
+                    *fields = Some(changed.into_iter().map(JsonFieldChange::from).collect());
                 }
-                None => {
-                    diffs.push(Change::Insert {
-                        table: table.to_string(),
-                        id: id.clone(),
-                        value: bincode::serialize(to_val)?,
-                    });
+            }
+            entries.push(entry);
+        }
+        serde_json::to_string(&entries).map_err(Into::into)
+    }
+
+    /// Renders the diff between two commits for people to read, one line per
+    /// change sorted by (table, id): `+` for inserts, `-` for deletes and `~`
+    /// for updates, followed by a hex preview of the new value.
+    pub fn diff_to_text(&self, from: [u8; 32], to: [u8; 32]) -> Result<String> {
+        let mut diffs = self.get_commit_diffs(&from, &to)?;
+        diffs.sort_by(|a, b| (a.table(), a.id()).cmp(&(b.table(), b.id())));
+
+        let mut text = String::new();
+        for change in &diffs {
+            let line = match change {
+                Change::Insert { table, id, value } => format!("+ {}/{} {}", table, id, value_preview(value)),
+                Change::Update { table, id, value, .. } => format!("~ {}/{} {}", table, id, value_preview(value)),
+                Change::FieldUpdate { table, id, field, value } => {
+                    format!("~ {}/{}.{} {}", table, id, field, value_preview(value))
                 }
-                _ => {}
+                Change::Delete { table, id } => format!("- {}/{}", table, id),
+                Change::SchemaAdd { table } => format!("+ {}/", table),
+                Change::SchemaDrop { table } => format!("- {}/", table),
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    /// Logs a HEAD move to `new` that also rewrote `branch`'s ref, if given.
+    fn stage_reflog(&self, batch: &mut WriteBatch, new: &[u8; 32], reason: &str, branch: Option<&str>) -> Result<()> {
+        let seq = match self.db.get(REFLOG_SEQ_KEY)? {
+            Some(raw) => u64::from_le_bytes(raw.as_slice().try_into().map_err(|_| {
+                GitDBError::CorruptData("Reflog sequence contains invalid data".into())
+            })?) + 1,
+            None => 0,
+        };
+        let entry = ReflogEntry {
+            timestamp: self.clock.now()?,
+            old: self.get_head()?,
+            new: *new,
+            reason: reason.to_string(),
+            old_branch: self.current_branch()?,
+            branch: branch.map(str::to_string),
+        };
+
+        batch.put(reflog_key(seq).as_bytes(), self.codec.encode_reflog(&entry)?);
+        batch.put(REFLOG_SEQ_KEY, seq.to_le_bytes());
+        Ok(())
+    }
+
+    /// HEAD movements, oldest first.
+    pub fn get_reflog(&self) -> Result<Vec<ReflogEntry>> {
+        let mut entries = Vec::new();
+        for item in self.db.prefix_iterator(REFLOG_PREFIX.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(REFLOG_PREFIX.as_bytes()) {
+                break;
             }
+            entries.push(self.codec.decode_reflog(&value)?);
         }
-        for (id, _) in from_rows {
-            if !to_rows.contains_key(&id) {
-                diffs.push(Change::Delete {
-                    table: table.to_string(),
-                    id,
-                });
+        Ok(entries)
+    }
+
+    /// Returns HEAD to where it was before the most recent movement and
+    /// reattaches it to the branch it was on then. A branch ref is only moved
+    /// back if that movement advanced it. The undo is itself logged, so
+    /// calling this twice returns to the starting point.
+    pub fn undo_last_head_move(&self) -> Result<()> {
+        let _guard = self.lock_head();
+        let last = self.get_reflog()?.pop()
+            .ok_or_else(|| GitDBError::InvalidInput("Reflog is empty".into()))?;
+        let previous = last.old
+            .ok_or_else(|| GitDBError::InvalidInput("HEAD had no previous position".into()))?;
+
+        let mut batch = WriteBatch::default();
+        self.stage_reflog(&mut batch, &previous, &format!("undo: {}", last.reason), last.branch.as_deref())?;
+        if let Some(branch) = &last.branch {
+            batch.put(branch_key(branch).as_bytes(), previous);
+        }
+        match &last.old_branch {
+            Some(branch) => batch.put(HEAD_BRANCH_KEY, branch.as_bytes()),
+            None => batch.delete(HEAD_BRANCH_KEY),
+        }
+        batch.put(b"HEAD", previous);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Adds the HEAD move (and current branch ref, if any) to `batch`.
+    fn stage_head(&self, batch: &mut WriteBatch, hash: &[u8; 32], reason: &str) -> Result<()> {
+        let branch = self.current_branch()?;
+        self.stage_reflog(batch, hash, reason, branch.as_deref())?;
+        if let Some(branch) = branch {
+            batch.put(branch_key(&branch).as_bytes(), hash);
+        }
+        batch.put(b"HEAD", hash);
+        Ok(())
+    }
+
+    pub fn get_commit_history(&self) -> Result<Vec<Commit>> {
+        self.load_commit_chain(self.get_head()?)
+    }
+
+    /// Counts the unique commits reachable from `from` (or HEAD) through any parent.
+    pub fn count_commits(&self, from: Option<[u8; 32]>) -> Result<usize> {
+        match from.or(self.get_head()?) {
+            Some(start) => Ok(self.ancestors(start)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    pub fn get_commit_history_with_hashes(&self) -> Result<Vec<CommitRecord>> {
+        self.load_commit_records(self.get_head()?)
+    }
+
+    /// Commits reachable from HEAD that satisfy `predicate`, newest first.
+    pub fn find_commits(&self, predicate: impl Fn(&Commit) -> bool) -> Result<Vec<CommitRecord>> {
+        let mut records = self.get_commit_history_with_hashes()?;
+        records.retain(|record| predicate(&record.commit));
+        Ok(records)
+    }
+
+    pub fn find_by_author(&self, author: &str) -> Result<Vec<CommitRecord>> {
+        self.find_commits(|commit| commit.author == author)
+    }
+
+    pub fn find_by_message_substring(&self, needle: &str) -> Result<Vec<CommitRecord>> {
+        self.find_commits(|commit| commit.message.contains(needle))
+    }
+
+    /// Returns up to `limit` commits newest-first starting at `start` (or HEAD),
+    /// plus the hash to pass as `start` for the next page, or `None` once
+    /// history is exhausted.
+    ///
+    /// Only the commits on the current page are loaded. Because the cursor is a
+    /// single commit, later pages follow that commit's ancestry.
+    pub fn get_commit_history_page(&self, start: Option<[u8; 32]>, limit: usize) -> Result<(Vec<Commit>, Option<[u8; 32]>)> {
+        let Some(start) = start.or(self.get_head()?) else {
+            return Ok((Vec::new(), None));
+        };
+
+        let mut page = Vec::new();
+        let mut next = None;
+        self.walk_history(start, |hash, commit| {
+            if page.len() == limit {
+                next = Some(hash);
+                return Ok(false);
+            }
+            page.push(commit);
+            Ok(true)
+        })?;
+        Ok((page, next))
+    }
+
+    /// Returns the most recent commit reachable from HEAD whose changes touch
+    /// `table`/`id`, or `None` if no commit ever did.
+    pub fn blame_row(&self, table: &str, id: &str) -> Result<Option<[u8; 32]>> {
+        let Some(head) = self.get_head()? else {
+            return Ok(None);
+        };
+
+        let mut found = None;
+        self.walk_history(head, |hash, commit| {
+            if commit.changes.iter().any(|c| c.table() == table && c.id() == id) {
+                found = Some(hash);
+                return Ok(false);
+            }
+            Ok(true)
+        })?;
+        Ok(found)
+    }
+
+    /// Every change made to `table`/`id` in history reachable from HEAD,
+    /// newest first, paired with the commit that made it.
+    pub fn row_history(&self, table: &str, id: &str) -> Result<Vec<([u8; 32], Change)>> {
+        let Some(head) = self.get_head()? else {
+            return Ok(Vec::new());
+        };
+
+        let mut history = Vec::new();
+        self.walk_history(head, |hash, commit| {
+            history.extend(commit.changes.into_iter()
+                .rev()
+                .filter(|c| c.table() == table && c.id() == id)
+                .map(|c| (hash, c)));
+            Ok(true)
+        })?;
+        Ok(history)
+    }
+
+    /// Visits commits reachable from `start` newest-first, each exactly once,
+    /// loading them lazily until `visit` returns `false`.
+    fn walk_history(&self, start: [u8; 32], mut visit: impl FnMut([u8; 32], Commit) -> Result<bool>) -> Result<()> {
+        let first = self.get_commit_by_hash(&start)?;
+        let mut ready = BinaryHeap::from([(first.timestamp, start)]);
+        let mut pending = HashMap::from([(start, first)]);
+        let mut seen = HashSet::from([start]);
+
+        while let Some((_, hash)) = ready.pop() {
+            let Some(commit) = pending.remove(&hash) else {
+                continue;
+            };
+            let parents = commit.parents.clone();
+            if parents.contains(&hash) {
+                return Err(cycle_detected(&hash));
+            }
+            if !visit(hash, commit)? {
+                break;
+            }
+            for parent in parents {
+                if seen.insert(parent) {
+                    self.check_history_depth(seen.len())?;
+                    let parent_commit = self.get_commit_by_hash(&parent)?;
+                    ready.push((parent_commit.timestamp, parent));
+                    pending.insert(parent, parent_commit);
+                }
             }
         }
-    
-        Ok(diffs)
+        Ok(())
+    }
+
+    pub fn get_table_diffs(&self, table: &str, from: &[u8; 32], to: &[u8; 32]) -> Result<Vec<Change>> {
+        self.ensure_commit_exists(from, "from")?;
+        self.ensure_commit_exists(to, "to")?;
+
+        let from_engine = self.replay_table(table, *from)?;
+        let to_engine = self.replay_table(table, *to)?;
+        diff_table_with_schema(table, &from_engine, &to_engine)
     }
 
     pub fn debug_commit(&self, hash: &str) -> Result<()> {
-        let hash_bytes = hex::decode(hash)?;
+        let hash_bytes = parse_hash(hash)?;
         match self.db.get(&hash_bytes)? {
             Some(data) => {
                 println!("Commit data ({} bytes):", data.len());
                 println!("Hex: {}", hex::encode(&data));
-                match bincode::deserialize::<Commit>(&data) {
+                match decode_commit(&*self.hasher, &*self.codec, &hash_bytes, &data) {
                     Ok(commit) => println!("Valid commit: {:?}", commit),
                     Err(e) => println!("Deserialization failed: {}", e),
                 }
@@ -253,13 +3167,59 @@ impl CommitStorage {
         Ok(())
     }
 
-    fn load_commit_chain(&self, mut current_hash: Option<[u8; 32]>) -> Result<Vec<Commit>> {
-        let mut history = Vec::new();
-        while let Some(hash) = current_hash {
+    /// Whether a commit is stored under `hash`, without decoding it.
+    pub fn commit_exists(&self, hash: [u8; 32]) -> Result<bool> {
+        Ok(self.db.get(&hash)?.is_some())
+    }
+
+    fn ensure_commit_exists(&self, hash: &[u8; 32], role: &str) -> Result<()> {
+        if !self.commit_exists(*hash)? {
+            return Err(GitDBError::InvalidInput(format!(
+                "{} commit {} does not exist", role, hex::encode(hash)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Like `replay_state`, but only for `table`, so the engine holds nothing
+    /// else even when it starts from a snapshot.
+    fn replay_table(&self, table: &str, tip: [u8; 32]) -> Result<CrdtEngine> {
+        let (base, records) = self.load_replay_plan(tip)?;
+        let mut engine = match base {
+            Some(base) => self.load_snapshot(&base)?.subset([table]),
+            None => CrdtEngine::new(),
+        };
+        for record in records.into_iter().rev() {
+            for change in record.commit.changes.iter().filter(|c| c.table() == table) {
+                engine.apply_change(change)?;
+            }
+        }
+        Ok(engine)
+    }
+
+    /// Returns every commit reachable from `start` exactly once, newest first.
+    ///
+    /// A commit is only emitted after all of its descendants in the reachable
+    /// graph; ties are broken by timestamp and then hash so the order is stable.
+    fn load_commit_chain(&self, start: Option<[u8; 32]>) -> Result<Vec<Commit>> {
+        Ok(self.load_commit_records(start)?.into_iter().map(|r| r.commit).collect())
+    }
+
+    fn load_commit_records(&self, start: Option<[u8; 32]>) -> Result<Vec<CommitRecord>> {
+        let Some(start) = start else {
+            return Ok(Vec::new());
+        };
+        let mut commits: HashMap<[u8; 32], Commit> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(hash) = queue.pop_front() {
+            if commits.contains_key(&hash) {
+                continue;
+            }
             let commit = self.get_commit_by_hash(&hash)?;
-            history.push(commit.clone());
-            current_hash = commit.parents.get(0).cloned();
+            queue.extend(commit.parents.iter().cloned());
+            commits.insert(hash, commit);
+            self.check_history_depth(commits.len())?;
         }
-        Ok(history)
+        newest_first(start, commits)
     }
 }
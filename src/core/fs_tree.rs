@@ -0,0 +1,122 @@
+use crate::core::database::{diff_states, CommitStorage};
+use crate::core::models::Change;
+use crate::error::{GitDBError, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Makes a table name or row id safe to use as a single path component.
+/// Bytes outside `[A-Za-z0-9_.-]` become `%XX`, as does a leading `.` so no
+/// name turns into `.`, `..` or a hidden file.
+fn encode_component(name: &str) -> Result<String> {
+    if name.is_empty() {
+        return Err(GitDBError::InvalidInput("Cannot export an empty table name or id".into()));
+    }
+    let mut encoded = String::with_capacity(name.len());
+    for (i, byte) in name.bytes().enumerate() {
+        let plain = byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' || (byte == b'.' && i > 0);
+        if plain {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Ok(encoded)
+}
+
+/// Reverses `encode_component`.
+fn decode_component(encoded: &std::ffi::OsStr) -> Result<String> {
+    let invalid = || GitDBError::InvalidInput(format!("'{}' is not an exported name", encoded.to_string_lossy()));
+    let encoded = encoded.to_str().ok_or_else(invalid)?;
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Reads a directory laid out by `export_tree_to_dir` back into rows.
+fn read_tree_dir(dir: &Path) -> Result<HashMap<String, HashMap<String, Vec<u8>>>> {
+    let mut tables = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            return Err(GitDBError::InvalidInput(format!(
+                "Unexpected file {} outside a table directory", entry.path().display()
+            )));
+        }
+        let mut rows = HashMap::new();
+        for row in fs::read_dir(entry.path())? {
+            let row = row?;
+            if !row.file_type()?.is_file() {
+                return Err(GitDBError::InvalidInput(format!(
+                    "Unexpected entry {} in a table directory", row.path().display()
+                )));
+            }
+            rows.insert(decode_component(&row.file_name())?, fs::read(row.path())?);
+        }
+        tables.insert(decode_component(&entry.file_name())?, rows);
+    }
+    Ok(tables)
+}
+
+impl CommitStorage {
+    /// Writes the rows at `commit` under `dir`, one file per row at
+    /// `<dir>/<table>/<id>` holding the value in the same encoding as
+    /// `Change` values. Names are escaped as needed; see `encode_component`.
+    ///
+    /// `dir` must be empty or not exist yet, so the export holds nothing but
+    /// the rows at `commit` and can be imported back as is.
+    pub fn export_tree_to_dir(&self, commit: [u8; 32], dir: &Path) -> Result<()> {
+        if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+            return Err(GitDBError::InvalidInput(format!("Export directory {} is not empty", dir.display())));
+        }
+        fs::create_dir_all(dir)?;
+        for (table, rows) in self.checkout_state(commit)? {
+            let table_dir = dir.join(encode_component(&table)?);
+            fs::create_dir_all(&table_dir)?;
+            for (id, value) in rows {
+                fs::write(table_dir.join(encode_component(&id)?), value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits the rows found under `dir`, laid out as `export_tree_to_dir`
+    /// writes them, relative to HEAD: new and changed files become inserts
+    /// and updates, missing files become deletes, and table directories
+    /// that appeared or disappeared become schema changes.
+    pub fn import_tree_from_dir(&self, dir: &Path, message: &str) -> Result<[u8; 32]> {
+        let current = match self.get_head()? {
+            Some(head) => self.checkout_state(head)?,
+            None => HashMap::new(),
+        };
+        let imported = read_tree_dir(dir)?;
+
+        let tables: BTreeSet<&String> = current.keys().chain(imported.keys()).collect();
+        let empty = HashMap::new();
+        let mut changes = Vec::new();
+        for table in tables {
+            let before = current.get(table);
+            let after = imported.get(table);
+            if before.is_none() {
+                changes.push(Change::schema_add(table));
+            }
+            changes.extend(diff_states(before.unwrap_or(&empty), after.unwrap_or(&empty), table));
+            if after.is_none() {
+                changes.push(Change::schema_drop(table));
+            }
+        }
+        self.create_commit(message, changes)
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::{GitDBError, Result};
+use sha2::Digest;
+use std::sync::Arc;
+
+/// An in-progress hash computation.
+pub trait HashState {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> [u8; 32];
+}
+
+/// The algorithm behind commit hashes, commit checksums and tree hashes.
+pub trait Hasher: Send + Sync {
+    fn algorithm(&self) -> ChecksumAlgorithm;
+    fn start(&self) -> Box<dyn HashState>;
+
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        let mut state = self.start();
+        state.update(data);
+        state.finish()
+    }
+}
+
+pub struct Blake3Hasher;
+
+impl HashState for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        *self.finalize().as_bytes()
+    }
+}
+
+impl Hasher for Blake3Hasher {
+    fn algorithm(&self) -> ChecksumAlgorithm {
+        ChecksumAlgorithm::Blake3
+    }
+
+    fn start(&self) -> Box<dyn HashState> {
+        Box::new(blake3::Hasher::new())
+    }
+}
+
+pub struct Sha256Hasher;
+
+impl HashState for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        (*self).finalize().into()
+    }
+}
+
+impl Hasher for Sha256Hasher {
+    fn algorithm(&self) -> ChecksumAlgorithm {
+        ChecksumAlgorithm::Sha256
+    }
+
+    fn start(&self) -> Box<dyn HashState> {
+        Box::new(sha2::Sha256::new())
+    }
+}
+
+/// Which `Hasher` a repository uses. Recorded in the database on first open
+/// so every later open hashes the same way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Blake3 => "blake3",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            other => Err(GitDBError::CorruptData(format!("Unknown checksum algorithm '{}'", other))),
+        }
+    }
+
+    pub fn hasher(self) -> Arc<dyn Hasher> {
+        match self {
+            ChecksumAlgorithm::Blake3 => Arc::new(Blake3Hasher),
+            ChecksumAlgorithm::Sha256 => Arc::new(Sha256Hasher),
+        }
+    }
+}
@@ -1,6 +1,7 @@
-use crate::core::crdt::CrdtEngine;
-use crate::core::models::Change;
-use crate::error::Result;
+use crate::core::crdt::{CrdtEngine, CrdtValue};
+use crate::core::models::{Change, Conflict};
+use std::collections::{BTreeMap, BTreeSet};
+use crate::error::{GitDBError, Result};
 
 pub fn merge_states(state1: &mut CrdtEngine, state2: &CrdtEngine) -> Result<Vec<Change>> {
     let mut changes = Vec::new();
@@ -13,21 +14,13 @@ pub fn merge_states(state1: &mut CrdtEngine, state2: &CrdtEngine) -> Result<Vec<
                 Some(local_val) => {
                     if local_val != value {
                         local_rows.insert(id.clone(), value.clone());
-                        changes.push(Change::Update {
-                            table: table.clone(),
-                            id: id.clone(),
-                            value: bincode::serialize(value)?,
-                        });
+                        changes.push(Change::update_value(table, id, value)?.with_clock(state2.clock(table, id)));
                     }
                     // else: unchanged, skip
                 }
                 None => {
                     local_rows.insert(id.clone(), value.clone());
-                    changes.push(Change::Insert {
-                        table: table.clone(),
-                        id: id.clone(),
-                        value: bincode::serialize(value)?,
-                    });
+                    changes.push(Change::insert_value(table, id, value)?);
                 }
             }
         }
@@ -35,3 +28,171 @@ pub fn merge_states(state1: &mut CrdtEngine, state2: &CrdtEngine) -> Result<Vec<
 
     Ok(changes)
 }
+
+/// Merges `theirs` into `ours` relative to their common `base` state.
+///
+/// Rows only one side touched take that side's value; rows both sides changed
+/// are joined with CRDT semantics. Returns the merged state along with the
+/// changes that turn `ours` into it.
+pub fn three_way_merge(base: &CrdtEngine, ours: &CrdtEngine, theirs: &CrdtEngine) -> Result<(CrdtEngine, Vec<Change>)> {
+    let mut merged = ours.clone();
+    let mut changes = Vec::new();
+
+    for (table, rows) in &theirs.state {
+        let base_rows = base.state.get(table);
+        if base_rows.is_none() && !merged.state.contains_key(table) {
+            changes.push(Change::schema_add(table));
+        }
+        let merged_rows = merged.state.entry(table.clone()).or_default();
+
+        for (id, their_val) in rows {
+            let base_val = base_rows.and_then(|r| r.get(id));
+            if base_val == Some(their_val) {
+                continue;
+            }
+
+            match merged_rows.get_mut(id) {
+                None => {
+                    merged_rows.insert(id.clone(), their_val.clone());
+                    changes.push(Change::insert_value(table, id, their_val)?);
+                }
+                Some(our_val) if our_val == their_val => {}
+                Some(our_val) => {
+                    let our_clock = ours.clock(table, id);
+                    let their_clock = theirs.clock(table, id);
+                    let before = our_val.clone();
+
+                    if base_val == Some(&*our_val) {
+                        *our_val = their_val.clone();
+                    } else if let Some(merged) = merge_field_rows(base_val, our_val, their_val)? {
+                        *our_val = merged;
+                    } else {
+                        // Both sides changed the row: the higher logical clock wins,
+                        // otherwise fall back to the CRDT join.
+                        match (our_clock, their_clock) {
+                            (Some(o), Some(t)) if t > o => *our_val = their_val.clone(),
+                            (Some(o), Some(t)) if o > t => {}
+                            _ => {
+                                our_val.join(their_val).map_err(|_| {
+                                    GitDBError::TypeMismatch(format!("Type mismatch on merge for ID: {}", id))
+                                })?;
+                            }
+                        }
+                    }
+
+                    if *our_val != before {
+                        changes.push(Change::update_value(table, id, our_val)?.with_clock(our_clock.max(their_clock)));
+                    }
+                }
+            }
+        }
+    }
+
+    // Rows deleted on their side are dropped unless we changed them since the base.
+    for (table, rows) in &base.state {
+        let their_rows = theirs.state.get(table);
+        let Some(merged_rows) = merged.state.get_mut(table) else {
+            continue;
+        };
+
+        for (id, base_val) in rows {
+            if their_rows.is_some_and(|r| r.contains_key(id)) {
+                continue;
+            }
+            if merged_rows.get(id) == Some(base_val) {
+                merged_rows.remove(id);
+                changes.push(Change::delete(table, id));
+            }
+        }
+    }
+
+    Ok((merged, changes))
+}
+
+/// Lists rows that `ours` and `theirs` both changed relative to `base` but to
+/// different values, sorted by table and id.
+pub fn find_conflicts(base: &CrdtEngine, ours: &CrdtEngine, theirs: &CrdtEngine) -> Result<Vec<Conflict>> {
+    let mut rows = BTreeSet::new();
+    for engine in [base, ours, theirs] {
+        for (table, table_rows) in &engine.state {
+            rows.extend(table_rows.keys().map(|id| (table.as_str(), id.as_str())));
+        }
+    }
+
+    let lookup = |engine: &CrdtEngine, table: &str, id: &str| {
+        engine.state.get(table).and_then(|r| r.get(id)).cloned()
+    };
+
+    let mut conflicts = Vec::new();
+    for (table, id) in rows {
+        let base_val = lookup(base, table, id);
+        let our_val = lookup(ours, table, id);
+        let their_val = lookup(theirs, table, id);
+
+        let both_changed = our_val != base_val && their_val != base_val && our_val != their_val;
+        let conflicting = match (&our_val, &their_val) {
+            (Some(CrdtValue::Fields(ours)), Some(CrdtValue::Fields(theirs))) => {
+                both_changed && fields_conflict(base_fields(base_val.as_ref()), ours, theirs)
+            }
+            _ => both_changed,
+        };
+        if conflicting {
+            conflicts.push(Conflict {
+                table: table.to_string(),
+                id: id.to_string(),
+                ours: our_val.map(|v| bincode::serialize(&v)).transpose()?,
+                theirs: their_val.map(|v| bincode::serialize(&v)).transpose()?,
+            });
+        }
+    }
+    Ok(conflicts)
+}
+
+fn base_fields(base: Option<&CrdtValue>) -> Option<&BTreeMap<String, CrdtValue>> {
+    match base {
+        Some(CrdtValue::Fields(fields)) => Some(fields),
+        _ => None,
+    }
+}
+
+/// Three-way merges two field rows one field at a time, so edits to different
+/// fields both survive. Returns `None` unless both sides are field rows.
+fn merge_field_rows(base: Option<&CrdtValue>, ours: &CrdtValue, theirs: &CrdtValue) -> Result<Option<CrdtValue>> {
+    let (CrdtValue::Fields(our_fields), CrdtValue::Fields(their_fields)) = (ours, theirs) else {
+        return Ok(None);
+    };
+    let base_fields = base_fields(base);
+
+    let mut merged = our_fields.clone();
+    for (field, their_val) in their_fields {
+        let base_val = base_fields.and_then(|f| f.get(field));
+        if base_val == Some(their_val) {
+            continue;
+        }
+        match merged.get_mut(field) {
+            None => {
+                merged.insert(field.clone(), their_val.clone());
+            }
+            Some(our_val) if base_val == Some(&*our_val) => *our_val = their_val.clone(),
+            Some(our_val) => our_val.join(their_val).map_err(|_| {
+                GitDBError::TypeMismatch(format!("Type mismatch on merge for field: {}", field))
+            })?,
+        }
+    }
+    Ok(Some(CrdtValue::Fields(merged)))
+}
+
+/// Whether any single field was changed differently by both sides.
+fn fields_conflict(
+    base: Option<&BTreeMap<String, CrdtValue>>,
+    ours: &BTreeMap<String, CrdtValue>,
+    theirs: &BTreeMap<String, CrdtValue>,
+) -> bool {
+    let fields: BTreeSet<&String> = ours.keys().chain(theirs.keys()).collect();
+    fields.into_iter().any(|field| {
+        let base_val = base.and_then(|f| f.get(field));
+        let our_val = ours.get(field);
+        let their_val = theirs.get(field);
+        our_val != base_val && their_val != base_val && our_val != their_val
+    })
+}
@@ -3,4 +3,11 @@ pub mod database;
 pub mod crdt;
 pub mod branch;
 pub mod merge;
-pub mod query;
\ No newline at end of file
+pub mod query;
+pub mod archive;
+pub mod backend;
+pub mod hashing;
+pub mod codec;
+pub mod clock;
+pub mod fs_tree;
+pub mod builder;
\ No newline at end of file
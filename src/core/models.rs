@@ -1,5 +1,7 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use crate::core::crdt::CrdtValue;
+use crate::error::Result;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commit {
@@ -7,7 +9,199 @@ pub struct Commit {
     pub message: String,
     pub timestamp: u64,
     pub changes: Vec<Change>,
-    pub tree: HashMap<String, [u8; 32]>, 
+    /// Ordered so a commit always serializes, and so hashes, the same way.
+    pub tree: BTreeMap<String, [u8; 32]>,
+    pub author: String,
+    pub email: String,
+    /// ed25519 signature over the commit hash, if the commit was signed.
+    pub signature: Option<Vec<u8>>,
+    /// Free-form application metadata such as a build id or ticket number.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Per-table counts of the changes between two commits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffStats {
+    pub inserts: usize,
+    pub updates: usize,
+    pub deletes: usize,
+}
+
+impl std::ops::AddAssign for DiffStats {
+    fn add_assign(&mut self, other: DiffStats) {
+        self.inserts += other.inserts;
+        self.updates += other.updates;
+        self.deletes += other.deletes;
+    }
+}
+
+/// A run of consecutive commits by one author, as listed by `changelog`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangelogGroup {
+    pub author: String,
+    pub email: String,
+    /// Messages of the commits in the run, oldest first.
+    pub messages: Vec<String>,
+    /// Per-table change counts summed over the run.
+    pub stats: HashMap<String, DiffStats>,
+}
+
+/// One movement of HEAD, as recorded in the reflog.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReflogEntry {
+    pub timestamp: u64,
+    pub old: Option<[u8; 32]>,
+    pub new: [u8; 32],
+    pub reason: String,
+    /// Branch HEAD was attached to before the move; `None` if it was detached.
+    pub old_branch: Option<String>,
+    /// Branch whose ref the move rewrote along with HEAD, if any. Checkouts
+    /// only move HEAD and leave this empty.
+    pub branch: Option<String>,
+}
+
+/// Reflog entry layout written before entries recorded branches. Only used
+/// to keep older reflogs readable; such entries undo as plain HEAD moves.
+#[derive(Deserialize)]
+pub(crate) struct BranchlessReflogEntry {
+    timestamp: u64,
+    old: Option<[u8; 32]>,
+    new: [u8; 32],
+    reason: String,
+}
+
+impl From<BranchlessReflogEntry> for ReflogEntry {
+    fn from(old: BranchlessReflogEntry) -> Self {
+        ReflogEntry {
+            timestamp: old.timestamp,
+            old: old.old,
+            new: old.new,
+            reason: old.reason,
+            old_branch: None,
+            branch: None,
+        }
+    }
+}
+
+/// A problem found by `CommitStorage::fsck`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegrityIssue {
+    /// The stored blob is too short or its trailing checksum does not match.
+    BadChecksum { hash: [u8; 32] },
+    /// The checksum holds but the payload does not decode as any commit layout.
+    Undecodable { hash: [u8; 32], reason: String },
+    MissingParent { commit: [u8; 32], parent: [u8; 32] },
+    /// The rows stored for `table` no longer hash to the commit's tree entry.
+    TreeMismatch { commit: [u8; 32], table: String },
+}
+
+/// A commit together with the hash it is stored under.
+#[derive(Debug, Clone)]
+pub struct CommitRecord {
+    pub hash: [u8; 32],
+    pub commit: Commit,
+}
+
+pub const UNKNOWN_AUTHOR: &str = "unknown";
+
+/// Commit layout written before author metadata and update clocks existed.
+/// Only used to keep older databases readable.
+#[derive(Deserialize)]
+pub(crate) struct LegacyCommit {
+    parents: Vec<[u8; 32]>,
+    message: String,
+    timestamp: u64,
+    changes: Vec<LegacyChange>,
+    tree: BTreeMap<String, [u8; 32]>,
+}
+
+#[derive(Deserialize)]
+enum LegacyChange {
+    Insert { table: String, id: String, value: Vec<u8> },
+    Update { table: String, id: String, value: Vec<u8> },
+    Delete { table: String, id: String },
+}
+
+impl From<LegacyCommit> for Commit {
+    fn from(legacy: LegacyCommit) -> Self {
+        let changes = legacy.changes.into_iter()
+            .map(|c| match c {
+                LegacyChange::Insert { table, id, value } => Change::Insert { table, id, value },
+                LegacyChange::Update { table, id, value } => Change::Update { table, id, value, clock: None },
+                LegacyChange::Delete { table, id } => Change::Delete { table, id },
+            })
+            .collect();
+
+        Commit {
+            parents: legacy.parents,
+            message: legacy.message,
+            timestamp: legacy.timestamp,
+            changes,
+            tree: legacy.tree,
+            author: UNKNOWN_AUTHOR.to_string(),
+            email: String::new(),
+            signature: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// Commit layout written after author metadata was added but before signing.
+#[derive(Deserialize)]
+pub(crate) struct UnsignedCommit {
+    parents: Vec<[u8; 32]>,
+    message: String,
+    timestamp: u64,
+    changes: Vec<Change>,
+    tree: BTreeMap<String, [u8; 32]>,
+    author: String,
+    email: String,
+}
+
+impl From<UnsignedCommit> for Commit {
+    fn from(unsigned: UnsignedCommit) -> Self {
+        Commit {
+            parents: unsigned.parents,
+            message: unsigned.message,
+            timestamp: unsigned.timestamp,
+            changes: unsigned.changes,
+            tree: unsigned.tree,
+            author: unsigned.author,
+            email: unsigned.email,
+            signature: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// Commit layout written after signing was added but before metadata.
+#[derive(Deserialize)]
+pub(crate) struct PreMetadataCommit {
+    parents: Vec<[u8; 32]>,
+    message: String,
+    timestamp: u64,
+    changes: Vec<Change>,
+    tree: BTreeMap<String, [u8; 32]>,
+    author: String,
+    email: String,
+    signature: Option<Vec<u8>>,
+}
+
+impl From<PreMetadataCommit> for Commit {
+    fn from(old: PreMetadataCommit) -> Self {
+        Commit {
+            parents: old.parents,
+            message: old.message,
+            timestamp: old.timestamp,
+            changes: old.changes,
+            tree: old.tree,
+            author: old.author,
+            email: old.email,
+            signature: old.signature,
+            metadata: BTreeMap::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,19 +210,239 @@ pub struct CommitMetadata {
     pub head: [u8; 32],                    
 }
 
+/// One field that differs between two versions of a row. Values use the same
+/// encoding as `Change` values; `None` means the field is absent on that side.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Option<Vec<u8>>,
+    pub to: Option<Vec<u8>>,
+}
+
+/// A row both sides of a merge changed differently relative to their base.
+/// Values are in the same encoding as `Change` values; `None` means deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conflict {
+    pub table: String,
+    pub id: String,
+    pub ours: Option<Vec<u8>>,
+    pub theirs: Option<Vec<u8>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Change {
     Insert { table: String, id: String, value: Vec<u8> },
-    Update { table: String, id: String, value: Vec<u8> },
+    /// `clock` is an optional logical timestamp; when present, merges keep the
+    /// update with the higher clock instead of relying on apply order.
+    Update { table: String, id: String, value: Vec<u8>, clock: Option<u64> },
     Delete { table: String, id: String },
+    /// A table came into existence, independent of any row in it.
+    SchemaAdd { table: String },
+    /// A table and all of its rows went away.
+    SchemaDrop { table: String },
+    /// Sets one field of a row, creating the row if needed, without touching
+    /// its other fields. `value` uses the same encoding as other change values.
+    FieldUpdate { table: String, id: String, field: String, value: Vec<u8> },
 }
 
 impl Change {
+    /// Insert of any serializable value, stored as a CRDT register.
+    pub fn insert<T: Serialize>(table: impl Into<String>, id: impl Into<String>, value: &T) -> Result<Change> {
+        Self::insert_value(table, id, &CrdtValue::Register(bincode::serialize(value)?))
+    }
+
+    /// Update of any serializable value, stored as a CRDT register.
+    pub fn update<T: Serialize>(table: impl Into<String>, id: impl Into<String>, value: &T) -> Result<Change> {
+        Self::update_value(table, id, &CrdtValue::Register(bincode::serialize(value)?))
+    }
+
+    pub fn insert_value(table: impl Into<String>, id: impl Into<String>, value: &CrdtValue) -> Result<Change> {
+        Ok(Change::Insert {
+            table: table.into(),
+            id: id.into(),
+            value: bincode::serialize(value)?,
+        })
+    }
+
+    pub fn update_value(table: impl Into<String>, id: impl Into<String>, value: &CrdtValue) -> Result<Change> {
+        Ok(Change::Update {
+            table: table.into(),
+            id: id.into(),
+            value: bincode::serialize(value)?,
+            clock: None,
+        })
+    }
+
+    pub fn delete(table: impl Into<String>, id: impl Into<String>) -> Change {
+        Change::Delete {
+            table: table.into(),
+            id: id.into(),
+        }
+    }
+
+    pub fn field_update(table: impl Into<String>, id: impl Into<String>, field: impl Into<String>, value: &CrdtValue) -> Result<Change> {
+        Ok(Change::FieldUpdate {
+            table: table.into(),
+            id: id.into(),
+            field: field.into(),
+            value: bincode::serialize(value)?,
+        })
+    }
+
+    pub fn schema_add(table: impl Into<String>) -> Change {
+        Change::SchemaAdd { table: table.into() }
+    }
+
+    pub fn schema_drop(table: impl Into<String>) -> Change {
+        Change::SchemaDrop { table: table.into() }
+    }
+
+    /// Sets the logical clock of an update; other changes are returned as is.
+    pub fn with_clock(mut self, new_clock: Option<u64>) -> Change {
+        if let Change::Update { clock, .. } = &mut self {
+            *clock = new_clock;
+        }
+        self
+    }
+
     pub fn table(&self) -> &str {
         match self {
             Change::Insert { table, .. } => table,
             Change::Update { table, .. } => table,
             Change::Delete { table, .. } => table,
+            Change::SchemaAdd { table } => table,
+            Change::SchemaDrop { table } => table,
+            Change::FieldUpdate { table, .. } => table,
+        }
+    }
+
+    /// Row id the change applies to. Schema changes have none and return "".
+    pub fn id(&self) -> &str {
+        match self {
+            Change::Insert { id, .. } => id,
+            Change::Update { id, .. } => id,
+            Change::Delete { id, .. } => id,
+            Change::FieldUpdate { id, .. } => id,
+            Change::SchemaAdd { .. } | Change::SchemaDrop { .. } => "",
+        }
+    }
+
+    pub fn is_schema(&self) -> bool {
+        matches!(self, Change::SchemaAdd { .. } | Change::SchemaDrop { .. })
+    }
+}
+
+/// Collapses a sequence of changes so each row appears at most once with its
+/// net effect, in order of first appearance. `absent(table, id)` tells whether
+/// a row is known not to exist before the changes: an insert followed by a
+/// delete of such a row cancels out entirely, while for any other row it
+/// becomes the delete.
+///
+/// Replaying the result leaves every row with the same value as replaying the
+/// input; cancelled rows may just miss the tombstone the delete would have
+/// left. Where two changes cannot be folded without changing a value (field
+/// updates, clocked updates that might lose to the stored clock, anything
+/// across a schema change to the table) the later change starts a new entry
+/// instead.
+pub fn coalesce_changes(changes: impl IntoIterator<Item = Change>, absent: impl Fn(&str, &str) -> bool) -> Vec<Change> {
+    let mut slots: Vec<Option<Change>> = Vec::new();
+    let mut open: HashMap<(String, String), usize> = HashMap::new();
+
+    for change in changes {
+        if change.is_schema() {
+            open.retain(|(table, _), _| table != change.table());
+            slots.push(Some(change));
+            continue;
         }
+        let key = (change.table().to_string(), change.id().to_string());
+        if let Some(&slot) = open.get(&key) {
+            if let Some(folded) = fold_row_change(slots[slot].as_ref(), &change, absent(&key.0, &key.1)) {
+                slots[slot] = folded;
+                continue;
+            }
+        }
+        open.insert(key, slots.len());
+        slots.push(Some(change));
+    }
+
+    slots.into_iter().flatten().collect()
+}
+
+/// The single change equivalent to `previous` (`None` if it cancelled out)
+/// followed by `next`, or `None` if no single change is. `absent` is whether
+/// the row is known not to exist before `previous`.
+fn fold_row_change(previous: Option<&Change>, next: &Change, absent: bool) -> Option<Option<Change>> {
+    match (previous, next) {
+        (_, Change::FieldUpdate { .. }) | (Some(Change::FieldUpdate { .. }), _) => None,
+        (Some(Change::Insert { .. }), Change::Delete { .. }) if absent => Some(None),
+        (Some(Change::Insert { table, id, .. }), Change::Update { value, clock: None, .. }) => {
+            Some(Some(Change::Insert { table: table.clone(), id: id.clone(), value: value.clone() }))
+        }
+        (Some(Change::Delete { table, id }), Change::Insert { value, .. }) if absent => {
+            Some(Some(Change::Insert { table: table.clone(), id: id.clone(), value: value.clone() }))
+        }
+        (Some(Change::Delete { table, id }), Change::Insert { value, .. }) => {
+            Some(Some(Change::Update { table: table.clone(), id: id.clone(), value: value.clone(), clock: None }))
+        }
+        // An update with a clock may lose to the stored one, so it only folds
+        // over a change whose own outcome it is sure to override.
+        (Some(Change::Update { clock: Some(before), .. }), Change::Update { clock: Some(after), .. }) if after > before => {
+            Some(Some(next.clone()))
+        }
+        (Some(Change::Update { clock: Some(_), .. }), Change::Insert { .. } | Change::Update { .. }) => None,
+        (Some(_), Change::Update { clock: Some(_), .. }) => None,
+        (_, next) => Some(Some(next.clone())),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::crdt::CrdtEngine;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Person {
+        name: String,
+        age: u32,
+    }
+
+    fn stored_person(engine: &CrdtEngine, id: &str) -> Person {
+        match &engine.table("people").unwrap()[id] {
+            CrdtValue::Register(bytes) => bincode::deserialize(bytes).unwrap(),
+            other => panic!("expected a register, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn typed_changes_round_trip_through_the_engine() {
+        let ada = Person { name: "Ada".into(), age: 36 };
+        let older = Person { name: "Ada".into(), age: 37 };
+        let mut engine = CrdtEngine::new();
+
+        engine.apply_change(&Change::insert("people", "1", &ada).unwrap()).unwrap();
+        assert_eq!(stored_person(&engine, "1"), ada);
+
+        engine.apply_change(&Change::update("people", "1", &older).unwrap()).unwrap();
+        assert_eq!(stored_person(&engine, "1"), older);
+    }
+
+    #[test]
+    fn insert_then_delete_only_cancels_for_absent_rows() {
+        let ada = Person { name: "Ada".into(), age: 36 };
+        let changes = || vec![Change::insert("people", "1", &ada).unwrap(), Change::delete("people", "1")];
+
+        assert!(coalesce_changes(changes(), |_, _| true).is_empty());
+        let kept = coalesce_changes(changes(), |_, _| false);
+        assert_eq!(kept.len(), 1);
+        assert!(matches!(&kept[0], Change::Delete { table, id } if table == "people" && id == "1"));
+    }
+
+    #[test]
+    fn delete_then_insert_folds_by_whether_the_row_existed() {
+        let ada = Person { name: "Ada".into(), age: 36 };
+        let changes = || vec![Change::delete("people", "1"), Change::insert("people", "1", &ada).unwrap()];
+
+        assert!(matches!(coalesce_changes(changes(), |_, _| true)[..], [Change::Insert { .. }]));
+        assert!(matches!(coalesce_changes(changes(), |_, _| false)[..], [Change::Update { clock: None, .. }]));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::core::models::Commit;
 use crate::core::crdt::CrdtEngine;
+use crate::core::database::{parse_hash, CommitStorage};
 use crate::error::{GitDBError, Result};
-use rocksdb::DB;
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
 use sqlparser::ast::{Statement, Query, SetExpr};
@@ -9,12 +9,12 @@ use std::collections::HashMap;
 use crate::core::crdt::CrdtValue;
 
 pub struct QueryProcessor<'a> {
-    db: &'a DB
+    storage: &'a CommitStorage
 }
 
 impl<'a> QueryProcessor<'a> {
-    pub fn new(db: &'a DB) -> Self {
-        QueryProcessor { db }
+    pub fn new(storage: &'a CommitStorage) -> Self {
+        QueryProcessor { storage }
     }
 
     pub fn execute(&self, sql: &str) -> Result<()> {
@@ -34,9 +34,7 @@ impl<'a> QueryProcessor<'a> {
         let commit = self.get_commit_by_hash(&commit_hash)?;
 
         let mut engine = CrdtEngine::new();
-        for change in &commit.changes {
-            engine.apply_change(change)?;
-        }
+        engine.apply_changes(&commit.changes)?;
 
         if let Some(rows) = engine.into_data().remove(&table) {
             for (id, value) in rows {
@@ -54,7 +52,7 @@ impl<'a> QueryProcessor<'a> {
             return Err(GitDBError::InvalidInput("Expected SELECT statement".into()));
         };
 
-        let from = select.from.get(0)
+        let from = select.from.first()
             .ok_or_else(|| GitDBError::InvalidInput("Missing FROM clause".into()))?;
 
         let table_name = from.relation.to_string();
@@ -63,7 +61,7 @@ impl<'a> QueryProcessor<'a> {
             return Err(GitDBError::InvalidInput("Missing WITH clause".into()));
         };
 
-        let cte = with.cte_tables.get(0)
+        let cte = with.cte_tables.first()
             .ok_or_else(|| GitDBError::InvalidInput("Missing CTE in WITH clause".into()))?;
 
         let commit_hash = cte.alias.name.to_string();
@@ -71,15 +69,11 @@ impl<'a> QueryProcessor<'a> {
     }
 
     fn get_commit_by_hash(&self, hex_str: &str) -> Result<Commit> {
-        let raw_hash = hex::decode(hex_str)
-            .map_err(|_| GitDBError::InvalidInput("Bad hex string for hash".into()))?;
-
-        let commit_bytes = self.db.get(&raw_hash)
-            .map_err(GitDBError::StorageError)?
-            .ok_or_else(|| GitDBError::InvalidInput("Hash not found".into()))?;
-
-        let parsed: Commit = bincode::deserialize(&commit_bytes)?;
-        Ok(parsed)
+        let raw_hash = parse_hash(hex_str)?;
+        if !self.storage.commit_exists(raw_hash)? {
+            return Err(GitDBError::InvalidInput("Hash not found".into()));
+        }
+        self.storage.get_commit_by_hash(&raw_hash)
     }
 
     pub fn get_table_at_commit(&self, table: &str, commit_hash: &[u8]) -> Result<HashMap<String, CrdtValue>> {
@@ -107,15 +101,14 @@ impl<'a> QueryProcessor<'a> {
                 }
             }
             
-            current_hash = commit.parents.get(0).map(|p| p.to_vec()).unwrap_or_default();
+            current_hash = commit.parents.first().map(|p| p.to_vec()).unwrap_or_default();
         }
         
         Ok(engine.state.get(table).cloned().unwrap_or_default())
     }
 
     pub fn get_head_hash(&self) -> Result<Vec<u8>> {
-        self.db.get(b"HEAD")
-            .map_err(GitDBError::StorageError)?
+        self.storage.db.get(b"HEAD")?
             .ok_or_else(|| GitDBError::InvalidInput("No HEAD commit".into()))
     }
 }
\ No newline at end of file
@@ -13,6 +13,8 @@ pub enum GitDBError {
     IoError(String),
     JsonError(serde_json::Error),
     CorruptData(String),
+    Conflict(String),
+    NoOpCommit,
 }
 
 pub type Result<T> = std::result::Result<T, GitDBError>;
@@ -30,6 +32,8 @@ impl fmt::Display for GitDBError {
             GitDBError::IoError(s) => write!(f, "IO error: {}", s),
             GitDBError::JsonError(e) => write!(f, "JSON error: {}", e),
             GitDBError::CorruptData(s) => write!(f, "Data corruption detected: {}", s),
+            GitDBError::Conflict(s) => write!(f, "Conflict: {}", s),
+            GitDBError::NoOpCommit => write!(f, "Commit does not change any data"),
         }
     }
 }
@@ -72,6 +76,6 @@ impl From<serde_json::Error> for GitDBError {
 
 impl From<SystemTimeError> for GitDBError {
     fn from(err: SystemTimeError) -> GitDBError {
-        GitDBError::IoError(err.to_string())
+        GitDBError::InvalidInput(format!("system clock before UNIX epoch by {:?}", err.duration()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,303 @@
+mod common;
+
+use common::*;
+use gitdb::core::database::CommitStorage;
+use gitdb::error::GitDBError;
+
+#[test]
+fn commits_advance_the_current_branch() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+
+    let tip = storage.create_commit("on feature", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(tip));
+    assert_eq!(
+        storage.list_branches().unwrap(),
+        vec![("feature".to_string(), tip), ("main".to_string(), root)]
+    );
+}
+
+#[test]
+fn commit_on_detached_head_moves_no_branch() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.detach_head(&root).unwrap();
+
+    let detached = storage.create_commit("detached", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(detached));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(root));
+    assert_eq!(storage.get_commit_by_hash(&detached).unwrap().parents, vec![root]);
+}
+
+#[test]
+fn tags_resolve_and_cannot_be_recreated() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+    storage.create_tag("v1", first).unwrap();
+    storage.create_tag("v0", first).unwrap();
+
+    assert_eq!(storage.resolve_tag("v1").unwrap(), first);
+    assert_eq!(storage.list_tags().unwrap(), vec!["v0".to_string(), "v1".to_string()]);
+    assert!(matches!(storage.create_tag("v1", second), Err(GitDBError::InvalidInput(_))));
+    assert_eq!(storage.resolve_tag("v1").unwrap(), first);
+    assert!(storage.resolve_tag("v2").is_err());
+}
+
+#[test]
+fn references_resolve_by_hash_branch_tag_and_head() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+    storage.create_branch("stable", first).unwrap();
+    storage.create_tag("v1", first).unwrap();
+
+    assert_eq!(storage.resolve_ref(&hex::encode(first)).unwrap(), first);
+    assert_eq!(storage.resolve_ref("stable").unwrap(), first);
+    assert_eq!(storage.resolve_ref("v1").unwrap(), first);
+    assert_eq!(storage.resolve_ref("HEAD").unwrap(), second);
+    assert!(matches!(storage.resolve_ref("nope"), Err(GitDBError::InvalidInput(_))));
+}
+
+#[test]
+fn hex_looking_and_shared_names_resolve_in_order() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+    // Valid hex, but not a full hash, so it can only be a branch name.
+    storage.create_branch("deadbeef", first).unwrap();
+    // A branch shadows a tag of the same name.
+    storage.create_branch("release", second).unwrap();
+    storage.create_tag("release", first).unwrap();
+
+    assert_eq!(storage.resolve_ref("deadbeef").unwrap(), first);
+    assert_eq!(storage.resolve_ref("release").unwrap(), second);
+    assert!(storage.resolve_ref(&hex::encode([9u8; 32])).is_err());
+}
+
+#[test]
+fn deleting_a_branch_other_than_the_current_one() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    assert_eq!(storage.delete_branch("feature").unwrap(), None);
+
+    assert_eq!(storage.get_branch("feature").unwrap(), None);
+    assert_eq!(storage.list_branches().unwrap(), vec![("main".to_string(), root)]);
+    assert!(matches!(storage.delete_branch("feature"), Err(GitDBError::InvalidInput(_))));
+}
+
+#[test]
+fn deleting_the_current_branch_is_refused() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    match storage.delete_branch("main") {
+        Err(GitDBError::InvalidInput(message)) => assert_eq!(message, "cannot delete current branch"),
+        other => panic!("expected a refusal, got {:?}", other),
+    }
+    assert_eq!(storage.get_branch("main").unwrap(), Some(root));
+}
+
+#[test]
+fn deleting_the_only_ref_to_a_commit_reports_it() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.create_branch("tagged", root).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let abandoned = storage.create_commit("abandoned", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("tagged").unwrap();
+    let released = storage.create_commit("released", vec![insert("users", "3", "carol")]).unwrap();
+    storage.create_tag("v1", released).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    storage.create_commit("ahead", vec![insert("users", "4", "dave")]).unwrap();
+
+    assert_eq!(storage.delete_branch("feature").unwrap(), Some(abandoned));
+    assert_eq!(storage.delete_branch("tagged").unwrap(), None);
+}
+
+#[test]
+fn undo_returns_head_to_the_previous_commit() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+
+    let reflog = storage.get_reflog().unwrap();
+    assert_eq!(reflog.len(), 2);
+    assert_eq!((reflog[1].old, reflog[1].new), (Some(first), second));
+
+    storage.undo_last_head_move().unwrap();
+    assert_eq!(storage.get_head().unwrap(), Some(first));
+
+    storage.undo_last_head_move().unwrap();
+    assert_eq!(storage.get_head().unwrap(), Some(second));
+}
+
+#[test]
+fn undoing_a_checkout_moves_no_branch() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let feature_tip = storage.create_commit("feature work", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let main_tip = storage.create_commit("main work", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+
+    storage.undo_last_head_move().unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(main_tip));
+    assert_eq!(storage.current_branch().unwrap(), Some("main".to_string()));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(main_tip));
+    assert_eq!(storage.get_branch("feature").unwrap(), Some(feature_tip));
+}
+
+#[test]
+fn undoing_a_detach_reattaches_head() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let tip = storage.create_commit("tip", vec![insert("users", "2", "bob")]).unwrap();
+    storage.detach_head(&root).unwrap();
+
+    storage.undo_last_head_move().unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(tip));
+    assert_eq!(storage.current_branch().unwrap(), Some("main".to_string()));
+    let next = storage.create_commit("next", vec![insert("users", "3", "carol")]).unwrap();
+    assert_eq!(storage.get_branch("main").unwrap(), Some(next));
+}
+
+#[test]
+fn undoing_a_commit_moves_back_only_the_branch_it_advanced() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let tip = storage.create_commit("tip", vec![insert("users", "2", "bob")]).unwrap();
+    storage.create_branch("feature", tip).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let feature_tip = storage.create_commit("feature work", vec![insert("users", "3", "carol")]).unwrap();
+
+    storage.undo_last_head_move().unwrap();
+    assert_eq!(storage.get_branch("feature").unwrap(), Some(tip));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(tip));
+    assert_eq!(storage.current_branch().unwrap(), Some("feature".to_string()));
+
+    storage.undo_last_head_move().unwrap();
+    assert_eq!(storage.get_branch("feature").unwrap(), Some(feature_tip));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(tip));
+}
+
+#[test]
+fn reflog_entries_without_branches_still_read() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let legacy = bincode::serialize(&(7u64, None::<[u8; 32]>, hash, "commit: root")).unwrap();
+    storage.db.put(format!("reflog:{:020}", 0).as_bytes(), &legacy).unwrap();
+
+    let entry = &storage.get_reflog().unwrap()[0];
+    assert_eq!((entry.timestamp, entry.new, entry.reason.as_str()), (7, hash, "commit: root"));
+    assert_eq!((entry.old_branch.clone(), entry.branch.clone()), (None, None));
+}
+
+#[test]
+fn current_branch_follows_checkouts_and_detaches() {
+    let storage = CommitStorage::open_in_memory();
+    assert_eq!(storage.current_branch().unwrap(), None);
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("feature", root).unwrap();
+
+    storage.set_head_to_branch("feature").unwrap();
+    assert_eq!(storage.current_branch().unwrap(), Some("feature".to_string()));
+
+    storage.detach_head(&root).unwrap();
+    assert_eq!(storage.current_branch().unwrap(), None);
+    assert_eq!(storage.get_head().unwrap(), Some(root));
+}
+
+#[test]
+fn resetting_a_branch_back_two_commits() {
+    let storage = CommitStorage::open_in_memory();
+    let root = commit_rows(&storage, "root", vec![insert("users", "1", "alice")]);
+    storage.create_branch("main", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    commit_rows(&storage, "second", vec![insert("users", "2", "bob")]);
+    let third = commit_rows(&storage, "third", vec![update("users", "1", "alicia")]);
+    let commits_before = storage.count_commits(None).unwrap();
+
+    storage.reset_branch("main", root, false).unwrap();
+
+    assert_eq!(storage.get_branch("main").unwrap(), Some(root));
+    assert_eq!(storage.get_head().unwrap(), Some(root));
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.get_row("users", "2").unwrap(), None);
+    assert!(storage.commit_exists(third).unwrap());
+    assert_eq!(storage.get_reflog().unwrap().last().unwrap().new, root);
+    assert_eq!(storage.count_commits(Some(third)).unwrap(), commits_before);
+}
+
+#[test]
+fn resetting_outside_the_branch_history_needs_hard() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.detach_head(&root).unwrap();
+    let elsewhere = storage.create_commit("elsewhere", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert!(matches!(storage.reset_branch("main", elsewhere, false), Err(GitDBError::InvalidInput(_))));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(root));
+
+    storage.reset_branch("main", elsewhere, true).unwrap();
+    assert_eq!(storage.get_branch("main").unwrap(), Some(elsewhere));
+}
+
+#[test]
+fn workspaces_commit_and_check_out_independently() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_workspace("tenant-a", None).unwrap();
+    storage.create_workspace("tenant-b", None).unwrap();
+
+    let a = storage.commit_in_workspace("tenant-a", "a", vec![insert("users", "1", "alice")]).unwrap();
+    let b = storage.commit_in_workspace("tenant-b", "b", vec![insert("users", "1", "bob")]).unwrap();
+    let a2 = storage.commit_in_workspace("tenant-a", "a2", vec![insert("users", "2", "carol")]).unwrap();
+    storage.checkout_workspace("tenant-a").unwrap();
+    storage.checkout_workspace("tenant-b").unwrap();
+
+    assert_eq!(storage.workspace_head("tenant-a").unwrap(), Some(a2));
+    assert_eq!(storage.workspace_head("tenant-b").unwrap(), Some(b));
+    assert_eq!(storage.parents_of(a2).unwrap(), vec![a]);
+    assert_eq!(storage.get_head().unwrap(), None);
+    assert_eq!(storage.db.get(b"ws:tenant-a:users:1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.db.get(b"ws:tenant-a:users:2").unwrap(), Some(encoded("carol")));
+    assert_eq!(storage.db.get(b"ws:tenant-b:users:1").unwrap(), Some(encoded("bob")));
+    assert_eq!(storage.db.get(b"ws:tenant-b:users:2").unwrap(), None);
+    assert_eq!(storage.get_row("users", "1").unwrap(), None);
+}
+
+#[test]
+fn ref_names_with_the_key_delimiter_are_rejected() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+
+    assert!(matches!(storage.create_branch("a:b", root), Err(GitDBError::InvalidInput(_))));
+    assert!(matches!(storage.create_tag("v1:rc", root), Err(GitDBError::InvalidInput(_))));
+    assert!(matches!(storage.create_workspace("x:y", Some(root)), Err(GitDBError::InvalidInput(_))));
+    assert!(storage.list_branches().unwrap().is_empty());
+    assert!(storage.list_tags().unwrap().is_empty());
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use common::*;
+use gitdb::core::backend::WriteBatch;
+use gitdb::core::codec::Codec;
+use gitdb::core::crdt::{CrdtEngine, CrdtValue};
+use gitdb::core::database::{CommitStorage, StorageConfig, WalEntry};
+use gitdb::core::hashing::ChecksumAlgorithm;
+use gitdb::core::models::{Change, Commit, ReflogEntry};
+use gitdb::core::query::QueryProcessor;
+use gitdb::error::{GitDBError, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Stores everything as CBOR.
+struct CborCodec;
+
+fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).map_err(|e| GitDBError::InvalidInput(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    ciborium::from_reader(bytes).map_err(|e| GitDBError::CorruptData(e.to_string()))
+}
+
+impl Codec for CborCodec {
+    fn name(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn encode(&self, commit: &Commit) -> Result<Vec<u8>> {
+        to_cbor(commit)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Commit> {
+        from_cbor(bytes)
+    }
+
+    fn encode_value(&self, value: &CrdtValue) -> Result<Vec<u8>> {
+        to_cbor(value)
+    }
+
+    fn decode_value(&self, bytes: &[u8]) -> Result<CrdtValue> {
+        from_cbor(bytes)
+    }
+
+    fn encode_change(&self, change: &Change) -> Result<Vec<u8>> {
+        to_cbor(change)
+    }
+
+    fn decode_change(&self, bytes: &[u8]) -> Result<Change> {
+        from_cbor(bytes)
+    }
+
+    fn encode_reflog(&self, entry: &ReflogEntry) -> Result<Vec<u8>> {
+        to_cbor(entry)
+    }
+
+    fn decode_reflog(&self, bytes: &[u8]) -> Result<ReflogEntry> {
+        from_cbor(bytes)
+    }
+
+    fn encode_snapshot(&self, engine: &CrdtEngine) -> Result<Vec<u8>> {
+        to_cbor(engine)
+    }
+
+    fn decode_snapshot(&self, bytes: &[u8]) -> Result<CrdtEngine> {
+        from_cbor(bytes)
+    }
+
+    fn encode_wal(&self, entry: &WalEntry) -> Result<Vec<u8>> {
+        to_cbor(entry)
+    }
+
+    fn decode_wal(&self, bytes: &[u8]) -> Result<WalEntry> {
+        from_cbor(bytes)
+    }
+}
+
+fn cbor_config() -> StorageConfig {
+    StorageConfig { codec: Some(Arc::new(CborCodec)), ..StorageConfig::default() }
+}
+
+#[test]
+fn cbor_repository_reopens_with_its_codec() {
+    let dir = TempDir::new("cbor");
+    let (root, tip) = {
+        let storage = CommitStorage::open_with_options(dir.path_str(), cbor_config()).unwrap();
+        let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+        storage.create_branch("main", root).unwrap();
+        storage.set_head_to_branch("main").unwrap();
+        storage.create_snapshot(root).unwrap();
+        let tip = storage.create_commit("tip", vec![insert("users", "2", "bob")]).unwrap();
+        storage.checkout_tables(tip, &["users"]).unwrap();
+        storage.stage_change(insert("users", "3", "carol")).unwrap();
+        (root, tip)
+    };
+
+    let storage = CommitStorage::open_with_options(dir.path_str(), cbor_config()).unwrap();
+
+    assert_eq!(storage.get_commit_by_hash(&tip).unwrap().parents, vec![root]);
+    assert_eq!(storage.checkout_state(tip).unwrap()["users"]["2"], encoded("bob"));
+    assert_eq!(format!("{:?}", storage.get_staged().unwrap()), format!("{:?}", vec![insert("users", "3", "carol")]));
+    assert_eq!(storage.get_reflog().unwrap().last().unwrap().new, tip);
+    // Stored as CBOR, handed out in the encoding of `Change` values.
+    let raw = storage.db.get(b"users:1").unwrap().unwrap();
+    assert!(raw.starts_with(&CborCodec.encode_value(&value("alice")).unwrap()));
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.get_rows("users", &["1"]).unwrap()["1"], Some(encoded("alice")));
+    assert!(storage.status(Some(tip)).unwrap().is_empty());
+
+    let rows = QueryProcessor::new(&storage).get_table_at_commit("users", &tip).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows["2"], value("bob"));
+}
+
+#[test]
+fn new_repository_takes_a_custom_codec_and_sha256_together() {
+    let dir = TempDir::new("cbor-sha256");
+    let cfg = || StorageConfig { checksum: Some(ChecksumAlgorithm::Sha256), ..cbor_config() };
+    let root = {
+        let storage = CommitStorage::open_with_options(dir.path_str(), cfg()).unwrap();
+        storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap()
+    };
+
+    let storage = CommitStorage::open_with_options(dir.path_str(), cfg()).unwrap();
+
+    assert_eq!(storage.checksum_algorithm(), ChecksumAlgorithm::Sha256);
+    assert_eq!(storage.get_commit_by_hash(&root).unwrap().message, "root");
+}
+
+#[test]
+fn custom_codec_repository_refuses_to_open_without_it() {
+    let dir = TempDir::new("cbor-missing");
+    {
+        let storage = CommitStorage::open_with_options(dir.path_str(), cbor_config()).unwrap();
+        storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    }
+
+    assert!(matches!(CommitStorage::open(dir.path_str()), Err(GitDBError::InvalidInput(msg)) if msg.contains("cbor")));
+}
+
+#[test]
+fn codec_cannot_change_once_commits_exist() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+
+    assert!(matches!(storage.with_codec(Arc::new(CborCodec)), Err(GitDBError::InvalidInput(_))));
+}
+
+#[test]
+fn commit_hashes_follow_the_codec() {
+    let clock = Arc::new(ManualClock::new(1_700_000_000));
+    let bincode = CommitStorage::open_in_memory().with_clock(clock.clone());
+    let cbor = CommitStorage::open_in_memory().with_clock(clock).with_codec(Arc::new(CborCodec)).unwrap();
+
+    let preview = cbor.preview_commit_hash("root", &[insert("users", "1", "alice")]).unwrap();
+    let by_bincode = bincode.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let by_cbor = cbor.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+
+    assert_ne!(by_bincode, by_cbor);
+    assert_eq!(preview, by_cbor);
+    // Tag byte, payload, checksum trailer.
+    let raw = cbor.db.get(&by_cbor).unwrap().unwrap();
+    let stored: Commit = from_cbor(&raw[1..raw.len() - 32]).unwrap();
+    assert_eq!(stored.message, "root");
+}
+
+#[test]
+fn write_ahead_log_is_read_with_the_codec() {
+    let dir = TempDir::new("cbor-wal");
+    {
+        let storage = CommitStorage::open_with_options(dir.path_str(), cbor_config()).unwrap();
+        let mut batch = WriteBatch::default();
+        batch.put(b"users:1", CborCodec.encode_value(&value("alice")).unwrap());
+        let entry = WalEntry { head: None, batch };
+        storage.db.put(b"wal:pending", &CborCodec.encode_wal(&entry).unwrap()).unwrap();
+    }
+
+    let storage = CommitStorage::open_with_options(dir.path_str(), cbor_config()).unwrap();
+
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.db.get(b"wal:pending").unwrap(), None);
+}
@@ -0,0 +1,659 @@
+mod common;
+
+use common::*;
+use gitdb::core::backend::InMemoryBackend;
+use gitdb::core::clock::Clock;
+use gitdb::core::crdt::CrdtValue;
+use gitdb::core::database::{parse_hash, row_key, CommitStorage, StorageConfig, DEFAULT_MAX_MESSAGE_LEN};
+use gitdb::core::models::{Change, UNKNOWN_AUTHOR};
+use gitdb::error::GitDBError;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn rows_are_readable_by_table_and_id() {
+    let storage = CommitStorage::open_in_memory();
+    let mut protected = encoded("bob");
+    protected.extend_from_slice(blake3::hash(&protected).as_bytes());
+    storage.db.put(row_key("users", "1").as_bytes(), &encoded("alice")).unwrap();
+    storage.db.put(row_key("users", "2").as_bytes(), &protected).unwrap();
+    storage.db.put(row_key("usersx", "1").as_bytes(), &encoded("other")).unwrap();
+
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.get_row("users", "2").unwrap(), Some(encoded("bob")));
+    assert_eq!(storage.get_row("users", "3").unwrap(), None);
+
+    let users = storage.get_table("users").unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!(users["2"], encoded("bob"));
+}
+
+#[test]
+fn table_hash_ignores_tables_sharing_its_name_prefix() {
+    let user_hash = |with_users: bool| {
+        let storage = CommitStorage::open_in_memory();
+        storage.db.put(row_key("user", "1").as_bytes(), &encoded("alice")).unwrap();
+        if with_users {
+            storage.db.put(row_key("users", "1").as_bytes(), &encoded("bob")).unwrap();
+        }
+        let commit = storage.create_commit("touch user", vec![insert("user", "1", "alice")]).unwrap();
+        storage.table_hash_at(commit, "user").unwrap().unwrap()
+    };
+
+    assert_eq!(user_hash(false), user_hash(true));
+}
+
+#[test]
+fn table_hash_at_returns_the_recorded_tree_entry() {
+    let storage = CommitStorage::open_in_memory();
+    let root = commit_rows(&storage, "root", vec![insert("users", "1", "alice"), insert("orders", "1", "parcel")]);
+    let second = commit_rows(&storage, "orders only", vec![update("orders", "1", "crate")]);
+    let dropped = commit_rows(&storage, "drop orders", vec![Change::schema_drop("orders")]);
+    let root_tree = storage.get_commit_by_hash(&root).unwrap().tree;
+    let second_tree = storage.get_commit_by_hash(&second).unwrap().tree;
+
+    assert_eq!(storage.table_hash_at(root, "users").unwrap(), Some(root_tree["users"]));
+    assert_eq!(storage.table_hash_at(second, "users").unwrap(), Some(root_tree["users"]));
+    assert_eq!(storage.table_hash_at(second, "orders").unwrap(), Some(second_tree["orders"]));
+    assert_ne!(second_tree["orders"], root_tree["orders"]);
+    assert_eq!(storage.table_hash_at(second, "missing").unwrap(), None);
+    assert_eq!(storage.table_hash_at(dropped, "orders").unwrap(), None);
+}
+
+#[test]
+fn revert_across_a_delete_records_the_reinsert() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    commit_rows(&storage, "remove", vec![Change::delete("users", "1")]);
+
+    storage.revert_to_commit(&seeded).unwrap();
+
+    let revert = storage.get_commit_by_hash(&storage.get_head().unwrap().unwrap()).unwrap();
+    assert!(revert.changes.iter().any(|c| matches!(
+        c, Change::Insert { table, id, value } if table == "users" && id == "1" && *value == encoded("alice")
+    )));
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+}
+
+#[test]
+fn author_and_email_round_trip() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit_with_author("Ada", "ada@example.com", "add row", vec![
+        insert("users", "1", "alice"),
+    ]).unwrap();
+    let anonymous = storage.create_commit("no author", vec![insert("users", "2", "bob")]).unwrap();
+
+    let commit = storage.get_commit_by_hash(&hash).unwrap();
+    assert_eq!((commit.author.as_str(), commit.email.as_str()), ("Ada", "ada@example.com"));
+    assert_eq!(storage.get_commit_by_hash(&anonymous).unwrap().author, UNKNOWN_AUTHOR);
+}
+
+#[test]
+fn checkout_state_is_read_only() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("seed", vec![
+        insert("users", "1", "alice"),
+        insert("users", "2", "bob"),
+    ]).unwrap();
+    let head = storage.create_commit("edit", vec![
+        update("users", "1", "alicia"),
+        Change::delete("users", "2"),
+    ]).unwrap();
+
+    let state = storage.checkout_state(first).unwrap();
+
+    assert_eq!(state["users"].len(), 2);
+    assert_eq!(state["users"]["1"], encoded("alice"));
+    assert_eq!(state["users"]["2"], encoded("bob"));
+    assert_eq!(storage.checkout_state(head).unwrap()["users"].len(), 1);
+    assert_eq!(storage.get_head().unwrap(), Some(head));
+    assert!(storage.get_table("users").unwrap().is_empty());
+}
+
+#[test]
+fn atomic_commits_persist_nothing_if_one_is_invalid() {
+    let storage = CommitStorage::open_in_memory();
+    let head = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    let broken = Change::Insert { table: "users".into(), id: "3".into(), value: vec![0xFF] };
+
+    let result = storage.create_commits_atomic(vec![
+        ("first".to_string(), vec![insert("users", "2", "bob")]),
+        ("second".to_string(), vec![broken]),
+    ]);
+
+    assert!(result.is_err());
+    assert_eq!(storage.get_head().unwrap(), Some(head));
+    assert_eq!(storage.count_commits(None).unwrap(), 1);
+    assert!(storage.find_by_message_substring("first").unwrap().is_empty());
+}
+
+#[test]
+fn atomic_commits_chain_onto_head() {
+    let storage = CommitStorage::open_in_memory();
+    let head = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+
+    let hashes = storage.create_commits_atomic(vec![
+        ("first".to_string(), vec![insert("users", "2", "bob")]),
+        ("second".to_string(), vec![insert("users", "3", "carol")]),
+    ]).unwrap();
+
+    assert_eq!(storage.get_commit_by_hash(&hashes[0]).unwrap().parents, vec![head]);
+    assert_eq!(storage.get_commit_by_hash(&hashes[1]).unwrap().parents, vec![hashes[0]]);
+    assert_eq!(storage.get_head().unwrap(), Some(hashes[1]));
+}
+
+#[test]
+fn preview_hash_matches_the_created_commit() {
+    let storage = CommitStorage::open_in_memory().with_clock(Arc::new(ManualClock::new(1_700_000_000)));
+    storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    let changes = vec![insert("users", "2", "bob"), update("users", "1", "alicia")];
+
+    let preview = storage.preview_commit_hash("next", &changes).unwrap();
+    let head_before = storage.get_head().unwrap();
+    assert!(!storage.commit_exists(preview).unwrap());
+    assert_eq!(storage.get_head().unwrap(), head_before);
+
+    assert_eq!(storage.create_commit("next", changes).unwrap(), preview);
+}
+
+#[test]
+fn empty_commits_are_rejected_unless_allowed() {
+    let storage = CommitStorage::open_in_memory();
+    let head = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+
+    match storage.create_commit("nothing", Vec::new()) {
+        Err(GitDBError::InvalidInput(message)) => assert_eq!(message, "empty commit"),
+        other => panic!("expected an empty commit error, got {:?}", other),
+    }
+    assert_eq!(storage.get_head().unwrap(), Some(head));
+
+    let empty = storage.create_commit_allow_empty("marker", Vec::new()).unwrap();
+    assert_eq!(storage.get_commit_by_hash(&empty).unwrap().parents, vec![head]);
+}
+
+#[test]
+fn commits_that_leave_the_data_unchanged_are_rejected() {
+    let storage = CommitStorage::open_in_memory();
+    let head = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+
+    assert!(matches!(
+        storage.create_commit("same again", vec![update("users", "1", "alice")]),
+        Err(GitDBError::NoOpCommit)
+    ));
+    assert_eq!(storage.get_head().unwrap(), Some(head));
+}
+
+#[test]
+fn cached_commits_are_served_without_the_db() {
+    let cached = CommitStorage::with_backend(Arc::new(InMemoryBackend::new()), 8).unwrap();
+    let hash = cached.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    cached.get_commit_by_hash(&hash).unwrap();
+    cached.db.delete(&hash).unwrap();
+    assert_eq!(cached.get_commit_by_hash(&hash).unwrap().message, "seed");
+
+    let uncached = CommitStorage::with_backend(Arc::new(InMemoryBackend::new()), 0).unwrap();
+    let hash = uncached.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    uncached.get_commit_by_hash(&hash).unwrap();
+    uncached.db.delete(&hash).unwrap();
+    assert!(uncached.get_commit_by_hash(&hash).is_err());
+}
+
+#[test]
+fn hashes_must_be_exactly_32_bytes_of_hex() {
+    let hash = [0xabu8; 32];
+    assert_eq!(parse_hash(&hex::encode(hash)).unwrap(), hash);
+
+    for bad in [hex::encode([0xab; 31]), hex::encode([0xab; 33]), "zz".repeat(32)] {
+        assert!(matches!(parse_hash(&bad), Err(GitDBError::InvalidInput(_))), "accepted {}", bad);
+    }
+
+    let storage = CommitStorage::open_in_memory();
+    assert!(matches!(storage.debug_commit(&hex::encode([0xab; 31])), Err(GitDBError::InvalidInput(_))));
+}
+
+#[test]
+fn table_iteration_yields_bare_ids_of_that_table_only() {
+    let storage = CommitStorage::open_in_memory();
+    for id in ["a", "b", "c"] {
+        storage.db.put(row_key("users", id).as_bytes(), &encoded(id)).unwrap();
+    }
+    storage.db.put(row_key("user", "z").as_bytes(), &encoded("z")).unwrap();
+    storage.db.put(row_key("usersx", "z").as_bytes(), &encoded("z")).unwrap();
+
+    let rows: Vec<_> = storage.iter_table("users").collect::<Result<_, _>>().unwrap();
+
+    let ids: Vec<_> = rows.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, vec!["a", "b", "c"]);
+    assert_eq!(rows[1].1, encoded("b"));
+}
+
+#[test]
+fn concurrent_commits_form_a_single_chain() {
+    const THREADS: usize = 8;
+    let storage = CommitStorage::open_in_memory();
+
+    std::thread::scope(|scope| {
+        for n in 0..THREADS {
+            let storage = &storage;
+            scope.spawn(move || {
+                let id = n.to_string();
+                storage.create_commit(&format!("commit {}", n), vec![insert("users", &id, "someone")]).unwrap();
+            });
+        }
+    });
+
+    let history = storage.get_commit_history_with_hashes().unwrap();
+    assert_eq!(history.len(), THREADS);
+    for pair in history.windows(2) {
+        assert_eq!(pair[0].commit.parents, vec![pair[1].hash]);
+    }
+    assert!(history[THREADS - 1].commit.parents.is_empty());
+}
+
+#[test]
+fn commit_and_diff_flow_runs_on_a_shared_in_memory_backend() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let storage = CommitStorage::with_backend(backend.clone(), 8).unwrap();
+    let from = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    let to = commit_rows(&storage, "edit", vec![update("users", "1", "alicia"), insert("users", "2", "bob")]);
+
+    let reopened = CommitStorage::with_backend(backend, 8).unwrap();
+    assert_eq!(reopened.get_head().unwrap(), Some(to));
+    assert_eq!(reopened.get_row("users", "1").unwrap(), Some(encoded("alicia")));
+    let stats = &reopened.summarize_diff(from, to).unwrap()["users"];
+    assert_eq!((stats.inserts, stats.updates, stats.deletes), (1, 1, 0));
+}
+
+#[test]
+fn partial_checkout_leaves_other_tables_alone() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = commit_rows(&storage, "seed", vec![
+        insert("users", "1", "alice"),
+        insert("teams", "1", "core"),
+    ]);
+    commit_rows(&storage, "edit", vec![
+        update("users", "1", "alicia"),
+        insert("users", "2", "bob"),
+        update("teams", "1", "infra"),
+    ]);
+    storage.db.put(row_key("userspace", "1").as_bytes(), &encoded("unrelated")).unwrap();
+
+    storage.checkout_tables(seeded, &["users"]).unwrap();
+
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.get_row("users", "2").unwrap(), None);
+    assert_eq!(storage.get_row("teams", "1").unwrap(), Some(encoded("infra")));
+    assert_eq!(storage.get_row("userspace", "1").unwrap(), Some(encoded("unrelated")));
+}
+
+#[test]
+fn renaming_a_table_moves_its_rows() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_commit("seed", vec![
+        insert("users", "1", "alice"),
+        insert("users", "2", "bob"),
+        insert("teams", "1", "core"),
+    ]).unwrap();
+
+    let renamed = storage.rename_table("users", "people", "rename users").unwrap();
+
+    let state = storage.checkout_state(renamed).unwrap();
+    assert!(!state.contains_key("users"));
+    assert_eq!(state["people"].len(), 2);
+    assert_eq!(state["people"]["2"], encoded("bob"));
+    assert!(matches!(storage.rename_table("people", "teams", "clash"), Err(GitDBError::Conflict(_))));
+    assert!(matches!(storage.rename_table("users", "members", "gone"), Err(GitDBError::InvalidInput(_))));
+}
+
+#[test]
+fn commit_metadata_round_trips() {
+    let storage = CommitStorage::open_in_memory();
+    let plain = storage.create_commit("plain", vec![insert("users", "1", "alice")]).unwrap();
+    let metadata = BTreeMap::from([
+        ("build".to_string(), "1234".to_string()),
+        ("ticket".to_string(), "DB-7".to_string()),
+    ]);
+    let tagged = storage.create_commit_with_metadata("tagged", vec![insert("users", "2", "bob")], metadata.clone()).unwrap();
+
+    assert_eq!(storage.get_commit_metadata(tagged).unwrap(), metadata);
+    assert!(storage.get_commit_metadata(plain).unwrap().is_empty());
+    assert_eq!(storage.get_commit_by_hash(&tagged).unwrap().message, "tagged");
+}
+
+#[test]
+fn revert_writes_rows_commit_and_head_together() {
+    let backend = Arc::new(FailingWrites::default());
+    let storage = CommitStorage::with_backend(backend.clone(), 0).unwrap();
+    let seeded = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    let edited = commit_rows(&storage, "edit", vec![update("users", "1", "alicia"), insert("users", "2", "bob")]);
+    let keys_before = storage.db.iterator().count();
+
+    backend.fail_writes(true);
+    assert!(storage.revert_to_commit(&seeded).is_err());
+    assert_eq!(storage.get_head().unwrap(), Some(edited));
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alicia")));
+    assert_eq!(storage.db.iterator().count(), keys_before);
+
+    backend.fail_writes(false);
+    storage.revert_to_commit(&seeded).unwrap();
+    let head = storage.get_head().unwrap().unwrap();
+    assert_eq!(storage.parents_of(head).unwrap(), vec![edited]);
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.get_row("users", "2").unwrap(), None);
+}
+
+#[test]
+fn ids_and_tables_containing_the_key_delimiter_are_rejected() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+
+    for change in [insert("users", "1:evil", "mallory"), Change::delete("users:admin", "1")] {
+        match storage.create_commit("smuggle", vec![change]) {
+            Err(GitDBError::InvalidInput(message)) => assert!(message.contains("must not contain ':'"), "{}", message),
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+    }
+    assert!(storage.stage_change(insert("users", "2:evil", "mallory")).is_err());
+    assert_eq!(storage.get_head().unwrap(), Some(seeded));
+}
+
+#[test]
+fn tables_named_after_internal_keyspaces_are_rejected() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+
+    for table in ["wal", "staging", "build", "ws", "blob", "reflog", "snapshot", "refs/heads/main"] {
+        match storage.create_commit("smuggle", vec![insert(table, "pending", "mallory")]) {
+            Err(GitDBError::InvalidInput(message)) => assert!(message.contains("reserved"), "{}", message),
+            other => panic!("expected {} to be rejected, got {:?}", table, other),
+        }
+    }
+    assert!(storage.stage_change(Change::schema_add("staging")).is_err());
+    assert_eq!(storage.get_head().unwrap(), Some(seeded));
+    assert_eq!(storage.db.get(b"wal:pending").unwrap(), None);
+    storage.create_commit("lookalike", vec![insert("walrus", "1", "wally")]).unwrap();
+}
+
+#[test]
+fn staged_changes_commit_together_and_clear() {
+    let storage = CommitStorage::open_in_memory();
+    assert!(storage.commit_staged("nothing").is_err());
+    storage.stage_change(insert("users", "1", "alice")).unwrap();
+    storage.unstage_all().unwrap();
+    assert!(storage.get_staged().unwrap().is_empty());
+
+    storage.stage_change(insert("users", "1", "alice")).unwrap();
+    storage.stage_change(insert("users", "2", "bob")).unwrap();
+    storage.stage_change(Change::delete("users", "2")).unwrap();
+    let staged: Vec<_> = storage.get_staged().unwrap().iter().map(|c| c.id().to_string()).collect();
+    assert_eq!(staged, vec!["1", "2", "2"]);
+
+    let hash = storage.commit_staged("staged").unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(hash));
+    assert_eq!(storage.get_commit_by_hash(&hash).unwrap().message, "staged");
+    assert!(storage.get_staged().unwrap().is_empty());
+    let users = &storage.checkout_state(hash).unwrap()["users"];
+    assert_eq!(users.len(), 1);
+    assert_eq!(users["1"], encoded("alice"));
+}
+
+#[test]
+fn identical_commits_hash_identically_across_repositories() {
+    let build = || {
+        let storage = CommitStorage::open_in_memory().with_clock(Arc::new(ManualClock::new(1_700_000_000)));
+        let changes = (0..16).map(|n| insert(&format!("table{}", n), "1", "row")).collect();
+        storage.create_commit("many tables", changes).unwrap()
+    };
+
+    let first = build();
+    for _ in 0..4 {
+        assert_eq!(build(), first);
+    }
+}
+
+#[test]
+fn missing_commits_are_refused_up_front() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let missing = [7u8; 32];
+    assert!(storage.commit_exists(hash).unwrap());
+    assert!(!storage.commit_exists(missing).unwrap());
+
+    let expect_missing = |result: Result<(), GitDBError>, role: &str| match result {
+        Err(GitDBError::InvalidInput(message)) => {
+            assert_eq!(message, format!("{} commit {} does not exist", role, hex::encode(missing)))
+        }
+        other => panic!("expected a missing {} commit, got {:?}", role, other),
+    };
+    expect_missing(storage.merge_commits(hash, missing, "merge").map(|_| ()), "theirs");
+    expect_missing(storage.cherry_pick(missing).map(|_| ()), "cherry-picked");
+    expect_missing(storage.revert_to_commit(&missing), "revert target");
+    assert_eq!(storage.get_head().unwrap(), Some(hash));
+}
+
+#[test]
+fn large_values_are_stored_out_of_line() {
+    let storage = CommitStorage::open_in_memory().with_blob_threshold(Some(1024));
+    let large = bincode::serialize(&CrdtValue::Register(vec![42u8; 64 * 1024])).unwrap();
+    let hash = storage.create_commit("large", vec![
+        Change::Insert { table: "files".into(), id: "1".into(), value: large.clone() },
+        insert("users", "1", "alice"),
+    ]).unwrap();
+
+    assert!(storage.db.get(&hash).unwrap().unwrap().len() < 1024);
+    assert_eq!(storage.db.prefix_iterator(b"blob:").count(), 1);
+    let commit = storage.get_commit_by_hash(&hash).unwrap();
+    assert!(matches!(&commit.changes[0], Change::Insert { value, .. } if *value == large));
+    assert_eq!(storage.checkout_state(hash).unwrap()["files"]["1"], large);
+}
+
+/// A clock set to before the UNIX epoch.
+struct PreEpochClock;
+
+impl Clock for PreEpochClock {
+    fn now(&self) -> gitdb::error::Result<u64> {
+        Ok(UNIX_EPOCH.duration_since(UNIX_EPOCH + Duration::from_secs(90))?.as_secs())
+    }
+}
+
+#[test]
+fn a_clock_before_the_epoch_is_reported_clearly() {
+    let storage = CommitStorage::open_in_memory().with_clock(Arc::new(PreEpochClock));
+
+    match storage.create_commit("root", vec![insert("users", "1", "alice")]) {
+        Err(GitDBError::InvalidInput(message)) => assert_eq!(message, "system clock before UNIX epoch by 90s"),
+        other => panic!("expected a clock error, got {:?}", other),
+    }
+    assert_eq!(storage.get_head().unwrap(), None);
+}
+
+#[test]
+fn bulk_row_lookup_reports_missing_ids() {
+    let storage = CommitStorage::open_in_memory();
+    storage.db.put(row_key("users", "1").as_bytes(), &encoded("alice")).unwrap();
+    storage.db.put(row_key("users", "3").as_bytes(), &encoded("carol")).unwrap();
+
+    let rows = storage.get_rows("users", &["1", "2", "3"]).unwrap();
+
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows["1"], Some(encoded("alice")));
+    assert_eq!(rows["2"], None);
+    assert_eq!(rows["3"], Some(encoded("carol")));
+}
+
+#[test]
+fn overlong_messages_and_control_characters_are_rejected() {
+    let storage = CommitStorage::open_in_memory();
+    let too_long = "m".repeat(DEFAULT_MAX_MESSAGE_LEN + 1);
+
+    for message in [too_long.as_str(), "null\0byte", "bell\x07"] {
+        assert!(matches!(
+            storage.create_commit(message, vec![insert("users", "1", "alice")]),
+            Err(GitDBError::InvalidInput(_))
+        ));
+    }
+    assert_eq!(storage.get_head().unwrap(), None);
+    storage.create_commit("subject\n\nbody", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_commit(&"m".repeat(DEFAULT_MAX_MESSAGE_LEN), vec![insert("users", "2", "bob")]).unwrap();
+}
+
+#[test]
+fn message_limit_comes_from_the_storage_config() {
+    let dir = TempDir::new("message-limit");
+    let cfg = StorageConfig { max_message_len: Some(8), ..StorageConfig::default() };
+    let storage = CommitStorage::open_with_options(dir.path_str(), cfg).unwrap();
+
+    match storage.create_commit("nine char", vec![insert("users", "1", "alice")]) {
+        Err(GitDBError::InvalidInput(message)) => assert!(message.contains("limit is 8"), "{}", message),
+        other => panic!("expected a rejection, got {:?}", other),
+    }
+    storage.create_commit("eight ch", vec![insert("users", "1", "alice")]).unwrap();
+}
+
+#[test]
+fn repeated_changes_to_a_row_are_stored_as_one() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit("insert then rename", vec![
+        insert("users", "1", "alice"),
+        update("users", "1", "alicia"),
+        insert("users", "2", "bob"),
+        Change::delete("users", "2"),
+    ]).unwrap();
+
+    let changes = storage.get_commit_by_hash(&hash).unwrap().changes;
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(&changes[0], Change::Insert { table, id, value } if table == "users" && id == "1" && *value == encoded("alicia")));
+}
+
+#[test]
+fn insert_then_delete_of_an_existing_row_is_stored_as_the_delete() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+
+    let hash = storage.create_commit("overwrite then remove", vec![
+        insert("users", "1", "mallory"),
+        Change::delete("users", "1"),
+    ]).unwrap();
+
+    let changes = storage.get_commit_by_hash(&hash).unwrap().changes;
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(&changes[0], Change::Delete { table, id } if table == "users" && id == "1"));
+    assert!(storage.checkout_state(hash).unwrap()["users"].is_empty());
+}
+
+#[test]
+fn changes_that_cancel_out_are_a_no_op() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    let cancelling = vec![insert("users", "2", "bob"), Change::delete("users", "2")];
+
+    assert!(matches!(storage.preview_commit_hash("cancel", &cancelling), Err(GitDBError::NoOpCommit)));
+    assert!(matches!(storage.create_commit("cancel", cancelling), Err(GitDBError::NoOpCommit)));
+    assert_eq!(storage.get_head().unwrap(), Some(seeded));
+}
+
+#[test]
+fn builder_commit_matches_a_one_shot_commit_of_the_same_changes() {
+    let changes = || (0..25_000).map(|n| insert(if n % 2 == 0 { "users" } else { "orders" }, &n.to_string(), "row"));
+    let clock = Arc::new(ManualClock::new(1_700_000_000));
+    let streamed = CommitStorage::open_in_memory().with_clock(clock.clone());
+    let one_shot = CommitStorage::open_in_memory().with_clock(clock);
+
+    let mut builder = streamed.commit_builder();
+    for change in changes() {
+        builder.push(change).unwrap();
+    }
+    let built = builder.finish("bulk import").unwrap();
+
+    assert_eq!(built, one_shot.create_commit("bulk import", changes().collect()).unwrap());
+    assert!(streamed.db.prefix_iterator(b"build:").next().is_none());
+    let state = streamed.checkout_state(built).unwrap();
+    assert_eq!(state["users"].len() + state["orders"].len(), 25_000);
+}
+
+#[test]
+fn builder_leaves_the_staging_area_alone() {
+    let storage = CommitStorage::open_in_memory();
+    storage.stage_change(insert("users", "1", "alice")).unwrap();
+
+    let mut builder = storage.commit_builder();
+    for n in 0..10_001 {
+        builder.push(insert("orders", &n.to_string(), "parcel")).unwrap();
+    }
+    let built = builder.finish("orders only").unwrap();
+
+    let changes = storage.get_commit_by_hash(&built).unwrap().changes;
+    assert_eq!(changes.len(), 10_001);
+    assert!(changes.iter().all(|change| change.table() == "orders"));
+    assert_eq!(format!("{:?}", storage.get_staged().unwrap()), format!("{:?}", vec![insert("users", "1", "alice")]));
+
+    let mut abandoned = storage.commit_builder();
+    for n in 0..10_000 {
+        abandoned.push(insert("orders", &n.to_string(), "lost")).unwrap();
+    }
+    assert!(storage.db.prefix_iterator(b"build:").next().is_some());
+    drop(abandoned);
+    assert!(storage.db.prefix_iterator(b"build:").next().is_none());
+}
+
+#[test]
+fn builder_tree_matches_a_one_shot_commit_after_head_moves() {
+    let clock = Arc::new(ManualClock::new(1_700_000_000));
+    let streamed = CommitStorage::open_in_memory().with_clock(clock.clone());
+    let one_shot = CommitStorage::open_in_memory().with_clock(clock);
+    let mut seeds = Vec::new();
+    for storage in [&streamed, &one_shot] {
+        let root = storage.create_commit("root", vec![insert("users", "0", "root")]).unwrap();
+        storage.create_commit("rename", vec![update("users", "0", "renamed")]).unwrap();
+        seeds.push(root);
+    }
+
+    let mut builder = streamed.commit_builder();
+    for n in 1..=10_000 {
+        builder.push(insert("users", &n.to_string(), "someone")).unwrap();
+    }
+    // The first chunk was hashed before the revert rewrote the stored rows.
+    streamed.revert_to_commit(&seeds[0]).unwrap();
+    one_shot.revert_to_commit(&seeds[1]).unwrap();
+    let built = builder.finish("bulk").unwrap();
+
+    let changes = (1..=10_000).map(|n| insert("users", &n.to_string(), "someone")).collect();
+    assert_eq!(built, one_shot.create_commit("bulk", changes).unwrap());
+}
+
+#[test]
+fn staged_changes_are_coalesced_when_committed() {
+    let storage = CommitStorage::open_in_memory();
+    storage.stage_change(insert("users", "1", "alice")).unwrap();
+    storage.stage_change(update("users", "1", "alicia")).unwrap();
+
+    let hash = storage.commit_staged("staged twice").unwrap();
+
+    let changes = storage.get_commit_by_hash(&hash).unwrap().changes;
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(&changes[0], Change::Insert { id, value, .. } if id == "1" && *value == encoded("alicia")));
+}
+
+#[test]
+fn concurrent_stagers_never_share_a_sequence_number() {
+    const THREADS: usize = 4;
+    const PER_THREAD: usize = 50;
+    let storage = CommitStorage::open_in_memory();
+
+    std::thread::scope(|scope| {
+        for n in 0..THREADS {
+            let storage = &storage;
+            scope.spawn(move || {
+                for m in 0..PER_THREAD {
+                    storage.stage_change(insert("users", &format!("{}-{}", n, m), "someone")).unwrap();
+                }
+            });
+        }
+    });
+
+    assert_eq!(storage.get_staged().unwrap().len(), THREADS * PER_THREAD);
+    let hash = storage.commit_staged("everyone").unwrap();
+    assert_eq!(storage.get_commit_by_hash(&hash).unwrap().changes.len(), THREADS * PER_THREAD);
+}
@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+
+use gitdb::core::backend::{Backend, InMemoryBackend, KvIter, WriteBatch};
+use gitdb::core::clock::Clock;
+use gitdb::core::crdt::CrdtValue;
+use gitdb::core::database::{row_key, CommitStorage};
+use gitdb::core::models::Change;
+use gitdb::error::{GitDBError, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn value(text: &str) -> CrdtValue {
+    CrdtValue::Register(text.as_bytes().to_vec())
+}
+
+/// `value(text)` in the encoding `Change` values use.
+pub fn encoded(text: &str) -> Vec<u8> {
+    bincode::serialize(&value(text)).unwrap()
+}
+
+pub fn insert(table: &str, id: &str, text: &str) -> Change {
+    Change::insert_value(table, id, &value(text)).unwrap()
+}
+
+pub fn update(table: &str, id: &str, text: &str) -> Change {
+    Change::update_value(table, id, &value(text)).unwrap()
+}
+
+/// Writes the rows `changes` touch, the way a caller materializing its own
+/// rows would, then commits `changes` on HEAD so the tree hashes see them.
+pub fn commit_rows(storage: &CommitStorage, message: &str, changes: Vec<Change>) -> [u8; 32] {
+    for change in &changes {
+        let key = row_key(change.table(), change.id());
+        match change {
+            Change::Insert { value, .. } | Change::Update { value, .. } => {
+                storage.db.put(key.as_bytes(), value).unwrap()
+            }
+            Change::Delete { .. } => storage.db.delete(key.as_bytes()).unwrap(),
+            Change::SchemaAdd { .. } => {}
+            Change::SchemaDrop { table } => {
+                for row in storage.iter_table(table).collect::<Vec<_>>() {
+                    storage.db.delete(row_key(table, &row.unwrap().0).as_bytes()).unwrap();
+                }
+            }
+            other => panic!("commit_rows does not materialize {:?}", other),
+        }
+    }
+    storage.create_commit(message, changes).unwrap()
+}
+
+/// Commits shaped `root -> {left, right} -> merge`, with `main` at the merge
+/// and `feature` at `right`. Returns `[root, left, right, merge]`.
+pub fn diamond(storage: &CommitStorage) -> [[u8; 32]; 4] {
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let left = storage.create_commit("left", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let right = storage.create_commit("right", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let merge = storage.merge_commits(left, right, "merge feature").unwrap();
+    [root, left, right, merge]
+}
+
+/// A clock that only moves when told to.
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        ManualClock(AtomicU64::new(now))
+    }
+
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Result<u64> {
+        Ok(self.0.load(Ordering::SeqCst))
+    }
+}
+
+/// An in-memory backend whose batch writes can be made to fail, to check
+/// that an operation leaves nothing behind when its batch is lost.
+#[derive(Default)]
+pub struct FailingWrites {
+    inner: InMemoryBackend,
+    fail: AtomicBool,
+}
+
+impl FailingWrites {
+    pub fn fail_writes(&self, fail: bool) {
+        self.fail.store(fail, Ordering::SeqCst);
+    }
+}
+
+impl Backend for FailingWrites {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put(key, value)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(GitDBError::IoError("write refused".into()));
+        }
+        self.inner.write(batch)
+    }
+
+    fn prefix_iterator<'a>(&'a self, prefix: &[u8]) -> KvIter<'a> {
+        self.inner.prefix_iterator(prefix)
+    }
+
+    fn iterator(&self) -> KvIter<'_> {
+        self.inner.iterator()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.inner.compact()
+    }
+}
+
+/// A fresh directory under the system temp dir, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let dir = std::env::temp_dir().join(format!(
+            "gitdb-{}-{}-{}-{}", name, std::process::id(), nanos, NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn path_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
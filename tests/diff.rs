@@ -0,0 +1,270 @@
+mod common;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use common::*;
+use gitdb::core::crdt::CrdtValue;
+use gitdb::core::database::{diff_states, field_diff, row_key, CommitStorage};
+use gitdb::core::models::{Change, DiffStats};
+use gitdb::error::GitDBError;
+use std::collections::{BTreeMap, HashMap};
+
+#[test]
+fn json_diff_tags_each_op_sorted_by_table_and_id() {
+    let storage = CommitStorage::open_in_memory();
+    let from = commit_rows(&storage, "seed", vec![
+        insert("users", "1", "alice"),
+        insert("users", "2", "bob"),
+    ]);
+    let to = commit_rows(&storage, "edit", vec![
+        insert("users", "3", "carol"),
+        Change::delete("users", "2"),
+        update("users", "1", "alicia"),
+    ]);
+
+    let expected = format!(
+        concat!(
+            r#"[{{"op":"update","table":"users","id":"1","value_base64":"{}"}},"#,
+            r#"{{"op":"delete","table":"users","id":"2"}},"#,
+            r#"{{"op":"insert","table":"users","id":"3","value_base64":"{}"}}]"#,
+        ),
+        BASE64.encode(encoded("alicia")),
+        BASE64.encode(encoded("carol")),
+    );
+    assert_eq!(storage.diff_to_json(from, to).unwrap(), expected);
+}
+
+#[test]
+fn summary_counts_changes_per_table() {
+    let storage = CommitStorage::open_in_memory();
+    let from = commit_rows(&storage, "seed", vec![
+        insert("users", "1", "alice"),
+        insert("users", "2", "bob"),
+        insert("teams", "1", "core"),
+    ]);
+    commit_rows(&storage, "untouched", vec![insert("audit", "1", "seen")]);
+    let to = commit_rows(&storage, "edit", vec![
+        update("users", "1", "alicia"),
+        Change::delete("users", "2"),
+        insert("users", "3", "carol"),
+        insert("users", "4", "dave"),
+        Change::delete("teams", "1"),
+    ]);
+
+    let summary = storage.summarize_diff(from, to).unwrap();
+
+    assert_eq!(summary["users"], DiffStats { inserts: 2, updates: 1, deletes: 1 });
+    assert_eq!(summary["teams"], DiffStats { inserts: 0, updates: 0, deletes: 1 });
+    assert_eq!(summary["audit"], DiffStats { inserts: 1, updates: 0, deletes: 0 });
+    assert_eq!(summary.len(), 3);
+}
+
+#[test]
+fn table_diff_names_a_missing_commit() {
+    let storage = CommitStorage::open_in_memory();
+    let real = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    let bogus = [7u8; 32];
+
+    match storage.get_table_diffs("users", &bogus, &real) {
+        Err(GitDBError::InvalidInput(message)) => {
+            assert_eq!(message, format!("from commit {} does not exist", hex::encode(bogus)));
+        }
+        other => panic!("expected an invalid input error, got {:?}", other),
+    }
+    match storage.get_table_diffs("users", &real, &bogus) {
+        Err(GitDBError::InvalidInput(message)) => assert!(message.starts_with("to commit")),
+        other => panic!("expected an invalid input error, got {:?}", other),
+    }
+}
+
+#[test]
+fn tree_comparison_reports_identical_changed_and_added_tables() {
+    let storage = CommitStorage::open_in_memory();
+    let original = commit_rows(&storage, "seed", vec![insert("users", "1", "alice"), insert("teams", "1", "core")]);
+    let renamed = commit_rows(&storage, "rename", vec![update("users", "1", "alicia"), update("teams", "1", "core")]);
+    let restored = commit_rows(&storage, "restore", vec![update("users", "1", "alice"), update("teams", "1", "core")]);
+    let audited = commit_rows(&storage, "audit", vec![
+        update("users", "1", "alice"),
+        update("teams", "1", "core"),
+        insert("audit", "1", "seen"),
+    ]);
+
+    assert!(storage.trees_equal(original, restored).unwrap());
+    assert!(storage.tables_changed(original, restored).unwrap().is_empty());
+
+    assert!(!storage.trees_equal(original, renamed).unwrap());
+    assert_eq!(storage.tables_changed(original, renamed).unwrap(), vec!["users".to_string()]);
+
+    assert!(!storage.trees_equal(restored, audited).unwrap());
+    assert_eq!(storage.tables_changed(restored, audited).unwrap(), vec!["audit".to_string()]);
+}
+
+#[test]
+fn dropped_table_shows_row_deletes_then_a_schema_drop() {
+    let storage = CommitStorage::open_in_memory();
+    let from = commit_rows(&storage, "seed", vec![
+        insert("users", "1", "alice"),
+        insert("teams", "1", "core"),
+        insert("teams", "2", "infra"),
+    ]);
+    let to = commit_rows(&storage, "drop teams", vec![Change::schema_drop("teams")]);
+
+    let diffs = storage.get_commit_diffs(&from, &to).unwrap();
+
+    let described: Vec<_> = diffs.iter().map(|c| match c {
+        Change::Delete { table, id } => format!("delete {}:{}", table, id),
+        Change::SchemaDrop { table } => format!("drop {}", table),
+        other => panic!("unexpected change {:?}", other),
+    }).collect();
+    assert_eq!(described.len(), 3);
+    assert!(described[..2].contains(&"delete teams:1".to_string()));
+    assert!(described[..2].contains(&"delete teams:2".to_string()));
+    assert_eq!(described[2], "drop teams");
+}
+
+#[test]
+fn merge_commit_diffs_against_each_parent() {
+    let storage = CommitStorage::open_in_memory();
+    let root = commit_rows(&storage, "root", vec![insert("users", "1", "alice")]);
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let ours = commit_rows(&storage, "ours", vec![insert("users", "2", "bob")]);
+    storage.set_head_to_branch("feature").unwrap();
+    storage.db.delete(row_key("users", "2").as_bytes()).unwrap();
+    let theirs = commit_rows(&storage, "theirs", vec![insert("users", "3", "carol")]);
+    storage.set_head_to_branch("main").unwrap();
+    storage.db.put(row_key("users", "2").as_bytes(), &encoded("bob")).unwrap();
+    let merge = storage.merge_commits(ours, theirs, "merge").unwrap();
+
+    let diffs = storage.diff_against_parents(merge).unwrap();
+
+    assert_eq!(diffs.len(), 2);
+    let only_insert = |changes: &Vec<Change>, expected: &str| {
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(&changes[0], Change::Insert { id, .. } if id == expected));
+    };
+    only_insert(&diffs[&ours], "3");
+    only_insert(&diffs[&theirs], "2");
+    assert!(storage.diff_against_parents(root).unwrap().is_empty());
+}
+
+#[test]
+fn added_table_diffs_as_a_schema_add_not_a_sentinel_row() {
+    let storage = CommitStorage::open_in_memory();
+    let from = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    let to = commit_rows(&storage, "add teams", vec![
+        Change::schema_add("teams"),
+        insert("teams", "1", "core"),
+    ]);
+
+    let diffs = storage.get_commit_diffs(&from, &to).unwrap();
+
+    assert_eq!(diffs.len(), 2);
+    assert!(matches!(&diffs[0], Change::SchemaAdd { table } if table == "teams"));
+    assert!(matches!(&diffs[1], Change::Insert { table, id, .. } if table == "teams" && id == "1"));
+}
+
+#[test]
+fn status_reports_rows_written_outside_a_commit() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    assert!(storage.status(None).unwrap().is_empty());
+
+    storage.db.put(row_key("users", "1").as_bytes(), &encoded("mallory")).unwrap();
+    storage.db.put(row_key("users", "2").as_bytes(), &encoded("eve")).unwrap();
+
+    let status = storage.status(Some(seeded)).unwrap();
+    assert_eq!(status.len(), 2);
+    assert!(matches!(&status[0], Change::Update { id, value, .. } if id == "1" && *value == encoded("alice")));
+    assert!(matches!(&status[1], Change::Delete { id, .. } if id == "2"));
+}
+
+#[test]
+fn text_diff_renders_one_sorted_line_per_change() {
+    let storage = CommitStorage::open_in_memory();
+    let from = commit_rows(&storage, "seed", vec![
+        insert("users", "1", "alice"),
+        insert("users", "2", "bob"),
+    ]);
+    let to = commit_rows(&storage, "edit", vec![
+        insert("users", "3", "carol"),
+        Change::delete("users", "2"),
+        update("users", "1", "alicia"),
+        Change::schema_add("teams"),
+    ]);
+    let preview = |name: &str| format!("{}...", hex::encode(&encoded(name)[..8]));
+
+    let expected = format!(
+        "+ teams/\n~ users/1 {}\n- users/2\n+ users/3 {}\n",
+        preview("alicia"),
+        preview("carol"),
+    );
+    assert_eq!(storage.diff_to_text(from, to).unwrap(), expected);
+}
+
+/// A row with `name` and `city` fields.
+fn person(city: &str) -> CrdtValue {
+    CrdtValue::Fields(BTreeMap::from([
+        ("city".to_string(), value(city)),
+        ("name".to_string(), value("Ada")),
+    ]))
+}
+
+#[test]
+fn field_diff_reports_only_the_changed_field() {
+    let encode = |city: &str| bincode::serialize(&person(city)).unwrap();
+
+    let changes = field_diff(&encode("London"), &encode("Paris")).unwrap();
+
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].field, "city");
+    assert_eq!(changes[0].from, Some(encoded("London")));
+    assert_eq!(changes[0].to, Some(encoded("Paris")));
+    assert!(field_diff(&encode("Paris"), &encode("Paris")).unwrap().is_empty());
+}
+
+#[test]
+fn json_diff_with_fields_names_the_changed_field() {
+    let storage = CommitStorage::open_in_memory();
+    let from = commit_rows(&storage, "seed", vec![Change::insert_value("people", "1", &person("London")).unwrap()]);
+    let to = commit_rows(&storage, "move", vec![Change::update_value("people", "1", &person("Paris")).unwrap()]);
+
+    let json: serde_json::Value = serde_json::from_str(&storage.diff_to_json_with_fields(from, to).unwrap()).unwrap();
+
+    let fields = &json[0]["fields"];
+    assert_eq!(fields.as_array().unwrap().len(), 1);
+    assert_eq!(fields[0]["field"], "city");
+    assert_eq!(fields[0]["to_base64"], BASE64.encode(encoded("Paris")));
+}
+
+#[test]
+fn raw_state_maps_diff_without_a_database() {
+    let rows = |pairs: &[(&str, &str)]| -> HashMap<String, Vec<u8>> {
+        pairs.iter().map(|(id, name)| (id.to_string(), encoded(name))).collect()
+    };
+    let from = rows(&[("1", "alice"), ("2", "bob"), ("3", "carol")]);
+    let to = rows(&[("1", "alicia"), ("3", "carol"), ("4", "dave")]);
+
+    let diffs = diff_states(&from, &to, "users");
+
+    assert_eq!(diffs.len(), 3);
+    assert!(matches!(&diffs[0], Change::Update { table, id, value, clock: None } if table == "users" && id == "1" && *value == encoded("alicia")));
+    assert!(matches!(&diffs[1], Change::Delete { table, id } if table == "users" && id == "2"));
+    assert!(matches!(&diffs[2], Change::Insert { table, id, value } if table == "users" && id == "4" && *value == encoded("dave")));
+    assert!(diff_states(&from, &from, "users").is_empty());
+}
+
+#[test]
+fn ref_names_containing_the_key_delimiter_are_not_rows() {
+    let storage = CommitStorage::open_in_memory();
+    let seeded = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    // Refs like this one predate the check that rejects ':' in ref names.
+    for key in ["refs/heads/a:b", "refs/tags/v1:rc", "refs/workspaces/x:y"] {
+        storage.db.put(key.as_bytes(), &seeded).unwrap();
+    }
+
+    assert!(storage.status(Some(seeded)).unwrap().is_empty());
+    assert!(storage.verify_worktree(seeded).unwrap().is_empty());
+    assert_eq!(storage.table_sizes().unwrap().keys().collect::<Vec<_>>(), ["users"]);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::*;
+use gitdb::core::database::CommitStorage;
+use gitdb::core::models::Change;
+use gitdb::error::GitDBError;
+use std::fs;
+
+#[test]
+fn export_writes_one_file_per_row_with_escaped_names() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_commit("seed", vec![insert("users", "1", "alice"), insert("users", "a/b", "bob")]).unwrap();
+    let head = storage.create_commit("more", vec![insert("orders", ".hidden", "parcel")]).unwrap();
+    let dir = TempDir::new("export");
+
+    storage.export_tree_to_dir(head, dir.path()).unwrap();
+
+    let mut tables: Vec<String> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    tables.sort();
+    assert_eq!(tables, ["orders", "users"]);
+
+    let mut users: Vec<String> = fs::read_dir(dir.path().join("users"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    users.sort();
+    assert_eq!(users, ["1", "a%2Fb"]);
+
+    assert_eq!(fs::read(dir.path().join("users/1")).unwrap(), encoded("alice"));
+    assert_eq!(fs::read(dir.path().join("users/a%2Fb")).unwrap(), encoded("bob"));
+    assert_eq!(fs::read(dir.path().join("orders/%2Ehidden")).unwrap(), encoded("parcel"));
+}
+
+#[test]
+fn import_commits_the_edits_made_to_an_export() {
+    let storage = CommitStorage::open_in_memory();
+    let head = storage
+        .create_commit("seed", vec![insert("users", "1", "alice"), insert("users", "2", "bob")])
+        .unwrap();
+    let dir = TempDir::new("import");
+    storage.export_tree_to_dir(head, dir.path()).unwrap();
+
+    fs::write(dir.path().join("users/1"), encoded("alicia")).unwrap();
+    fs::remove_file(dir.path().join("users/2")).unwrap();
+    fs::write(dir.path().join("users/3"), encoded("carol")).unwrap();
+    let imported = storage.import_tree_from_dir(dir.path(), "edit on disk").unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(imported));
+    let commit = storage.get_commit_by_hash(&imported).unwrap();
+    assert_eq!(commit.parents, vec![head]);
+    let changes = commit.changes;
+    assert_eq!(changes.len(), 3);
+    assert!(matches!(&changes[0], Change::Update { table, id, value, .. } if table == "users" && id == "1" && *value == encoded("alicia")));
+    assert!(matches!(&changes[1], Change::Delete { table, id } if table == "users" && id == "2"));
+    assert!(matches!(&changes[2], Change::Insert { table, id, value } if table == "users" && id == "3" && *value == encoded("carol")));
+
+    let state = storage.checkout_state(imported).unwrap();
+    assert_eq!(state["users"].len(), 2);
+    assert_eq!(state["users"]["1"], encoded("alicia"));
+    assert_eq!(state["users"]["3"], encoded("carol"));
+}
+
+#[test]
+fn export_refuses_a_directory_that_is_not_empty() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![Change::delete("users", "1")]).unwrap();
+    let dir = TempDir::new("export-twice");
+    storage.export_tree_to_dir(first, dir.path()).unwrap();
+
+    assert!(matches!(storage.export_tree_to_dir(second, dir.path()), Err(GitDBError::InvalidInput(_))));
+    assert_eq!(fs::read(dir.path().join("users/1")).unwrap(), encoded("alice"));
+
+    let fresh = dir.path().join("fresh");
+    storage.export_tree_to_dir(second, &fresh).unwrap();
+    assert!(fs::read_dir(fresh.join("users")).unwrap().next().is_none());
+}
@@ -0,0 +1,371 @@
+mod common;
+
+use common::*;
+use gitdb::core::database::CommitStorage;
+use gitdb::core::models::Change;
+use gitdb::error::GitDBError;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[test]
+fn history_visits_every_diamond_commit_once() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, right, merge] = diamond(&storage);
+
+    let history = storage.get_commit_history_with_hashes().unwrap();
+
+    let hashes: Vec<_> = history.iter().map(|record| record.hash).collect();
+    assert_eq!(hashes.len(), 4);
+    assert_eq!(hashes.iter().collect::<HashSet<_>>().len(), 4);
+    for hash in [root, left, right, merge] {
+        assert!(hashes.contains(&hash));
+    }
+    // Newest first: every commit comes before its parents.
+    let position = |hash| hashes.iter().position(|h| *h == hash).unwrap();
+    assert!(position(left) < position(root));
+    assert!(position(right) < position(root));
+    assert!(position(merge) < position(left));
+    assert!(position(merge) < position(right));
+}
+
+#[test]
+fn history_pages_resume_where_the_last_one_stopped() {
+    let storage = CommitStorage::open_in_memory();
+    let hashes: Vec<_> = (0..10)
+        .map(|i| storage.create_commit(&format!("commit {}", i), vec![insert("users", &i.to_string(), "row")]).unwrap())
+        .collect();
+
+    let mut messages = Vec::new();
+    let mut start = None;
+    let mut pages = 0;
+    loop {
+        let (page, next) = storage.get_commit_history_page(start, 4).unwrap();
+        assert!(page.len() <= 4);
+        messages.extend(page.into_iter().map(|commit| commit.message));
+        pages += 1;
+        match next {
+            Some(next) => {
+                assert!(hashes.contains(&next));
+                start = Some(next);
+            }
+            None => break,
+        }
+    }
+
+    assert_eq!(pages, 3);
+    let expected: Vec<_> = (0..10).rev().map(|i| format!("commit {}", i)).collect();
+    assert_eq!(messages, expected);
+}
+
+#[test]
+fn history_hashes_are_the_storage_keys() {
+    let storage = CommitStorage::open_in_memory();
+    let created: Vec<_> = ["first", "second", "third"].iter()
+        .map(|message| storage.create_commit(message, vec![insert("users", message, "row")]).unwrap())
+        .collect();
+
+    let history = storage.get_commit_history_with_hashes().unwrap();
+
+    let hashes: Vec<_> = history.iter().map(|record| record.hash).collect();
+    assert_eq!(hashes, created.iter().rev().cloned().collect::<Vec<_>>());
+    for record in &history {
+        assert!(storage.db.get(&record.hash).unwrap().is_some());
+        assert_eq!(storage.get_commit_by_hash(&record.hash).unwrap().message, record.commit.message);
+    }
+}
+
+#[test]
+fn squash_coalesces_three_commits_into_one() {
+    let storage = CommitStorage::open_in_memory();
+    let from = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_commit("add", vec![insert("users", "2", "bob"), insert("users", "3", "carol")]).unwrap();
+    storage.create_commit("rename", vec![update("users", "2", "bobby")]).unwrap();
+    let to = storage.create_commit("remove", vec![Change::delete("users", "3")]).unwrap();
+
+    let squashed = storage.squash(from, to, "squashed").unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), Some(squashed));
+    let commit = storage.get_commit_by_hash(&squashed).unwrap();
+    assert_eq!(commit.parents, vec![from]);
+    assert_eq!(commit.changes.len(), 1);
+    assert!(matches!(
+        &commit.changes[0],
+        Change::Insert { table, id, value } if table == "users" && id == "2" && *value == encoded("bobby")
+    ));
+}
+
+#[test]
+fn squashing_a_range_with_no_net_effect_is_refused() {
+    let storage = CommitStorage::open_in_memory();
+    let from = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_commit("add", vec![insert("users", "2", "bob")]).unwrap();
+    let to = storage.create_commit("remove", vec![Change::delete("users", "2")]).unwrap();
+
+    assert!(matches!(storage.squash(from, to, "nothing"), Err(GitDBError::NoOpCommit)));
+    assert_eq!(storage.get_head().unwrap(), Some(to));
+}
+
+#[test]
+fn blame_names_the_last_commit_to_touch_a_row() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_commit("add", vec![insert("users", "1", "alice")]).unwrap();
+    let updated = storage.create_commit("rename", vec![update("users", "1", "alicia")]).unwrap();
+    storage.create_commit("unrelated", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert_eq!(storage.blame_row("users", "1").unwrap(), Some(updated));
+    assert_eq!(storage.blame_row("users", "9").unwrap(), None);
+}
+
+#[test]
+fn commit_count_deduplicates_branched_history() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, right, merge] = diamond(&storage);
+
+    assert_eq!(storage.count_commits(None).unwrap(), 4);
+    assert_eq!(storage.count_commits(Some(merge)).unwrap(), 4);
+    assert_eq!(storage.count_commits(Some(left)).unwrap(), 2);
+    assert_eq!(storage.count_commits(Some(right)).unwrap(), 2);
+    assert_eq!(storage.count_commits(Some(root)).unwrap(), 1);
+    assert_eq!(CommitStorage::open_in_memory().count_commits(None).unwrap(), 0);
+}
+
+#[test]
+fn squash_only_rewrites_up_to_head() {
+    let storage = CommitStorage::open_in_memory();
+    let from = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+    let middle = storage.create_commit("add", vec![insert("users", "2", "bob")]).unwrap();
+    let head = storage.create_commit("more", vec![insert("users", "3", "carol")]).unwrap();
+
+    assert!(matches!(storage.squash(from, middle, "partial"), Err(GitDBError::InvalidInput(_))));
+    assert_eq!(storage.get_head().unwrap(), Some(head));
+}
+
+#[test]
+fn tables_listed_are_those_committed() {
+    let storage = CommitStorage::open_in_memory();
+    assert!(storage.list_tables(None).unwrap().is_empty());
+    let first = storage.create_commit("users", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_commit("teams", vec![insert("teams", "1", "core"), Change::schema_add("audit")]).unwrap();
+    storage.create_commit("drop audit", vec![Change::schema_drop("audit")]).unwrap();
+
+    assert_eq!(storage.list_tables(None).unwrap(), vec!["teams".to_string(), "users".to_string()]);
+    assert_eq!(storage.list_tables(Some(first)).unwrap(), vec!["users".to_string()]);
+}
+
+#[test]
+fn row_history_lists_every_version_newest_first() {
+    let storage = CommitStorage::open_in_memory();
+    let added = storage.create_commit("add", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_commit("unrelated", vec![insert("users", "2", "bob")]).unwrap();
+    let renamed = storage.create_commit("rename", vec![update("users", "1", "alicia")]).unwrap();
+    let renamed_again = storage.create_commit("rename again", vec![update("users", "1", "ali")]).unwrap();
+    let removed = storage.create_commit("remove", vec![Change::delete("users", "1")]).unwrap();
+
+    let history: Vec<_> = storage.row_history("users", "1").unwrap().into_iter().map(|(hash, change)| {
+        let version = match change {
+            Change::Insert { value, .. } => ("insert", Some(value)),
+            Change::Update { value, .. } => ("update", Some(value)),
+            Change::Delete { .. } => ("delete", None),
+            other => panic!("unexpected change {:?}", other),
+        };
+        (hash, version)
+    }).collect();
+
+    assert_eq!(history, vec![
+        (removed, ("delete", None)),
+        (renamed_again, ("update", Some(encoded("ali")))),
+        (renamed, ("update", Some(encoded("alicia")))),
+        (added, ("insert", Some(encoded("alice")))),
+    ]);
+}
+
+#[test]
+fn timestamps_stay_monotonic_when_the_clock_goes_backwards() {
+    let clock = Arc::new(ManualClock::new(1_000));
+    let storage = CommitStorage::open_in_memory().with_clock(clock.clone());
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    clock.set(500);
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+    let third = storage.create_commit("third", vec![insert("users", "3", "carol")]).unwrap();
+    clock.set(2_000);
+    let fourth = storage.create_commit("fourth", vec![insert("users", "4", "dave")]).unwrap();
+
+    let timestamps: Vec<u64> = [first, second, third, fourth].iter()
+        .map(|hash| storage.get_commit_by_hash(hash).unwrap().timestamp)
+        .collect();
+    assert_eq!(timestamps, vec![1_000, 1_001, 1_002, 2_000]);
+}
+
+#[test]
+fn parents_of_a_merge_are_both_sides() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, right, merge] = diamond(&storage);
+
+    assert_eq!(storage.parents_of(merge).unwrap(), vec![left, right]);
+    assert!(storage.parents_of(root).unwrap().is_empty());
+    match storage.parents_of([7u8; 32]) {
+        Err(GitDBError::InvalidInput(message)) => assert!(message.ends_with("does not exist"), "{}", message),
+        other => panic!("expected a missing commit error, got {:?}", other),
+    }
+}
+
+#[test]
+fn commits_between_lists_only_the_branch_commits() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let main_tip = storage.create_commit("main", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let first = storage.create_commit("feature 1", vec![insert("users", "3", "carol")]).unwrap();
+    let second = storage.create_commit("feature 2", vec![insert("users", "4", "dave")]).unwrap();
+
+    let between: Vec<_> = storage.commits_between(main_tip, second).unwrap().into_iter().map(|r| r.hash).collect();
+
+    assert_eq!(between, vec![second, first]);
+    assert!(storage.commits_between(second, root).unwrap().is_empty());
+}
+
+#[test]
+fn ancestry_checks_cover_parents_grandparents_siblings_and_self() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, right, merge] = diamond(&storage);
+
+    assert!(storage.is_ancestor(left, merge).unwrap());
+    assert!(storage.is_ancestor(root, merge).unwrap());
+    assert!(!storage.is_ancestor(left, right).unwrap());
+    assert!(!storage.is_ancestor(merge, root).unwrap());
+    assert!(storage.is_ancestor(merge, merge).unwrap());
+}
+
+#[test]
+fn commits_are_found_by_message_substring_and_author() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("fix: login", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_commit_with_author("Ada", "ada@example.com", "feat: teams", vec![insert("teams", "1", "core")]).unwrap();
+    let second = storage.create_commit("fix: logout", vec![insert("users", "2", "bob")]).unwrap();
+
+    let fixes: Vec<_> = storage.find_by_message_substring("fix:").unwrap().into_iter().map(|r| r.hash).collect();
+    assert_eq!(fixes, vec![second, first]);
+    let by_ada = storage.find_by_author("Ada").unwrap();
+    assert_eq!(by_ada.len(), 1);
+    assert_eq!(by_ada[0].commit.message, "feat: teams");
+    assert!(storage.find_by_message_substring("revert").unwrap().is_empty());
+}
+
+/// `op table:id` for each change, to compare diffs at a glance.
+fn describe(changes: &[Change]) -> Vec<String> {
+    changes.iter().map(|c| {
+        let op = match c {
+            Change::Insert { .. } => "insert",
+            Change::Update { .. } => "update",
+            Change::Delete { .. } => "delete",
+            Change::SchemaAdd { .. } => "add",
+            Change::SchemaDrop { .. } => "drop",
+            Change::FieldUpdate { .. } => "field",
+        };
+        format!("{} {}:{}", op, c.table(), c.id())
+    }).collect()
+}
+
+#[test]
+fn history_diffs_yield_each_commit_against_its_first_parent() {
+    let storage = CommitStorage::open_in_memory();
+    let seed = commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    let edit = commit_rows(&storage, "edit", vec![update("users", "1", "alicia"), insert("users", "2", "bob")]);
+    let teams = commit_rows(&storage, "teams", vec![insert("teams", "1", "core")]);
+    let remove = commit_rows(&storage, "remove", vec![Change::delete("users", "2")]);
+
+    let diffs: Vec<_> = storage.history_diffs()
+        .map(|entry| entry.map(|(hash, changes)| (hash, describe(&changes))))
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(diffs, vec![
+        (remove, vec!["delete users:2".to_string()]),
+        (teams, vec!["add teams:".to_string(), "insert teams:1".to_string()]),
+        (edit, vec!["update users:1".to_string(), "insert users:2".to_string()]),
+        (seed, vec!["insert users:1".to_string()]),
+    ]);
+}
+
+#[test]
+fn history_diffs_match_per_commit_diffs_across_merges() {
+    let storage = CommitStorage::open_in_memory();
+    let root = commit_rows(&storage, "root", vec![insert("users", "1", "alice")]);
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let left = commit_rows(&storage, "left", vec![insert("users", "2", "bob")]);
+    storage.set_head_to_branch("feature").unwrap();
+    let right = commit_rows(&storage, "right", vec![update("users", "1", "alicia"), insert("teams", "1", "core")]);
+    storage.set_head_to_branch("main").unwrap();
+    storage.merge_commits(left, right, "merge feature").unwrap();
+    commit_rows(&storage, "after", vec![Change::delete("users", "2")]);
+
+    let diffs: Vec<_> = storage.history_diffs().collect::<Result<_, _>>().unwrap();
+
+    let history = storage.get_commit_history_with_hashes().unwrap();
+    assert_eq!(diffs.len(), history.len());
+    for ((hash, changes), record) in diffs.iter().zip(&history) {
+        assert_eq!(*hash, record.hash);
+        let expected = match record.commit.parents.first() {
+            Some(parent) => storage.get_commit_diffs(parent, hash).unwrap(),
+            None => record.commit.changes.clone(),
+        };
+        assert_eq!(format!("{:?}", changes), format!("{:?}", expected), "{}", record.commit.message);
+    }
+}
+
+#[test]
+fn dot_export_has_a_node_per_commit_and_an_edge_per_parent() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, right, merge] = diamond(&storage);
+    let node = |hash: [u8; 32], message: &str| {
+        format!("    \"{}\" [label=\"{} {}\"];", hex::encode(hash), hex::encode(&hash[..4]), message)
+    };
+    let edge = |from: [u8; 32], to: [u8; 32]| format!("    \"{}\" -> \"{}\";", hex::encode(from), hex::encode(to));
+
+    let dot = storage.to_dot().unwrap();
+    let lines: HashSet<&str> = dot.lines().collect();
+
+    assert!(dot.starts_with("digraph commits {\n") && dot.ends_with("}\n"));
+    for expected in [
+        node(root, "root"),
+        node(left, "left"),
+        node(right, "right"),
+        node(merge, "merge feature"),
+        edge(left, root),
+        edge(right, root),
+        edge(merge, left),
+        edge(merge, right),
+    ] {
+        assert!(lines.contains(expected.as_str()), "missing {}", expected);
+    }
+    assert_eq!(dot.matches("->").count(), 4);
+}
+
+#[test]
+fn changelog_groups_consecutive_commits_by_author() {
+    let storage = CommitStorage::open_in_memory();
+    let ada = |message: &str, changes| storage.create_commit_with_author("Ada", "ada@example.com", message, changes).unwrap();
+    let from = ada("seed", vec![insert("users", "1", "alice")]);
+    ada("add bob", vec![insert("users", "2", "bob")]);
+    ada("rename alice", vec![update("users", "1", "alicia")]);
+    storage.create_commit_with_author("Grace", "grace@example.com", "add team", vec![insert("teams", "1", "core")]).unwrap();
+    let to = ada("remove bob", vec![Change::delete("users", "2")]);
+
+    let groups = storage.changelog(from, to).unwrap();
+
+    let summary: Vec<_> = groups.iter().map(|g| (g.author.as_str(), g.messages.clone())).collect();
+    assert_eq!(summary, vec![
+        ("Ada", vec!["add bob".to_string(), "rename alice".to_string()]),
+        ("Grace", vec!["add team".to_string()]),
+        ("Ada", vec!["remove bob".to_string()]),
+    ]);
+    let users = &groups[0].stats["users"];
+    assert_eq!((users.inserts, users.updates, users.deletes), (1, 1, 0));
+    assert_eq!(groups[1].stats["teams"].inserts, 1);
+    assert_eq!(groups[2].stats["users"].deletes, 1);
+}
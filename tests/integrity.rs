@@ -0,0 +1,205 @@
+mod common;
+
+use common::*;
+use ed25519_dalek::SigningKey;
+use gitdb::core::backend::InMemoryBackend;
+use gitdb::core::database::CommitStorage;
+use gitdb::core::models::IntegrityIssue;
+use gitdb::error::GitDBError;
+use std::sync::Arc;
+
+#[test]
+fn flipped_byte_fails_checksum_on_read() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit("add row", vec![insert("users", "1", "alice")]).unwrap();
+
+    let mut raw = storage.db.get(&hash).unwrap().unwrap();
+    let middle = raw.len() / 2;
+    raw[middle] ^= 0xFF;
+    storage.db.put(&hash, &raw).unwrap();
+
+    match storage.get_commit_by_hash(&hash) {
+        Err(GitDBError::CorruptData(message)) => assert!(message.contains(&hex::encode(hash))),
+        other => panic!("expected a checksum error, got {:?}", other),
+    }
+}
+
+#[test]
+fn compressed_commits_are_smaller_and_read_back() {
+    let storage = CommitStorage::open_in_memory().with_compression(3);
+    let changes: Vec<_> = (0..500).map(|i| insert("events", &i.to_string(), "same payload every time")).collect();
+    let hash = storage.create_commit("bulk load", changes).unwrap();
+
+    let commit = storage.get_commit_by_hash(&hash).unwrap();
+    let stored = storage.db.get(&hash).unwrap().unwrap();
+    assert!(stored.len() < bincode::serialize(&commit).unwrap().len() / 2);
+    assert_eq!(commit.changes.len(), 500);
+    assert_eq!(commit.message, "bulk load");
+}
+
+#[test]
+fn uncompressed_commits_still_read_after_enabling_compression() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let plain = CommitStorage::with_backend(backend.clone(), 0).unwrap();
+    let hash = plain.create_commit("plain", vec![insert("users", "1", "alice")]).unwrap();
+
+    let compressed = CommitStorage::with_backend(backend, 0).unwrap().with_compression(3);
+    assert_eq!(compressed.get_commit_by_hash(&hash).unwrap().message, "plain");
+}
+
+fn signing_key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+#[test]
+fn signed_commits_verify_only_with_their_key() {
+    let storage = CommitStorage::open_in_memory();
+    let key = signing_key(1);
+    let signed = storage.create_signed_commit(&key, "signed", vec![insert("users", "1", "alice")]).unwrap();
+    let unsigned = storage.create_commit("unsigned", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert!(storage.verify_commit_signature(signed, &key.verifying_key()).unwrap());
+    assert!(!storage.verify_commit_signature(signed, &signing_key(2).verifying_key()).unwrap());
+    assert!(!storage.verify_commit_signature(unsigned, &key.verifying_key()).unwrap());
+    assert_eq!(storage.get_commit_by_hash(&unsigned).unwrap().signature, None);
+}
+
+#[test]
+fn tampered_signed_commit_fails_verification() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let storage = CommitStorage::with_backend(backend, 0).unwrap();
+    let key = signing_key(1);
+    let hash = storage.create_signed_commit(&key, "signed", vec![insert("users", "1", "alice")]).unwrap();
+
+    // Rewrite the stored commit with a new message but a valid checksum, so
+    // only the signature can notice.
+    let mut tampered = storage.get_commit_by_hash(&hash).unwrap();
+    tampered.message = "forged".into();
+    let mut raw = vec![0xF0];
+    raw.extend(bincode::serialize(&tampered).unwrap());
+    let checksum = *blake3::hash(&raw).as_bytes();
+    raw.extend_from_slice(&checksum);
+    storage.db.put(&hash, &raw).unwrap();
+
+    assert_eq!(storage.get_commit_by_hash(&hash).unwrap().message, "forged");
+    assert!(!storage.verify_commit_signature(hash, &key.verifying_key()).unwrap());
+}
+
+#[test]
+fn fsck_reports_exactly_the_corrupted_commit() {
+    let storage = CommitStorage::open_in_memory();
+    commit_rows(&storage, "seed", vec![insert("users", "1", "alice")]);
+    let middle = commit_rows(&storage, "middle", vec![insert("users", "2", "bob")]);
+    commit_rows(&storage, "tip", vec![insert("users", "3", "carol")]);
+    assert!(storage.fsck().unwrap().is_empty());
+
+    let mut raw = storage.db.get(&middle).unwrap().unwrap();
+    raw[4] ^= 0xFF;
+    storage.db.put(&middle, &raw).unwrap();
+
+    assert_eq!(storage.fsck().unwrap(), vec![IntegrityIssue::BadChecksum { hash: middle }]);
+}
+
+#[test]
+fn self_referencing_parent_is_reported_as_a_cycle() {
+    let storage = CommitStorage::with_backend(Arc::new(InMemoryBackend::new()), 0).unwrap();
+    let hash = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+
+    let mut looped = storage.get_commit_by_hash(&hash).unwrap();
+    looped.parents = vec![hash];
+    let mut raw = vec![0xF0];
+    raw.extend(bincode::serialize(&looped).unwrap());
+    let checksum = *blake3::hash(&raw).as_bytes();
+    raw.extend_from_slice(&checksum);
+    storage.db.put(&hash, &raw).unwrap();
+
+    for result in [storage.get_commit_history_with_hashes().map(|_| ()), storage.checkout_state(hash).map(|_| ())] {
+        match result {
+            Err(GitDBError::CorruptData(message)) => assert!(message.starts_with("cycle detected"), "{}", message),
+            other => panic!("expected a cycle error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn history_deeper_than_the_limit_is_refused() {
+    let storage = CommitStorage::open_in_memory().with_max_history_depth(2);
+    for n in 0..3 {
+        storage.create_commit(&format!("commit {}", n), vec![insert("users", &n.to_string(), "someone")]).unwrap();
+    }
+
+    assert!(matches!(storage.get_commit_history_with_hashes(), Err(GitDBError::CorruptData(_))));
+}
+
+#[test]
+fn stripped_checksum_is_repaired() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit_with_author("Ada", "ada@example.com", "root", vec![insert("users", "1", "alice")]).unwrap();
+    let raw = storage.db.get(&hash).unwrap().unwrap();
+    assert!(!storage.repair_commit(hash).unwrap());
+
+    storage.db.put(&hash, &raw[..raw.len() - 32]).unwrap();
+    assert!(storage.repair_commit(hash).unwrap());
+
+    assert_eq!(storage.db.get(&hash).unwrap().unwrap(), raw);
+    assert_eq!(storage.get_commit_by_hash(&hash).unwrap().author, "Ada");
+}
+
+#[test]
+fn undecodable_payload_is_not_repaired() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.db.put(&hash, b"\xF0garbage").unwrap();
+
+    assert!(matches!(storage.repair_commit(hash), Err(GitDBError::CorruptData(_))));
+    assert_eq!(storage.db.get(&hash).unwrap().unwrap(), b"\xF0garbage".to_vec());
+}
+
+#[test]
+fn worktree_check_names_the_table_with_a_corrupted_row() {
+    let storage = CommitStorage::open_in_memory();
+    let hash = commit_rows(&storage, "seed", vec![
+        insert("users", "1", "alice"),
+        insert("teams", "1", "core"),
+    ]);
+    assert!(storage.verify_worktree(hash).unwrap().is_empty());
+
+    storage.db.put(b"teams:1", &encoded("tampered")).unwrap();
+
+    assert_eq!(storage.verify_worktree(hash).unwrap(), vec!["teams".to_string()]);
+}
+
+#[test]
+fn dangling_head_is_repaired_to_a_fallback() {
+    let storage = CommitStorage::with_backend(Arc::new(InMemoryBackend::new()), 0).unwrap();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let lost = storage.create_commit("lost", vec![insert("users", "2", "bob")]).unwrap();
+    storage.db.delete(&lost).unwrap();
+
+    assert!(matches!(storage.validate_head(), Err(GitDBError::CorruptData(_))));
+    assert!(storage.get_commit_history_with_hashes().is_err());
+
+    assert!(storage.repair_head(Some(first)).unwrap());
+
+    storage.validate_head().unwrap();
+    assert_eq!(storage.get_head().unwrap(), Some(first));
+    let next = storage.create_commit("next", vec![insert("users", "3", "carol")]).unwrap();
+    assert_eq!(storage.parents_of(next).unwrap(), vec![first]);
+    assert!(!storage.repair_head(None).unwrap());
+}
+
+#[test]
+fn dangling_head_without_a_fallback_is_cleared() {
+    let storage = CommitStorage::with_backend(Arc::new(InMemoryBackend::new()), 0).unwrap();
+    let lost = storage.create_commit("lost", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", lost).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    storage.db.delete(&lost).unwrap();
+
+    assert!(storage.repair_head(None).unwrap());
+
+    assert_eq!(storage.get_head().unwrap(), None);
+    assert_eq!(storage.get_branch("main").unwrap(), None);
+    let root = storage.create_commit("fresh", vec![insert("users", "1", "alice")]).unwrap();
+    assert!(storage.parents_of(root).unwrap().is_empty());
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use common::*;
+use gitdb::core::database::{row_key, CommitStorage};
+use gitdb::error::GitDBError;
+
+#[test]
+fn gc_removes_exactly_the_orphan_commit() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let tip = storage.create_commit("tip", vec![insert("users", "2", "bob")]).unwrap();
+    storage.detach_head(&root).unwrap();
+    let orphan = storage.create_commit("abandoned", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    storage.db.put(row_key("users", "1").as_bytes(), &encoded("alice")).unwrap();
+
+    assert_eq!(storage.gc().unwrap(), 1);
+
+    assert!(!storage.commit_exists(orphan).unwrap());
+    assert!(storage.commit_exists(root).unwrap());
+    assert!(storage.commit_exists(tip).unwrap());
+    assert_eq!(storage.get_head().unwrap(), Some(tip));
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+}
+
+#[test]
+fn gc_keeps_commits_only_a_tag_reaches() {
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.detach_head(&root).unwrap();
+    let release = storage.create_commit("release", vec![insert("users", "2", "bob")]).unwrap();
+    storage.create_tag("v1", release).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    assert_eq!(storage.gc().unwrap(), 0);
+
+    assert!(storage.commit_exists(release).unwrap());
+    assert_eq!(storage.get_commit_by_hash(&storage.resolve_tag("v1").unwrap()).unwrap().message, "release");
+}
+
+#[test]
+fn gc_and_pruning_delete_blobs_no_commit_references() {
+    let storage = CommitStorage::open_in_memory().with_blob_threshold(Some(1024));
+    let blobs = || storage.db.prefix_iterator(b"blob:").count();
+    let large = |label: &str| label.repeat(2048);
+    let first = storage.create_commit("first", vec![insert("docs", "1", &large("a"))]).unwrap();
+    storage.create_commit("second", vec![update("docs", "1", &large("b"))]).unwrap();
+    let third = storage.create_commit("third", vec![insert("docs", "2", &large("c"))]).unwrap();
+    storage.detach_head(&first).unwrap();
+    storage.create_commit("abandoned", vec![insert("docs", "3", &large("d"))]).unwrap();
+    storage.detach_head(&third).unwrap();
+    assert_eq!(blobs(), 4);
+
+    assert_eq!(storage.gc().unwrap(), 1);
+    assert_eq!(blobs(), 3);
+
+    // The new root still needs the current values of both rows.
+    assert_eq!(storage.prune_before(third).unwrap(), 2);
+    assert_eq!(blobs(), 2);
+    let state = storage.checkout_state(third).unwrap();
+    assert_eq!(state["docs"]["1"], encoded(&large("b")));
+    assert_eq!(state["docs"]["2"], encoded(&large("c")));
+}
+
+#[test]
+fn checkout_matches_with_and_without_a_snapshot() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, _right, merge] = diamond(&storage);
+    let tip = storage.create_commit("tip", vec![update("users", "1", "alicia")]).unwrap();
+    let before = storage.checkout_state(tip).unwrap();
+    let left_before = storage.checkout_state(left).unwrap();
+    let diffs_before = format!("{:?}", storage.get_table_diffs("users", &root, &tip).unwrap());
+
+    // A snapshot on one side of the merge covers only part of the history.
+    storage.create_snapshot(left).unwrap();
+    assert_eq!(storage.checkout_state(tip).unwrap(), before);
+
+    storage.create_snapshot(merge).unwrap();
+    assert_eq!(storage.nearest_snapshot(tip).unwrap(), Some(merge));
+    assert_eq!(storage.checkout_state(tip).unwrap(), before);
+    assert_eq!(storage.checkout_state(left).unwrap(), left_before);
+    assert_eq!(format!("{:?}", storage.get_table_diffs("users", &root, &tip).unwrap()), diffs_before);
+}
+
+#[test]
+fn table_sizes_sum_keys_and_values_per_table() {
+    let storage = CommitStorage::open_in_memory();
+    storage.create_commit("unrelated", vec![insert("audit", "1", "entry")]).unwrap();
+    storage.db.put(b"users:1", &[0u8; 100]).unwrap();
+    storage.db.put(b"users:2", &[0u8; 50]).unwrap();
+    storage.db.put(b"teams:a:b", &[0u8; 10]).unwrap();
+
+    let sizes = storage.table_sizes().unwrap();
+
+    assert_eq!(sizes.len(), 2);
+    assert_eq!(sizes["users"], 7 + 100 + 7 + 50);
+    assert_eq!(sizes["teams"], 9 + 10);
+}
+
+#[test]
+fn data_survives_flush_and_compaction() {
+    let dir = TempDir::new("compact");
+    let storage = CommitStorage::open(dir.path_str()).unwrap();
+    let mut tip = None;
+    for n in 0..50 {
+        tip = Some(commit_rows(&storage, &format!("commit {}", n), vec![insert("users", &n.to_string(), "someone")]));
+    }
+
+    storage.flush().unwrap();
+    storage.compact().unwrap();
+
+    assert_eq!(storage.get_head().unwrap(), tip);
+    assert_eq!(storage.count_commits(None).unwrap(), 50);
+    assert_eq!(storage.get_row("users", "49").unwrap(), Some(encoded("someone")));
+    assert_eq!(storage.checkout_state(tip.unwrap()).unwrap()["users"].len(), 50);
+}
+
+#[test]
+fn pruning_at_the_third_commit_keeps_its_state() {
+    let storage = CommitStorage::open_in_memory();
+    let mut hashes = Vec::new();
+    for n in 1..=5 {
+        let mut changes = vec![insert("users", &n.to_string(), "someone")];
+        if n > 1 {
+            changes.push(update("users", "1", &format!("alice v{}", n)));
+        }
+        hashes.push(storage.create_commit(&format!("commit {}", n), changes).unwrap());
+    }
+    let third = hashes[2];
+    let state_at_third = storage.checkout_state(third).unwrap();
+    let state_at_tip = storage.checkout_state(hashes[4]).unwrap();
+
+    assert_eq!(storage.prune_before(third).unwrap(), 2);
+
+    assert!(!storage.commit_exists(hashes[0]).unwrap());
+    assert!(!storage.commit_exists(hashes[1]).unwrap());
+    assert!(storage.parents_of(third).unwrap().is_empty());
+    assert_eq!(storage.checkout_state(third).unwrap(), state_at_third);
+    assert_eq!(storage.checkout_state(hashes[4]).unwrap(), state_at_tip);
+    assert_eq!(storage.count_commits(None).unwrap(), 3);
+}
+
+#[test]
+fn pruning_refuses_refs_into_the_pruned_region() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+    storage.create_tag("v0", first).unwrap();
+
+    assert!(matches!(storage.prune_before(second), Err(GitDBError::InvalidInput(_))));
+    assert!(storage.commit_exists(first).unwrap());
+}
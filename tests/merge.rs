@@ -0,0 +1,294 @@
+mod common;
+
+use common::*;
+use gitdb::core::crdt::CrdtValue;
+use gitdb::core::database::CommitStorage;
+use gitdb::core::models::{Change, Conflict};
+use gitdb::error::GitDBError;
+use std::collections::BTreeMap;
+
+/// A root commit on `main` plus a `feature` branch at the same commit, with
+/// HEAD on `main`.
+fn forked(storage: &CommitStorage) -> [u8; 32] {
+    let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    root
+}
+
+#[test]
+fn merge_keeps_rows_inserted_on_both_sides() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("ours", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("theirs", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    let merge = storage.merge_commits(ours, theirs, "merge feature").unwrap();
+
+    let commit = storage.get_commit_by_hash(&merge).unwrap();
+    assert_eq!(commit.parents, vec![ours, theirs]);
+    assert!(commit.changes.iter().any(|c| matches!(c, Change::Insert { id, .. } if id == "3")));
+    let users = &storage.checkout_state(merge).unwrap()["users"];
+    assert_eq!(users.len(), 3);
+    assert_eq!(users["2"], encoded("bob"));
+    assert_eq!(users["3"], encoded("carol"));
+}
+
+#[test]
+fn common_ancestor_of_linear_history_is_the_older_commit() {
+    let storage = CommitStorage::open_in_memory();
+    let first = storage.create_commit("first", vec![insert("users", "1", "alice")]).unwrap();
+    let second = storage.create_commit("second", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert_eq!(storage.find_common_ancestor(first, second).unwrap(), Some(first));
+    assert_eq!(storage.find_common_ancestor(second, first).unwrap(), Some(first));
+}
+
+#[test]
+fn common_ancestor_of_diamond_sides_is_the_fork_point() {
+    let storage = CommitStorage::open_in_memory();
+    let [root, left, right, merge] = diamond(&storage);
+
+    assert_eq!(storage.find_common_ancestor(left, right).unwrap(), Some(root));
+    assert_eq!(storage.find_common_ancestor(merge, right).unwrap(), Some(right));
+}
+
+#[test]
+fn disjoint_histories_have_no_common_ancestor() {
+    let storage = CommitStorage::open_in_memory();
+    let main = storage.create_commit("main", vec![insert("users", "1", "alice")]).unwrap();
+    storage.create_workspace("other", None).unwrap();
+    let other = storage.commit_in_workspace("other", "other", vec![insert("users", "1", "bob")]).unwrap();
+
+    assert_eq!(storage.find_common_ancestor(main, other).unwrap(), None);
+}
+
+#[test]
+fn higher_clock_update_wins_in_either_merge_direction() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("ours", vec![update("users", "1", "zed").with_clock(Some(1))]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("theirs", vec![update("users", "1", "amy").with_clock(Some(2))]).unwrap();
+
+    let into_feature = storage.merge_commits(theirs, ours, "merge main").unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let into_main = storage.merge_commits(ours, theirs, "merge feature").unwrap();
+
+    for merge in [into_feature, into_main] {
+        assert_eq!(storage.checkout_state(merge).unwrap()["users"]["1"], encoded("amy"));
+    }
+}
+
+#[test]
+fn rows_changed_on_one_side_only_do_not_conflict() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("ours", vec![update("users", "1", "alicia")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("theirs", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert!(storage.detect_conflicts(ours, theirs).unwrap().is_empty());
+}
+
+#[test]
+fn rows_changed_differently_on_both_sides_conflict() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("ours", vec![update("users", "1", "alicia")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("theirs", vec![update("users", "1", "ally")]).unwrap();
+
+    let conflicts = storage.detect_conflicts(ours, theirs).unwrap();
+
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!((conflicts[0].table.as_str(), conflicts[0].id.as_str()), ("users", "1"));
+    assert_eq!(conflicts[0].ours, Some(encoded("alicia")));
+    assert_eq!(conflicts[0].theirs, Some(encoded("ally")));
+}
+
+#[test]
+fn cherry_pick_applies_one_commit_from_another_branch() {
+    let storage = CommitStorage::open_in_memory();
+    let root = forked(&storage);
+    storage.set_head_to_branch("feature").unwrap();
+    storage.create_commit("skipped", vec![insert("users", "2", "bob")]).unwrap();
+    let picked = storage.create_commit("picked", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    let copy = storage.cherry_pick(picked).unwrap();
+
+    let commit = storage.get_commit_by_hash(&copy).unwrap();
+    assert_eq!(commit.parents, vec![root]);
+    assert_eq!(commit.message, format!("Cherry-pick {}", hex::encode(picked)));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(copy));
+    let users = &storage.checkout_state(copy).unwrap()["users"];
+    assert!(users.contains_key("3"));
+    assert!(!users.contains_key("2"));
+}
+
+#[test]
+fn cherry_pick_of_a_delete_of_a_missing_row_fails() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    storage.set_head_to_branch("feature").unwrap();
+    storage.create_commit("add", vec![insert("users", "2", "bob")]).unwrap();
+    let delete = storage.create_commit("remove", vec![Change::delete("users", "2")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    match storage.cherry_pick(delete) {
+        Err(GitDBError::Conflict(message)) => assert!(message.contains("delete of a missing row")),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert_eq!(storage.get_branch("main").unwrap(), storage.get_head().unwrap());
+}
+
+#[test]
+fn rebase_replays_a_branch_linearly_onto_main() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let main_tip = storage.create_commit("main work", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let first = storage.create_commit("feature one", vec![insert("users", "3", "carol")]).unwrap();
+    let second = storage.create_commit("feature two", vec![update("users", "3", "caroline")]).unwrap();
+
+    let tip = storage.rebase(second, main_tip).unwrap();
+
+    let rebased_second = storage.get_commit_by_hash(&tip).unwrap();
+    assert_eq!(rebased_second.message, "feature two");
+    let rebased_first_hash = rebased_second.parents[0];
+    assert_eq!(rebased_second.parents.len(), 1);
+    assert_ne!(rebased_first_hash, first);
+    let rebased_first = storage.get_commit_by_hash(&rebased_first_hash).unwrap();
+    assert_eq!(rebased_first.message, "feature one");
+    assert_eq!(rebased_first.parents, vec![main_tip]);
+
+    let users = &storage.checkout_state(tip).unwrap()["users"];
+    assert_eq!(users["2"], encoded("bob"));
+    assert_eq!(users["3"], encoded("caroline"));
+    assert_eq!(storage.get_branch("feature").unwrap(), Some(second));
+}
+
+#[test]
+fn rebase_aborts_on_a_conflicting_change() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let main_tip = storage.create_commit("drop alice", vec![Change::delete("users", "1")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let edit = storage.create_commit("edit alice", vec![update("users", "1", "alicia")]).unwrap();
+    let keys_before = storage.db.iterator().count();
+
+    assert!(matches!(storage.rebase(edit, main_tip), Err(GitDBError::Conflict(_))));
+    assert_eq!(storage.db.iterator().count(), keys_before);
+}
+
+#[test]
+fn field_updates_on_different_fields_merge_without_conflict() {
+    let field = |name: &str, value: &str| {
+        Change::field_update("people", "1", name, &CrdtValue::Register(value.as_bytes().to_vec())).unwrap()
+    };
+    let storage = CommitStorage::open_in_memory();
+    let root = storage.create_commit("root", vec![field("name", "Ada"), field("city", "London")]).unwrap();
+    storage.create_branch("main", root).unwrap();
+    storage.create_branch("feature", root).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+    let ours = storage.create_commit("rename", vec![field("name", "Ada L.")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("move", vec![field("city", "Paris")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    assert!(storage.detect_conflicts(ours, theirs).unwrap().is_empty());
+    let merge = storage.merge_commits(ours, theirs, "merge feature").unwrap();
+
+    let expected = CrdtValue::Fields(BTreeMap::from([
+        ("city".to_string(), CrdtValue::Register(b"Paris".to_vec())),
+        ("name".to_string(), CrdtValue::Register(b"Ada L.".to_vec())),
+    ]));
+    assert_eq!(storage.checkout_state(merge).unwrap()["people"]["1"], bincode::serialize(&expected).unwrap());
+}
+
+#[test]
+fn merging_a_descendant_fast_forwards() {
+    let storage = CommitStorage::open_in_memory();
+    let root = forked(&storage);
+    storage.set_head_to_branch("feature").unwrap();
+    let ahead = storage.create_commit("ahead", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    assert!(storage.can_fast_forward(root, ahead).unwrap());
+    assert!(!storage.can_fast_forward(ahead, root).unwrap());
+    assert_eq!(storage.merge_commits(root, ahead, "merge feature").unwrap(), ahead);
+    assert_eq!(storage.get_head().unwrap(), Some(ahead));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(ahead));
+    assert_eq!(storage.count_commits(None).unwrap(), 2);
+}
+
+#[test]
+fn fast_forward_refuses_when_head_is_not_ours() {
+    let storage = CommitStorage::open_in_memory();
+    let root = forked(&storage);
+    let main = storage.create_commit("main moves on", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let ahead = storage.create_commit("ahead", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    assert!(matches!(storage.merge_commits(root, ahead, "merge feature"), Err(GitDBError::Conflict(_))));
+    assert_eq!(storage.get_head().unwrap(), Some(main));
+    assert_eq!(storage.get_branch("main").unwrap(), Some(main));
+}
+
+#[test]
+fn divergent_histories_get_a_merge_commit() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("ours", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("theirs", vec![insert("users", "3", "carol")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    assert!(!storage.can_fast_forward(ours, theirs).unwrap());
+    assert!(!storage.can_fast_forward(theirs, ours).unwrap());
+    let merge = storage.merge_commits(ours, theirs, "merge feature").unwrap();
+    assert_eq!(storage.parents_of(merge).unwrap(), vec![ours, theirs]);
+}
+
+#[test]
+fn conflicts_go_to_the_resolver_or_fail_without_one() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("ours", vec![update("users", "1", "alicia")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("theirs", vec![update("users", "1", "ally")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    match storage.merge_commits_with(ours, theirs, "merge", None) {
+        Err(GitDBError::Conflict(message)) => assert_eq!(message, "Merge conflicts on users:1"),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert_eq!(storage.get_head().unwrap(), Some(ours));
+
+    let take_theirs = |conflict: &Conflict| conflict.theirs.clone().unwrap();
+    let merge = storage.merge_commits_with(ours, theirs, "merge", Some(&take_theirs)).unwrap();
+
+    assert_eq!(storage.parents_of(merge).unwrap(), vec![ours, theirs]);
+    assert_eq!(storage.checkout_state(merge).unwrap()["users"]["1"], encoded("ally"));
+}
+
+#[test]
+fn a_delete_on_one_side_survives_the_merge() {
+    let storage = CommitStorage::open_in_memory();
+    forked(&storage);
+    let ours = storage.create_commit("delete", vec![Change::delete("users", "1")]).unwrap();
+    storage.set_head_to_branch("feature").unwrap();
+    let theirs = storage.create_commit("unrelated", vec![insert("users", "2", "bob")]).unwrap();
+    storage.set_head_to_branch("main").unwrap();
+
+    let merge = storage.merge_commits(ours, theirs, "merge feature").unwrap();
+
+    let users = &storage.checkout_state(merge).unwrap()["users"];
+    assert!(!users.contains_key("1"));
+    assert_eq!(users["2"], encoded("bob"));
+}
@@ -0,0 +1,305 @@
+mod common;
+
+use common::*;
+use gitdb::core::backend::WriteBatch;
+use gitdb::core::codec::{BincodeCodec, Codec};
+use gitdb::core::database::{row_key, CommitStorage, StorageConfig, WalEntry};
+use gitdb::core::hashing::ChecksumAlgorithm;
+use gitdb::error::GitDBError;
+use rocksdb::DBCompressionType;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+#[test]
+fn archive_round_trips_into_a_fresh_repository() {
+    let source = CommitStorage::open_in_memory();
+    let root = source.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    source.create_branch("main", root).unwrap();
+    source.set_head_to_branch("main").unwrap();
+    let tip = source.create_commit("tip", vec![insert("users", "2", "bob")]).unwrap();
+    source.create_tag("v1", root).unwrap();
+    source.db.put(row_key("users", "1").as_bytes(), &encoded("alice")).unwrap();
+    let mut archive = Vec::new();
+    source.export_archive(&mut archive).unwrap();
+
+    let dir = TempDir::new("archive");
+    let target = CommitStorage::open(dir.path_str()).unwrap();
+    target.import_archive(archive.as_slice()).unwrap();
+
+    assert_eq!(target.get_head().unwrap(), Some(tip));
+    assert_eq!(target.current_branch().unwrap(), Some("main".to_string()));
+    assert_eq!(target.resolve_tag("v1").unwrap(), root);
+    assert_eq!(target.get_commit_by_hash(&tip).unwrap().parents, vec![root]);
+    assert_eq!(target.get_row("users", "1").unwrap(), Some(encoded("alice")));
+}
+
+#[test]
+fn archive_with_a_flipped_byte_is_rejected() {
+    let source = CommitStorage::open_in_memory();
+    source.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let mut archive = Vec::new();
+    source.export_archive(&mut archive).unwrap();
+    let middle = archive.len() / 2;
+    archive[middle] ^= 0x01;
+
+    let target = CommitStorage::open_in_memory();
+    assert!(target.import_archive(archive.as_slice()).is_err());
+    assert_eq!(target.get_head().unwrap(), None);
+}
+
+fn exported(source: &CommitStorage) -> Vec<u8> {
+    let mut archive = Vec::new();
+    source.export_archive(&mut archive).unwrap();
+    archive
+}
+
+#[test]
+fn archive_import_refuses_a_repository_with_data() {
+    let source = CommitStorage::open_in_memory();
+    source.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let target = CommitStorage::open_in_memory();
+    let existing = target.create_commit("local", vec![insert("users", "1", "bob")]).unwrap();
+
+    match target.import_archive(exported(&source).as_slice()) {
+        Err(GitDBError::InvalidInput(message)) => assert!(message.contains("non-empty")),
+        other => panic!("expected a refusal, got {:?}", other),
+    }
+    assert_eq!(target.get_head().unwrap(), Some(existing));
+}
+
+#[test]
+fn archive_import_rejects_a_different_trailer_setting() {
+    let source = CommitStorage::open_in_memory();
+    source.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let dir = TempDir::new("archive-untrailed");
+    let cfg = StorageConfig { integrity_trailer: Some(false), ..StorageConfig::default() };
+    let target = CommitStorage::open_with_options(dir.path_str(), cfg).unwrap();
+
+    match target.import_archive(exported(&source).as_slice()) {
+        Err(GitDBError::InvalidInput(message)) => assert!(message.contains("integrity trailers")),
+        other => panic!("expected a settings mismatch, got {:?}", other),
+    }
+    assert_eq!(target.get_head().unwrap(), None);
+}
+
+#[test]
+fn archive_with_a_huge_length_field_fails_cleanly() {
+    let source = CommitStorage::open_in_memory();
+    source.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    let mut archive = exported(&source);
+    // The first record's key length sits right after the magic and version byte.
+    archive[9..13].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+
+    let target = CommitStorage::open_in_memory();
+    assert!(matches!(target.import_archive(archive.as_slice()), Err(GitDBError::CorruptData(_))));
+}
+
+#[test]
+fn table_hash_is_stable_across_reopen() {
+    let dir = TempDir::new("table-hash");
+    let (first, before) = {
+        let storage = CommitStorage::open(dir.path_str()).unwrap();
+        storage.db.put(row_key("users", "1").as_bytes(), &encoded("alice")).unwrap();
+        let first = storage.create_commit("seed", vec![insert("users", "1", "alice")]).unwrap();
+        (first, storage.table_hash_at(first, "users").unwrap())
+    };
+
+    let storage = CommitStorage::open(dir.path_str()).unwrap();
+    let second = storage.create_commit("touch", vec![
+        update("users", "1", "alice"),
+        insert("teams", "1", "core"),
+    ]).unwrap();
+
+    assert_eq!(storage.table_hash_at(first, "users").unwrap(), before);
+    assert_eq!(storage.table_hash_at(second, "users").unwrap(), before);
+}
+
+#[test]
+fn truncated_row_is_reported_by_key() {
+    let storage = CommitStorage::open_in_memory();
+    let key = row_key("users", "1");
+    storage.db.put(key.as_bytes(), &encoded("alice")[..3]).unwrap();
+
+    match storage.create_commit("touch", vec![insert("users", "1", "alice")]) {
+        Err(GitDBError::CorruptData(message)) => assert!(message.contains(&hex::encode(&key))),
+        other => panic!("expected corruption to be reported, got {:?}", other),
+    }
+}
+
+#[test]
+fn tuned_repository_commits_and_reads() {
+    let dir = TempDir::new("tuned");
+    let cfg = StorageConfig {
+        write_buffer_size: Some(1 << 20),
+        max_open_files: Some(64),
+        compression: Some(DBCompressionType::Lz4),
+        block_cache_bytes: Some(1 << 20),
+        ..StorageConfig::default()
+    };
+    let hash = {
+        let storage = CommitStorage::open_with_options(dir.path_str(), cfg).unwrap();
+        storage.create_commit("tuned", vec![insert("users", "1", "alice")]).unwrap()
+    };
+
+    let storage = CommitStorage::open(dir.path_str()).unwrap();
+    assert_eq!(storage.get_head().unwrap(), Some(hash));
+    assert_eq!(storage.get_commit_by_hash(&hash).unwrap().message, "tuned");
+}
+
+#[test]
+fn sha256_repository_reopens_with_sha256() {
+    let dir = TempDir::new("sha256");
+    let config = StorageConfig { checksum: Some(ChecksumAlgorithm::Sha256), ..StorageConfig::default() };
+    let hash = {
+        let storage = CommitStorage::open_with_options(dir.path_str(), config).unwrap();
+        storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap()
+    };
+
+    let reopened = CommitStorage::open(dir.path_str()).unwrap();
+    assert_eq!(reopened.checksum_algorithm(), ChecksumAlgorithm::Sha256);
+    assert_eq!(reopened.get_commit_by_hash(&hash).unwrap().message, "root");
+    let raw = reopened.db.get(&hash).unwrap().unwrap();
+    let (payload, trailer) = raw.split_at(raw.len() - 32);
+    assert_eq!(trailer, Sha256::digest(payload).as_slice());
+    drop(reopened);
+
+    let blake3 = StorageConfig { checksum: Some(ChecksumAlgorithm::Blake3), ..StorageConfig::default() };
+    assert!(matches!(CommitStorage::open_with_options(dir.path_str(), blake3), Err(GitDBError::InvalidInput(_))));
+}
+
+#[test]
+fn open_retries_until_a_transient_lock_is_released() {
+    let dir = TempDir::new("retry");
+    let holder = CommitStorage::open(dir.path_str()).unwrap();
+    let hash = holder.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+
+    match CommitStorage::open_with_retry(dir.path_str(), 2, Duration::from_millis(1)) {
+        Err(GitDBError::IoError(message)) => assert!(message.contains("still locked"), "{}", message),
+        other => panic!("expected a lock error, got {:?}", other.map(|_| ())),
+    }
+
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        drop(holder);
+    });
+    let reopened = CommitStorage::open_with_retry(dir.path_str(), 10, Duration::from_millis(10)).unwrap();
+    release.join().unwrap();
+    assert_eq!(reopened.get_head().unwrap(), Some(hash));
+}
+
+#[test]
+fn retrying_open_applies_the_given_options() {
+    let dir = TempDir::new("retry-options");
+    let sha256 = || StorageConfig { checksum: Some(ChecksumAlgorithm::Sha256), ..StorageConfig::default() };
+    let holder = CommitStorage::open_with_options(dir.path_str(), sha256()).unwrap();
+    let hash = holder.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        drop(holder);
+    });
+    let reopened = CommitStorage::open_with_options_and_retry(dir.path_str(), sha256(), 10, Duration::from_millis(10)).unwrap();
+    release.join().unwrap();
+    assert_eq!(reopened.get_head().unwrap(), Some(hash));
+    drop(reopened);
+
+    let blake3 = StorageConfig { checksum: Some(ChecksumAlgorithm::Blake3), ..StorageConfig::default() };
+    assert!(matches!(
+        CommitStorage::open_with_options_and_retry(dir.path_str(), blake3, 1, Duration::from_millis(1)),
+        Err(GitDBError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn commits_read_back_whichever_trailer_mode_wrote_them() {
+    let dir = TempDir::new("trailer");
+    let with_trailer = |enabled| StorageConfig { integrity_trailer: Some(enabled), ..StorageConfig::default() };
+
+    let bare = {
+        let storage = CommitStorage::open_with_options(dir.path_str(), with_trailer(false)).unwrap();
+        storage.create_commit("bare", vec![insert("users", "1", "alice")]).unwrap()
+    };
+    let storage = CommitStorage::open_with_options(dir.path_str(), with_trailer(true)).unwrap();
+    let checked = storage.create_commit("checked", vec![insert("users", "2", "bob")]).unwrap();
+
+    assert_eq!(storage.db.get(&bare).unwrap().unwrap()[0], 0xF2);
+    assert_eq!(storage.db.get(&checked).unwrap().unwrap()[0], 0xF0);
+    assert_eq!(storage.get_commit_by_hash(&bare).unwrap().message, "bare");
+    assert_eq!(storage.get_commit_by_hash(&checked).unwrap().message, "checked");
+    assert!(storage.fsck().unwrap().is_empty());
+    drop(storage);
+
+    // The last setting sticks for later opens.
+    let reopened = CommitStorage::open(dir.path_str()).unwrap();
+    let later = reopened.create_commit("later", vec![insert("users", "3", "carol")]).unwrap();
+    assert_eq!(reopened.db.get(&later).unwrap().unwrap()[0], 0xF0);
+    assert_eq!(reopened.count_commits(None).unwrap(), 3);
+}
+
+#[test]
+fn clone_subset_copies_only_the_history_of_the_tip() {
+    let source = CommitStorage::open_in_memory();
+    let root = source.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+    source.create_branch("main", root).unwrap();
+    source.create_branch("feature", root).unwrap();
+    source.set_head_to_branch("feature").unwrap();
+    let side = source.create_commit("side", vec![insert("users", "3", "carol")]).unwrap();
+    source.set_head_to_branch("main").unwrap();
+    let tip = source.create_commit("tip", vec![update("users", "1", "alicia"), insert("users", "2", "bob")]).unwrap();
+    let dir = TempDir::new("clone");
+
+    source.clone_subset(dir.path_str(), tip).unwrap();
+
+    let clone = CommitStorage::open(dir.path_str()).unwrap();
+    assert_eq!(clone.get_head().unwrap(), Some(tip));
+    assert!(clone.list_branches().unwrap().is_empty());
+    assert!(clone.commit_exists(root).unwrap());
+    assert!(!clone.commit_exists(side).unwrap());
+    assert_eq!(clone.get_commit_by_hash(&tip).unwrap().parents, vec![root]);
+    assert_eq!(clone.get_row("users", "1").unwrap(), Some(encoded("alicia")));
+    assert_eq!(clone.get_row("users", "2").unwrap(), Some(encoded("bob")));
+    assert_eq!(clone.get_row("users", "3").unwrap(), None);
+    drop(clone);
+
+    assert!(matches!(source.clone_subset(dir.path_str(), tip), Err(GitDBError::InvalidInput(_))));
+}
+
+/// Leaves a logged write that only puts `users:1`, as a crash right after
+/// logging it would.
+fn log_pending_write(storage: &CommitStorage, head: Option<[u8; 32]>) {
+    let mut batch = WriteBatch::default();
+    batch.put(row_key("users", "1"), encoded("alice"));
+    let entry = WalEntry { head, batch };
+    storage.db.put(b"wal:pending", &BincodeCodec.encode_wal(&entry).unwrap()).unwrap();
+}
+
+#[test]
+fn write_ahead_log_replays_a_write_cut_short() {
+    let dir = TempDir::new("wal-replay");
+    {
+        let storage = CommitStorage::open(dir.path_str()).unwrap().with_write_ahead_log(true);
+        let root = storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+        assert_eq!(storage.db.get(b"wal:pending").unwrap(), None);
+        log_pending_write(&storage, Some(root));
+    }
+
+    let storage = CommitStorage::open(dir.path_str()).unwrap();
+
+    assert_eq!(storage.get_row("users", "1").unwrap(), Some(encoded("alice")));
+    assert_eq!(storage.db.get(b"wal:pending").unwrap(), None);
+}
+
+#[test]
+fn write_ahead_log_drops_a_write_head_has_moved_past() {
+    let dir = TempDir::new("wal-rollback");
+    {
+        let storage = CommitStorage::open(dir.path_str()).unwrap();
+        storage.create_commit("root", vec![insert("users", "1", "alice")]).unwrap();
+        log_pending_write(&storage, None);
+    }
+
+    let storage = CommitStorage::open(dir.path_str()).unwrap();
+
+    assert_eq!(storage.get_row("users", "1").unwrap(), None);
+    assert_eq!(storage.db.get(b"wal:pending").unwrap(), None);
+}